@@ -0,0 +1,290 @@
+//! Reading ISO 20022 camt.053 account statements.
+
+use roxmltree::{Document, Node};
+
+use crate::{
+    diagnostic::Diagnostic,
+    format,
+    import::{Balance, BankTransaction, Statement, parse_amount, parse_date},
+    money::Money,
+};
+
+/// Reads the booked entries and balances of all the statements in a
+/// camt.053 document. An entry with several transactions in its details,
+/// like a batch of incoming payments, is read as one transaction each.
+/// All amounts must be in `currency`, the base currency of the ledger.
+pub fn read(xml: &str, currency: &str) -> Result<Statement, Diagnostic> {
+    let document = Document::parse(xml)
+        .map_err(|e| Diagnostic::without_span(format!("tiliotetta ei voi lukea: {e}")))?;
+    let mut statement = Statement::default();
+    for stmt in document.descendants().filter(|n| n.has_tag_name("Stmt")) {
+        for bal in children(stmt, "Bal") {
+            let code = text(bal, &["Tp", "CdOrPrtry", "Cd"]);
+            if !matches!(code.as_str(), "OPBD" | "CLBD") {
+                continue;
+            }
+            let date = date(bal, &["Dt"]).ok_or_else(|| error("saldolta puuttuu päivämäärä"))?;
+            check_currency(bal, currency)?;
+            statement.balances.push(Balance {
+                date,
+                amount: amount(bal).ok_or_else(|| error("saldolta puuttuu summa"))?,
+                opening: code == "OPBD",
+            });
+        }
+        for entry in children(stmt, "Ntry") {
+            let status = match text(entry, &["Sts", "Cd"]) {
+                s if s.is_empty() => text(entry, &["Sts"]),
+                s => s,
+            };
+            if !matches!(status.as_str(), "BOOK" | "") {
+                continue;
+            }
+            statement
+                .transactions
+                .extend(transactions(entry, currency)?);
+        }
+    }
+    Ok(statement)
+}
+
+fn transactions(entry: Node, currency: &str) -> Result<Vec<BankTransaction>, Diagnostic> {
+    let reference = text(entry, &["AcctSvcrRef"]);
+    let date = date(entry, &["BookgDt"])
+        .ok_or_else(|| error(&format!("viennillä '{reference}' ei ole kirjauspäivää")))?;
+    let total =
+        amount(entry).ok_or_else(|| error(&format!("viennillä '{reference}' ei ole summaa")))?;
+    check_currency(entry, currency)?;
+    let details = children(entry, "NtryDtls")
+        .flat_map(|d| children(d, "TxDtls"))
+        .collect::<Vec<_>>();
+    let amounts = details
+        .iter()
+        .map(|d| {
+            let amounts = first(*d, "AmtDtls")?;
+            let amt = first(amounts, "TxAmt").or_else(|| first(amounts, "InstdAmt"))?;
+            let n = signed(
+                first(amt, "Amt")?,
+                first(*d, "CdtDbtInd").or_else(|| first(entry, "CdtDbtInd")),
+            )?;
+            Some((amt, n))
+        })
+        .collect::<Option<Vec<_>>>();
+    let split = match amounts {
+        Some(amounts) if details.len() > 1 => amounts,
+        _ => {
+            let detail = details.first().copied();
+            return Ok(vec![transaction(entry, detail, date, total, reference)]);
+        }
+    };
+    let mut sum = Money::ZERO;
+    for (amt, n) in &split {
+        check_currency(*amt, currency)?;
+        sum = sum
+            .checked_add(*n)
+            .ok_or_else(|| error(&format!("viennin '{reference}' summa on liian suuri")))?;
+    }
+    if sum != total {
+        return Err(error(&format!(
+            "viennin '{reference}' tapahtumien summa {} ei vastaa viennin summaa {}",
+            format::amount(sum),
+            format::amount(total)
+        )));
+    }
+    let split = split.into_iter().map(|(_, n)| n);
+    Ok(details
+        .iter()
+        .zip(split)
+        .enumerate()
+        .map(|(i, (detail, amount))| {
+            let id = match text(*detail, &["Refs", "AcctSvcrRef"]) {
+                id if !id.is_empty() => id,
+                _ => format!("{reference}/{}", i + 1),
+            };
+            transaction(entry, Some(*detail), date, amount, id)
+        })
+        .collect())
+}
+
+fn transaction(
+    entry: Node,
+    detail: Option<Node>,
+    date: time::Date,
+    amount: Money,
+    archive_id: String,
+) -> BankTransaction {
+    // The counterparty is the debtor of money coming in and the creditor
+    // of money going out.
+    let party = if amount.is_negative() { "Cdtr" } else { "Dbtr" };
+    let (payee, reference, message) = match detail {
+        Some(d) => (
+            first(d, "RltdPties")
+                .and_then(|p| first(p, party))
+                .and_then(|p| p.descendants().find(|n| n.has_tag_name("Nm")))
+                .and_then(|n| n.text())
+                .unwrap_or("")
+                .to_string(),
+            text(d, &["RmtInf", "Strd", "CdtrRefInf", "Ref"]),
+            match children(d, "RmtInf")
+                .flat_map(|r| children(r, "Ustrd"))
+                .filter_map(|n| n.text())
+                .collect::<Vec<_>>()
+                .join(" ")
+            {
+                m if m.is_empty() => text(d, &["AddtlTxInf"]),
+                m => m,
+            },
+        ),
+        None => (String::new(), String::new(), String::new()),
+    };
+    let message = if message.is_empty() {
+        text(entry, &["AddtlNtryInf"])
+    } else {
+        message
+    };
+    BankTransaction {
+        date,
+        amount,
+        payee: payee.trim().to_string(),
+        reference: reference.replace(' ', ""),
+        message: message.trim().to_string(),
+        archive_id,
+    }
+}
+
+fn error(message: &str) -> Diagnostic {
+    Diagnostic::without_span(format!("tiliotetta ei voi lukea: {message}"))
+}
+
+fn children<'a, 'i>(node: Node<'a, 'i>, name: &'static str) -> impl Iterator<Item = Node<'a, 'i>> {
+    node.children().filter(move |n| n.has_tag_name(name))
+}
+
+fn first<'a, 'i>(node: Node<'a, 'i>, name: &'static str) -> Option<Node<'a, 'i>> {
+    children(node, name).next()
+}
+
+/// The text of the element at `path` under `node`, or an empty string.
+fn text(node: Node, path: &[&'static str]) -> String {
+    path.iter()
+        .try_fold(node, |n, name| first(n, name))
+        .and_then(|n| n.text())
+        .unwrap_or("")
+        .trim()
+        .to_string()
+}
+
+/// Reads `Dt` or `DtTm` under the element at `path`.
+fn date(node: Node, path: &[&'static str]) -> Option<time::Date> {
+    let node = path.iter().try_fold(node, |n, name| first(n, name))?;
+    let s = match text(node, &["Dt"]) {
+        s if s.is_empty() => text(node, &["DtTm"]),
+        s => s,
+    };
+    parse_date(s.get(..10)?)
+}
+
+/// Checks that the `Ccy` of the `Amt` of `node`, if given, is `currency`.
+fn check_currency(node: Node, currency: &str) -> Result<(), Diagnostic> {
+    match first(node, "Amt").and_then(|amt| amt.attribute("Ccy")) {
+        Some(ccy) if ccy != currency => Err(error(&format!(
+            "summa on valuuttaa {ccy}, mutta kirjanpidon valuutta on {currency}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// The `Amt` of `node`, negative when `CdtDbtInd` is `DBIT`.
+fn amount(node: Node) -> Option<Money> {
+    signed(first(node, "Amt")?, first(node, "CdtDbtInd"))
+}
+
+fn signed(amt: Node, indicator: Option<Node>) -> Option<Money> {
+    let n = parse_amount(amt.text()?.trim())?;
+    let debit = indicator
+        .and_then(|i| i.text())
+        .is_some_and(|i| i.trim() == "DBIT");
+    Some(if debit { -n } else { n })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camt() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+<BkToCstmrStmt><Stmt>
+  <Bal><Tp><CdOrPrtry><Cd>OPBD</Cd></CdOrPrtry></Tp>
+    <Amt Ccy="EUR">100.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Dt><Dt>2025-03-01</Dt></Dt></Bal>
+  <Bal><Tp><CdOrPrtry><Cd>CLBD</Cd></CdOrPrtry></Tp>
+    <Amt Ccy="EUR">87.50</Amt><CdtDbtInd>CRDT</CdtDbtInd><Dt><Dt>2025-03-31</Dt></Dt></Bal>
+  <Ntry>
+    <Amt Ccy="EUR">12.50</Amt><CdtDbtInd>DBIT</CdtDbtInd><Sts>BOOK</Sts>
+    <BookgDt><Dt>2025-03-02</Dt></BookgDt><AcctSvcrRef>A1</AcctSvcrRef>
+    <NtryDtls><TxDtls>
+      <RltdPties><Cdtr><Nm>K-Market</Nm></Cdtr></RltdPties>
+      <RmtInf><Ustrd>maitoa</Ustrd></RmtInf>
+    </TxDtls></NtryDtls>
+  </Ntry>
+  <Ntry>
+    <Amt Ccy="EUR">1.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts>PDNG</Sts>
+    <BookgDt><Dt>2025-03-03</Dt></BookgDt>
+  </Ntry>
+</Stmt></BkToCstmrStmt>
+</Document>"#;
+        let statement = read(xml, "EUR").unwrap();
+        assert_eq!(statement.balances.len(), 2);
+        assert!(statement.balances[0].opening);
+        assert_eq!(
+            statement.transactions,
+            vec![BankTransaction {
+                date: time::Date::from_calendar_date(2025, time::Month::March, 2).unwrap(),
+                amount: -Money::parse("12,5").unwrap(),
+                payee: "K-Market".into(),
+                reference: "".into(),
+                message: "maitoa".into(),
+                archive_id: "A1".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn split() {
+        let xml = r#"<Document><BkToCstmrStmt><Stmt>
+  <Ntry>
+    <Amt Ccy="EUR">15.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts>BOOK</Sts>
+    <BookgDt><Dt>2025-03-02</Dt></BookgDt><AcctSvcrRef>B1</AcctSvcrRef>
+    <NtryDtls>
+      <TxDtls><AmtDtls><TxAmt><Amt Ccy="EUR">10.00</Amt></TxAmt></AmtDtls>
+        <RmtInf><Strd><CdtrRefInf><Ref>1232</Ref></CdtrRefInf></Strd></RmtInf></TxDtls>
+      <TxDtls><AmtDtls><TxAmt><Amt Ccy="EUR">5.00</Amt></TxAmt></AmtDtls>
+        <RmtInf><Strd><CdtrRefInf><Ref>1245</Ref></CdtrRefInf></Strd></RmtInf></TxDtls>
+    </NtryDtls>
+  </Ntry>
+</Stmt></BkToCstmrStmt></Document>"#;
+        let statement = read(xml, "EUR").unwrap();
+        let transactions = statement
+            .transactions
+            .iter()
+            .map(|t| (t.amount, t.reference.as_str(), t.archive_id.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            transactions,
+            [
+                (Money::from_cents(1000), "1232", "B1/1"),
+                (Money::from_cents(500), "1245", "B1/2")
+            ]
+        );
+        assert_eq!(
+            read(&xml.replace("15.00", "16.00"), "EUR")
+                .unwrap_err()
+                .message,
+            "tiliotetta ei voi lukea: viennin 'B1' tapahtumien summa 15,00 ei vastaa viennin summaa 16,00"
+        );
+        assert_eq!(
+            read(xml, "USD").unwrap_err().message,
+            "tiliotetta ei voi lukea: summa on valuuttaa EUR, mutta kirjanpidon valuutta on USD"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Span {
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(file: usize, line: usize, column: usize) -> Self {
+        Self { file, line, column }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span: Some(span),
+            message: message.into(),
+        }
+    }
+
+    pub fn without_span(message: impl Into<String>) -> Self {
+        Self {
+            span: None,
+            message: message.into(),
+        }
+    }
+}
+
+struct SourceFile {
+    name: String,
+    text: String,
+}
+
+/// Keeps the text of every parsed file so that diagnostics can quote the
+/// offending line.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: &str, text: &str) -> usize {
+        self.files.push(SourceFile {
+            name: name.to_string(),
+            text: text.to_string(),
+        });
+        self.files.len() - 1
+    }
+
+    pub fn name(&self, file: usize) -> &str {
+        self.files.get(file).map_or("?", |f| f.name.as_str())
+    }
+
+    pub fn line(&self, span: Span) -> Option<&str> {
+        self.files
+            .get(span.file)
+            .and_then(|f| f.text.lines().nth(span.line.checked_sub(1)?))
+    }
+
+    /// Renders a diagnostic as `file:line:column: message` followed by the
+    /// quoted source line and a caret under the offending column.
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let Some(span) = diagnostic.span else {
+            return format!("virhe: {}\n", diagnostic.message);
+        };
+        let mut out = format!(
+            "{}:{}:{}: {}\n",
+            self.name(span.file),
+            span.line,
+            span.column,
+            diagnostic.message
+        );
+        if let Some(line) = self.line(span) {
+            let gutter = span.line.to_string();
+            let pad = " ".repeat(gutter.len());
+            let caret_indent: String = line
+                .chars()
+                .take(span.column.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let _ = writeln!(out, " {gutter} | {line}");
+            let _ = writeln!(out, " {pad} | {caret_indent}^");
+        }
+        out
+    }
+
+    pub fn render_all(&self, diagnostics: &[Diagnostic]) -> String {
+        diagnostics.iter().map(|d| self.render(d)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_quotes_line() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("kirjanpito.tamp", "a\n  3999: 100\n");
+        let d = Diagnostic::new(Span::new(file, 2, 3), "tili 3999 ei ole määritelty");
+        assert_eq!(
+            sources.render(&d),
+            "kirjanpito.tamp:2:3: tili 3999 ei ole määritelty\n 2 |   3999: 100\n   |   ^\n"
+        );
+    }
+}
@@ -0,0 +1,673 @@
+//! Exporting the ledger for other programs.
+//!
+//! The JSON export is an object with these keys:
+//!
+//! - `version`: [`JSON_VERSION`], raised when a key is removed or changes
+//!   meaning. New keys may be added without raising it.
+//! - `currency`: the base currency of the amounts.
+//! - `columns`: one per ledger given, the main ledger first and then the
+//!   comparisons in the order given. Each has its `type` (`main` or
+//!   `budget`), `name` (the `lyhenne` option), `period` (`start` and `end`,
+//!   or null) and all its `options`.
+//! - `accounts`: the account tree. Each account has its `number` (null for
+//!   headings), `name`, `type` (`assets`, `liabilities` or
+//!   `income_statement`), `vat` rate, `totals` and `children`. `totals` has
+//!   an element for each column with the `debit` and `credit` sums of the
+//!   account and its children and their `balance`, debits less credits for
+//!   assets and credits less debits otherwise.
+//! - `diary`: the transactions of the main ledger by date, with `n`, `doc`,
+//!   `date`, `description`, `entries`, `metadata` and `recurring`.
+//! - `general_ledger`: for each account with entries its `account` number
+//!   and `entries`, each with the `n` and `doc` of its transaction, `date`,
+//!   `description`, `amount`, `foreign` amount, `cleared` and the running
+//!   `balance` with the sign of `totals`.
+//! - `statements`: for each column the `income`, `expenses` and `result` of
+//!   the income statement and, for the main ledger and comparisons that are
+//!   not budgets, the `assets` and `liabilities` totals of the balance
+//!   sheet. `income` and `expenses` are the net totals of the top-level
+//!   income statement headings with a credit and a debit balance.
+//!   `liabilities` includes the result.
+//!
+//! Amounts are strings with `.` as the decimal separator, e.g. `"-12.50"`,
+//! and dates are written as `2025-03-14`.
+//!
+//! The CSV exports are meant for spreadsheets and have one report each,
+//! with a header row and the columns of the report in the same order.
+//!
+//! The journal export writes the transactions of the main ledger in the
+//! journal format of hledger and ledger-cli, with accounts named by their
+//! path in the account tree.
+
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    ledger::{AccTransaction, Account, Foreign, Ledger, LedgerType, Transaction},
+    money::Money,
+    semantic::{AccountType, Metadata},
+};
+
+/// The version of the JSON export schema.
+pub const JSON_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Export<'a> {
+    version: u32,
+    currency: &'a str,
+    columns: Vec<Column<'a>>,
+    accounts: Vec<AccountOut>,
+    diary: &'a [Transaction],
+    general_ledger: Vec<GeneralLedgerAccount>,
+    statements: Vec<Statements>,
+}
+
+#[derive(Serialize)]
+struct Column<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    name: Option<&'a str>,
+    period: Option<Period>,
+    options: BTreeMap<&'a str, &'a str>,
+}
+
+#[derive(Serialize)]
+struct Period {
+    start: String,
+    end: String,
+}
+
+#[derive(Serialize)]
+struct AccountOut {
+    number: Option<i32>,
+    name: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    vat: Option<Money>,
+    totals: Vec<Totals>,
+    children: Vec<AccountOut>,
+}
+
+#[derive(Serialize)]
+struct Totals {
+    debit: Money,
+    credit: Money,
+    balance: Money,
+}
+
+#[derive(Serialize)]
+struct GeneralLedgerAccount {
+    account: i32,
+    entries: Vec<GeneralLedgerEntry>,
+}
+
+#[derive(Serialize)]
+struct GeneralLedgerEntry {
+    n: i32,
+    doc: String,
+    date: String,
+    description: String,
+    amount: Money,
+    foreign: Option<Foreign>,
+    cleared: bool,
+    balance: Money,
+}
+
+#[derive(Serialize)]
+struct Statements {
+    income: Money,
+    expenses: Money,
+    result: Money,
+    assets: Option<Money>,
+    liabilities: Option<Money>,
+}
+
+/// The ledger and its comparisons as JSON, see the module documentation.
+pub fn json(ledger: &Ledger) -> String {
+    let accounts = ledger.accounts();
+    let export = Export {
+        version: JSON_VERSION,
+        currency: ledger.base_currency(0),
+        columns: columns(ledger),
+        accounts: accounts.iter().map(account).collect(),
+        diary: &ledger.ledger,
+        general_ledger: general_ledger(ledger),
+        statements: statements(ledger, &accounts),
+    };
+    serde_json::to_string_pretty(&export).unwrap()
+}
+
+fn is_budget(ledger: &Ledger, column: usize) -> bool {
+    ledger.comp_ledger_types[column] == LedgerType::Budget
+        || (column == 0 && ledger.ledger_type == LedgerType::Budget)
+}
+
+fn columns(ledger: &Ledger) -> Vec<Column<'_>> {
+    ledger
+        .options
+        .iter()
+        .enumerate()
+        .map(|(i, options)| Column {
+            kind: if is_budget(ledger, i) {
+                "budget"
+            } else {
+                "main"
+            },
+            name: options.get("lyhenne").map(String::as_str),
+            period: ledger.period(i).map(|(start, end)| Period {
+                start: start.to_string(),
+                end: end.to_string(),
+            }),
+            options: options
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect(),
+        })
+        .collect()
+}
+
+/// Debits less credits for assets, credits less debits otherwise.
+fn balance(t: AccountType, debit: Money, credit: Money) -> Money {
+    match t {
+        AccountType::Assets => debit - credit,
+        _ => credit - debit,
+    }
+}
+
+fn account(account: &Account) -> AccountOut {
+    AccountOut {
+        number: account.n,
+        name: account.name.clone(),
+        kind: match account.t {
+            AccountType::Assets => "assets",
+            AccountType::Liabilities | AccountType::LiabilitiesTopLevel => "liabilities",
+            AccountType::None => "income_statement",
+        },
+        vat: account.vat,
+        totals: account
+            .rec_debits
+            .iter()
+            .zip(&account.rec_credits)
+            .map(|(&debit, &credit)| Totals {
+                debit,
+                credit,
+                balance: balance(account.t, debit, credit),
+            })
+            .collect(),
+        children: account
+            .sub_accounts
+            .iter()
+            .map(|a| self::account(&a.borrow()))
+            .collect(),
+    }
+}
+
+fn general_ledger(ledger: &Ledger) -> Vec<GeneralLedgerAccount> {
+    let mut numbers = ledger.account_dict.keys().copied().collect::<Vec<_>>();
+    numbers.sort();
+    numbers
+        .into_iter()
+        .filter_map(|n| {
+            let account = ledger.account_dict[&n].borrow();
+            if account.transactions.is_empty() {
+                return None;
+            }
+            let mut sum = Money::ZERO;
+            let entries = account
+                .transactions
+                .iter()
+                .map(|t: &AccTransaction| {
+                    sum += t.amount;
+                    GeneralLedgerEntry {
+                        n: t.n,
+                        doc: t.doc.clone(),
+                        date: t.date.to_string(),
+                        description: t.description.clone(),
+                        amount: t.amount,
+                        foreign: t.foreign.clone(),
+                        cleared: t.cleared,
+                        balance: balance(account.t, sum, Money::ZERO),
+                    }
+                })
+                .collect();
+            Some(GeneralLedgerAccount {
+                account: n,
+                entries,
+            })
+        })
+        .collect()
+}
+
+fn statements(ledger: &Ledger, accounts: &[Account]) -> Vec<Statements> {
+    (0..ledger.options.len())
+        .map(|i| {
+            let sum = |t: AccountType| {
+                accounts
+                    .iter()
+                    .filter(|a| a.t == t)
+                    .fold((Money::ZERO, Money::ZERO), |(d, c), a| {
+                        (d + a.rec_debits[i], c + a.rec_credits[i])
+                    })
+            };
+            // Net of each top-level heading, so that e.g. a credit note on a
+            // sales account lowers the income instead of adding to expenses
+            let (mut income, mut expenses) = (Money::ZERO, Money::ZERO);
+            for a in accounts.iter().filter(|a| a.t == AccountType::None) {
+                let net = a.rec_credits[i] - a.rec_debits[i];
+                if net.is_negative() {
+                    expenses -= net;
+                } else {
+                    income += net;
+                }
+            }
+            let result = income - expenses;
+            let balance_sheet = !is_budget(ledger, i);
+            let (assets_debit, assets_credit) = sum(AccountType::Assets);
+            let (liabilities_debit, liabilities_credit) = sum(AccountType::LiabilitiesTopLevel);
+            Statements {
+                income,
+                expenses,
+                result,
+                assets: balance_sheet.then(|| assets_debit - assets_credit),
+                liabilities: balance_sheet.then(|| liabilities_credit - liabilities_debit + result),
+            }
+        })
+        .collect()
+}
+
+/// A report exported as CSV.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CsvReport {
+    Diary,
+    GeneralLedger,
+    TrialBalance,
+    BalanceSheet,
+    IncomeStatement,
+}
+
+impl CsvReport {
+    pub const NAMES: [&str; 5] = [
+        "päiväkirja",
+        "pääkirja",
+        "saldoluettelo",
+        "tase",
+        "tuloslaskelma",
+    ];
+
+    pub fn parse(name: &str) -> Option<CsvReport> {
+        match name {
+            "päiväkirja" => Some(CsvReport::Diary),
+            "pääkirja" => Some(CsvReport::GeneralLedger),
+            "saldoluettelo" => Some(CsvReport::TrialBalance),
+            "tase" => Some(CsvReport::BalanceSheet),
+            "tuloslaskelma" => Some(CsvReport::IncomeStatement),
+            _ => None,
+        }
+    }
+}
+
+/// Writes `report` as CSV separated by `delimiter`, with decimal commas in
+/// the amounts unless `decimal_point` is set.
+pub fn csv(ledger: &Ledger, report: CsvReport, delimiter: u8, decimal_point: bool) -> String {
+    let amount = |n: Money| {
+        let s = n.to_string();
+        if decimal_point {
+            s
+        } else {
+            s.replace('.', ",")
+        }
+    };
+    let rows = match report {
+        CsvReport::Diary => diary_rows(ledger, &amount),
+        CsvReport::GeneralLedger => general_ledger_rows(ledger, &amount),
+        CsvReport::TrialBalance => trial_balance_rows(ledger, &amount),
+        CsvReport::BalanceSheet => statement_rows(ledger, true, &amount),
+        CsvReport::IncomeStatement => statement_rows(ledger, false, &amount),
+    };
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_writer(vec![]);
+    for row in rows {
+        writer.write_record(row).unwrap();
+    }
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
+
+type Rows = Vec<Vec<String>>;
+
+/// `amount` if it's not zero, for debit and credit columns.
+fn nonzero(amount: &dyn Fn(Money) -> String, n: Money) -> String {
+    if n.is_zero() {
+        String::new()
+    } else {
+        amount(n)
+    }
+}
+
+fn account_name(ledger: &Ledger, n: i32) -> String {
+    ledger
+        .account_dict
+        .get(&n)
+        .map_or(String::new(), |a| a.borrow().name.clone())
+}
+
+fn diary_rows(ledger: &Ledger, amount: &dyn Fn(Money) -> String) -> Rows {
+    let mut rows = vec![header(&[
+        "Tosite",
+        "Päiväys",
+        "Selite",
+        "Tili",
+        "Tilin nimi",
+        "Debet",
+        "Kredit",
+    ])];
+    for t in &ledger.ledger {
+        for e in &t.entries {
+            rows.push(vec![
+                t.doc.clone(),
+                ledger.fmt_date(t.date),
+                t.description.clone(),
+                e.account.to_string(),
+                account_name(ledger, e.account),
+                nonzero(amount, e.amount.max(Money::ZERO)),
+                nonzero(amount, -e.amount.min(Money::ZERO)),
+            ]);
+        }
+    }
+    rows
+}
+
+fn general_ledger_rows(ledger: &Ledger, amount: &dyn Fn(Money) -> String) -> Rows {
+    let mut rows = vec![header(&[
+        "Tili",
+        "Tilin nimi",
+        "Tosite",
+        "Päiväys",
+        "Selite",
+        "Debet",
+        "Kredit",
+        "Saldo",
+    ])];
+    for n in ledger.account_dict.keys().sorted() {
+        let account = ledger.account_dict[n].borrow();
+        let mut sum = Money::ZERO;
+        for t in &account.transactions {
+            sum += t.amount;
+            rows.push(vec![
+                n.to_string(),
+                account.name.clone(),
+                t.doc.clone(),
+                ledger.fmt_date(t.date),
+                t.description.clone(),
+                nonzero(amount, t.amount.max(Money::ZERO)),
+                nonzero(amount, -t.amount.min(Money::ZERO)),
+                amount(balance(account.t, sum, Money::ZERO)),
+            ]);
+        }
+    }
+    rows
+}
+
+/// The debits, credits and balance of each account of the main ledger with
+/// entries, and their totals.
+fn trial_balance_rows(ledger: &Ledger, amount: &dyn Fn(Money) -> String) -> Rows {
+    let mut rows = vec![header(&["Tili", "Tilin nimi", "Debet", "Kredit", "Saldo"])];
+    let mut debits = Money::ZERO;
+    let mut credits = Money::ZERO;
+    for n in ledger.account_dict.keys().sorted() {
+        let account = ledger.account_dict[n].borrow();
+        let (debit, credit) = (account.debits[0], account.credits[0]);
+        if debit.is_zero() && credit.is_zero() {
+            continue;
+        }
+        debits += debit;
+        credits += credit;
+        rows.push(vec![
+            n.to_string(),
+            account.name.clone(),
+            amount(debit),
+            amount(credit),
+            amount(debit - credit),
+        ]);
+    }
+    rows.push(vec![
+        String::new(),
+        "Yhteensä".into(),
+        amount(debits),
+        amount(credits),
+        amount(debits - credits),
+    ]);
+    rows
+}
+
+/// The rows of the balance sheet or the income statement with a column for
+/// each ledger, in the order of the report. Budgets are left out of the
+/// balance sheet. The income statement has the debits, credits and balance
+/// of each ledger.
+fn statement_rows(ledger: &Ledger, balance_sheet: bool, amount: &dyn Fn(Money) -> String) -> Rows {
+    let columns = (0..ledger.options.len())
+        .rev()
+        .filter(|i| !(balance_sheet && is_budget(ledger, *i)))
+        .collect_vec();
+    let mut header = vec!["Tili".to_string(), "Nimi".to_string()];
+    for &i in &columns {
+        let name = column_name(ledger, i);
+        if balance_sheet {
+            header.push(name);
+        } else {
+            header.extend(["menot", "tulot", "summa"].map(|h| format!("{name} {h}")));
+        }
+    }
+    let mut rows = vec![header];
+    let mut result = ledger.result_account();
+    result.rec_debits.clone_from(&result.debits);
+    result.rec_credits.clone_from(&result.credits);
+    for account in ledger.accounts() {
+        let is_balance_sheet = account.t != AccountType::None;
+        if is_balance_sheet == balance_sheet {
+            account_rows(&account, &columns, &result, amount, &mut rows);
+        }
+    }
+    rows
+}
+
+fn account_rows(
+    account: &Account,
+    columns: &[usize],
+    result: &Account,
+    amount: &dyn Fn(Money) -> String,
+    rows: &mut Rows,
+) {
+    let mut row = vec![
+        account.n.map_or(String::new(), |n| n.to_string()),
+        account.name.clone(),
+    ];
+    // The result of the period is a part of the liabilities
+    let with_result = account.t == AccountType::LiabilitiesTopLevel;
+    for &i in columns {
+        let (mut debit, mut credit) = (account.rec_debits[i], account.rec_credits[i]);
+        if with_result {
+            debit += result.debits[i];
+            credit += result.credits[i];
+        }
+        if account.t == AccountType::None {
+            row.extend([debit, credit, credit - debit].map(amount));
+        } else {
+            row.push(amount(balance(account.t, debit, credit)));
+        }
+    }
+    rows.push(row);
+    for sub in &account.sub_accounts {
+        account_rows(&sub.borrow(), columns, result, amount, rows);
+    }
+    if with_result {
+        account_rows(result, columns, result, amount, rows);
+    }
+}
+
+/// The `lyhenne` of ledger `i`, or its period.
+fn column_name(ledger: &Ledger, i: usize) -> String {
+    match (ledger.options[i].get("lyhenne"), ledger.period(i)) {
+        (Some(name), _) => name.clone(),
+        (None, Some((start, end))) => {
+            format!("{}–{}", ledger.fmt_date(start), ledger.fmt_date(end))
+        }
+        (None, None) => format!("{}", i + 1),
+    }
+}
+
+fn header(names: &[&str]) -> Vec<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
+
+/// The transactions of the main ledger as an hledger and ledger-cli
+/// journal. Docs are the codes of the transactions, and tags, dimensions
+/// and metadata values are written as tags. Amounts are in the base
+/// currency, so the balances of the journal match the ledger.
+pub fn journal(ledger: &Ledger) -> String {
+    let currency = ledger.base_currency(0);
+    let mut names = HashMap::new();
+    let mut out = String::new();
+    for account in ledger.accounts() {
+        account_names(&account, "", &mut names, &mut out);
+    }
+    for t in &ledger.ledger {
+        out.push_str(&format!(
+            "\n{}{} ({}) {}\n",
+            t.date,
+            if t.metadata.cleared { " *" } else { "" },
+            t.doc,
+            t.description.replace(';', ","),
+        ));
+        for comment in journal_tags(&t.metadata) {
+            out.push_str(&format!("    ; {comment}\n"));
+        }
+        for e in &t.entries {
+            let name = names.get(&e.account).map_or("", String::as_str);
+            let status = if e.metadata.cleared && !t.metadata.cleared {
+                "* "
+            } else {
+                ""
+            };
+            out.push_str(&format!("    {status}{name}  {} {currency}", e.amount));
+            // Dimensions of the transaction are already on it
+            let mut metadata = e.metadata.clone();
+            metadata
+                .dimensions
+                .retain(|k, v| t.metadata.dimensions.get(k) != Some(v));
+            let tags = journal_tags(&metadata);
+            if !tags.is_empty() {
+                out.push_str(&format!("  ; {}", tags.join(", ")));
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Writes an `account` directive for `account` and its sub-accounts, and
+/// records their names, the path from the top of the account tree.
+fn account_names(
+    account: &Account,
+    parent: &str,
+    names: &mut HashMap<i32, String>,
+    out: &mut String,
+) {
+    // `:` separates the parts of a name and two spaces end it
+    let part = account.name.replace(':', "-").split_whitespace().join(" ");
+    let name = if parent.is_empty() {
+        part
+    } else {
+        format!("{parent}:{part}")
+    };
+    let kind = match account.t {
+        AccountType::Assets => "  ; type: A",
+        AccountType::Liabilities | AccountType::LiabilitiesTopLevel => "  ; type: L",
+        AccountType::None => "",
+    };
+    out.push_str(&format!("account {name}{kind}\n"));
+    if let Some(n) = account.n {
+        names.insert(n, name.clone());
+    }
+    for sub in &account.sub_accounts {
+        account_names(&sub.borrow(), &name, names, out);
+    }
+}
+
+/// Tags `#name`, dimensions `#name=value` and `key: value` lines as
+/// journal tags.
+fn journal_tags(metadata: &Metadata) -> Vec<String> {
+    let value = |v: &str| v.replace([',', '\n'], " ");
+    metadata
+        .tags
+        .iter()
+        .map(|tag| format!("{tag}:"))
+        .chain(
+            metadata
+                .dimensions
+                .iter()
+                .chain(&metadata.values)
+                .map(|(k, v)| format!("{k}: {}", value(v))),
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::tests::parse_valid;
+
+    #[test]
+    fn json_export() {
+        let source = "§ TIEDOT\nalku = 1.1.2025\nloppu = 31.12.2025\n§ TILIKARTTA\n+ \"Vastaavaa\"\n  1910 \"Pankki\"\n- \"Vastattavaa\"\n  2000 \"Oma pääoma\"\n\"Tulot\"\n  3000 \"Myynti\"\n§ KIRJANPITO\n1.3.2025 \"Myynti\" A1\n  1910: 100\n  3000: AUTO\n";
+        let ledger = parse_valid(source);
+        let value: serde_json::Value = serde_json::from_str(&json(&ledger)).unwrap();
+        assert_eq!(value["version"], JSON_VERSION);
+        assert_eq!(value["columns"][0]["period"]["end"], "2025-12-31");
+        assert_eq!(value["diary"][0]["doc"], "A1");
+        assert_eq!(value["accounts"][0]["totals"][0]["balance"], "100.00");
+        assert_eq!(value["accounts"][2]["type"], "income_statement");
+        assert_eq!(value["general_ledger"][0]["account"], 1910);
+        assert_eq!(
+            value["general_ledger"][0]["entries"][0]["balance"],
+            "100.00"
+        );
+        assert_eq!(value["statements"][0]["result"], "100.00");
+        assert_eq!(value["statements"][0]["liabilities"], "100.00");
+    }
+
+    #[test]
+    fn statements_net() {
+        let source = "§ TILIKARTTA\n+ \"Vastaavaa\"\n  1910 \"Pankki\"\n\"Tulot\"\n  3000 \"Myynti\"\n\"Menot\"\n  4000 \"Ostot\"\n§ KIRJANPITO\n1.3.2025 \"Myynti\"\n  1910: 100\n  3000: AUTO\n2.3.2025 \"Hyvitys\"\n  3000: 10\n  1910: AUTO\n3.3.2025 \"Osto\"\n  4000: 30\n  1910: AUTO\n";
+        let ledger = parse_valid(source);
+        let value: serde_json::Value = serde_json::from_str(&json(&ledger)).unwrap();
+        assert_eq!(value["statements"][0]["income"], "90.00");
+        assert_eq!(value["statements"][0]["expenses"], "30.00");
+        assert_eq!(value["statements"][0]["result"], "60.00");
+    }
+
+    #[test]
+    fn journal_export() {
+        let source = "§ TILIKARTTA\n+ \"Vastaavaa\"\n  \"Rahat\"\n    1910 \"Pankki: käyttötili\"\n\"Tulot\"\n  3000 \"Myynti\"\n§ KIRJANPITO\n1.3.2025 \"Myynti\" A1 #projekti=nuoriso\n  * 1910: 12,5\n  3000: AUTO\n";
+        let ledger = parse_valid(source);
+        assert_eq!(
+            journal(&ledger),
+            "account Vastaavaa  ; type: A\naccount Vastaavaa:Rahat  ; type: A\naccount Vastaavaa:Rahat:Pankki- käyttötili  ; type: A\naccount Tulot\naccount Tulot:Myynti\n\n2025-03-01 (A1) Myynti\n    ; projekti: nuoriso\n    * Vastaavaa:Rahat:Pankki- käyttötili  12.50 EUR\n    Tulot:Myynti  -12.50 EUR\n"
+        );
+    }
+
+    #[test]
+    fn csv_export() {
+        let source = "§ TIEDOT\nlyhenne = \"2025\"\n§ TILIKARTTA\n+ \"Vastaavaa\"\n  1910 \"Pankki\"\n- \"Vastattavaa\"\n  2000 \"Oma pääoma\"\n\"Tulot\"\n  3000 \"Myynti\"\n§ KIRJANPITO\n1.3.2025 \"Myynti\"\n  1910: 12,5\n  3000: AUTO\n";
+        let ledger = parse_valid(source);
+        assert_eq!(
+            csv(&ledger, CsvReport::Diary, b';', false),
+            "Tosite;Päiväys;Selite;Tili;Tilin nimi;Debet;Kredit\n0;1.3.2025;Myynti;1910;Pankki;12,50;\n0;1.3.2025;Myynti;3000;Myynti;;12,50\n"
+        );
+        assert_eq!(
+            csv(&ledger, CsvReport::BalanceSheet, b',', true),
+            "Tili,Nimi,2025\n,Vastaavaa,12.50\n1910,Pankki,12.50\n,Vastattavaa,12.50\n2000,Oma pääoma,0.00\n,Tilikauden tulos,12.50\n"
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use time::{Date, macros::format_description};
+
+use crate::{
+    diagnostic::Diagnostic,
+    lexer::{Lexer, Token, closing_bracket},
+    money::Money,
+    parser::Parser,
+    semantic::{REFERENCE, is_amount},
+};
+
+const INDENT: &str = "  ";
+
+/// One physical line of a source file. Read in order the lines form the
+/// file's syntax tree: the children of a line are the following lines with
+/// a greater depth. Everything except whitespace is kept, comments included.
+struct Line {
+    depth: usize,
+    tokens: Vec<Token>,
+    comment: Option<String>,
+    blank_before: bool,
+    has_children: bool,
+    /// The source text of the line
+    raw: String,
+}
+
+impl Line {
+    fn is_comment_only(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Entry lines look like `1910: 12,50` or `* 1910: 12,50` and have their
+    /// amounts aligned.
+    /// Returns the position of the colon.
+    fn entry_colon(&self) -> Option<usize> {
+        let colon = self.tokens.iter().position(|t| *t == Token::Colon)?;
+        let first = (self.tokens.first() == Some(&Token::Cleared)) as usize;
+        let is_entry = !self.has_children
+            && colon > first
+            && colon + 1 < self.tokens.len()
+            && matches!(self.tokens[first], Token::Number(_) | Token::Identifier(_))
+            && self.tokens[first + 1..colon]
+                .iter()
+                .all(|t| matches!(t, Token::Identifier(_) | Token::Tag(..)));
+        is_entry.then_some(colon)
+    }
+
+    fn is_entry(&self) -> bool {
+        !self.is_metadata() && self.entry_colon().is_some()
+    }
+
+    /// Metadata lines look like `laskunro: 2025-17`. Their values are kept
+    /// as written unless they're strings. A `viite` is never an amount.
+    fn is_metadata(&self) -> bool {
+        !self.has_children
+            && matches!(
+                &self.tokens[..],
+                [Token::Identifier(key), Token::Colon, rest @ ..]
+                    if !rest.is_empty()
+                        && (key == REFERENCE || !is_amount(rest))
+                        && !matches!(rest, [Token::String(_)])
+            )
+    }
+}
+
+/// Formats `source` canonically: two-space indentation, amounts with at
+/// least two decimals, `D`/`C` for debit and credit, normalised quotes and
+/// aligned amounts in transactions. Comments and single blank lines are
+/// kept. Sources that don't parse are not formatted.
+pub fn format_source(source: &str, file: usize) -> Result<String, Vec<Diagnostic>> {
+    let mut parser = Parser::with_file(source, file);
+    parser.parse();
+    if !parser.errors.is_empty() {
+        return Err(parser.errors);
+    }
+    let lines = lines(source, file);
+    let mut out = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = &lines[i];
+        if line.blank_before {
+            out.push('\n');
+        }
+        if line.is_metadata() {
+            let value = line.raw.split_once(':').map_or("", |(_, v)| v);
+            let value = value.split("--").next().unwrap_or_default().trim();
+            let text = format!("{}: {value}", render(&line.tokens, 0..1, false));
+            write_line(&mut out, line, text);
+            i += 1;
+        } else if line.is_entry() {
+            let group_end = (i + 1..lines.len())
+                .find(|&j| {
+                    let l = &lines[j];
+                    !l.is_entry() || l.depth != line.depth || l.blank_before
+                })
+                .unwrap_or(lines.len());
+            write_entries(&mut out, &lines[i..group_end]);
+            i = group_end;
+        } else {
+            let text = render(&line.tokens, 0..line.tokens.len(), line.has_children);
+            write_line(&mut out, line, text);
+            i += 1;
+        }
+    }
+    Ok(out)
+}
+
+fn write_line(out: &mut String, line: &Line, text: String) {
+    let mut text = format!("{}{text}", INDENT.repeat(line.depth));
+    if let Some(comment) = &line.comment {
+        if !line.is_comment_only() {
+            text.push(' ');
+        }
+        text.push_str("--");
+        text.push_str(comment.trim_end());
+    }
+    out.push_str(text.trim_end());
+    out.push('\n');
+}
+
+fn write_entries(out: &mut String, group: &[Line]) {
+    let colons = group
+        .iter()
+        .map(|l| l.entry_colon().unwrap())
+        .collect::<Vec<_>>();
+    let accounts = group
+        .iter()
+        .zip(&colons)
+        .map(|(l, c)| render(&l.tokens, 0..c + 1, false))
+        .collect::<Vec<_>>();
+    let amounts = group
+        .iter()
+        .zip(&colons)
+        .map(|(l, c)| render(&l.tokens, c + 1..l.tokens.len(), false))
+        .collect::<Vec<_>>();
+    let account_width = accounts
+        .iter()
+        .map(|a| a.chars().count())
+        .max()
+        .unwrap_or(0);
+    let amount_width = group
+        .iter()
+        .zip(&colons)
+        .zip(&amounts)
+        .filter(|((l, c), _)| is_numeric(&l.tokens[*c + 1..]))
+        .map(|(_, a)| a.chars().count())
+        .max()
+        .unwrap_or(0);
+    for (((line, colon), account), amount) in group.iter().zip(colons).zip(accounts).zip(amounts) {
+        let text = if is_numeric(&line.tokens[colon + 1..]) {
+            format!("{account:account_width$} {amount:>amount_width$}")
+        } else {
+            format!("{account:account_width$} {amount}")
+        };
+        write_line(out, line, text);
+    }
+}
+
+fn is_numeric(tokens: &[Token]) -> bool {
+    let tokens = tokens
+        .iter()
+        .filter(|t| !matches!(t, Token::DigitGroup(_)))
+        .cloned()
+        .collect::<Vec<_>>();
+    matches!(
+        tokens[..],
+        [Token::Number(_)]
+            | [
+                Token::Minus | Token::Debit | Token::Credit,
+                Token::Number(_)
+            ]
+            | [Token::Number(_), Token::Debit | Token::Credit]
+    )
+}
+
+/// Renders `tokens[range]`, looking at the neighbouring tokens to tell
+/// account numbers from amounts.
+fn render(tokens: &[Token], range: std::ops::Range<usize>, has_children: bool) -> String {
+    let mut out = String::new();
+    for i in range.clone() {
+        let token = &tokens[i];
+        let prev = if i > range.start {
+            Some(&tokens[i - 1])
+        } else {
+            None
+        };
+        let next = tokens.get(i + 1);
+        let space = !matches!(
+            (prev, token),
+            (None, _)
+                | (_, Token::Colon | Token::Semicolon | Token::BlockEnd(_))
+                | (Some(Token::BlockStart(_)), _)
+                | (Some(Token::Minus | Token::Plus), Token::Number(_))
+        ) && !is_range(prev, token, next);
+        if space {
+            out.push(' ');
+        }
+        match token {
+            Token::Number(n) if matches!(next, Some(Token::DigitGroup(_))) => {
+                match n.as_integer() {
+                    Some(n) => out.push_str(&n.to_string()),
+                    None => out.push_str(&amount(*n)),
+                }
+            }
+            Token::DigitGroup(group) => {
+                let mut text = group.replace('.', ",");
+                if !matches!(next, Some(Token::DigitGroup(_))) {
+                    let decimals = text.find(',').map(|i| text.len() - i - 1);
+                    if decimals.is_none() {
+                        text.push(',');
+                    }
+                    text.push_str(&"0".repeat(2_usize.saturating_sub(decimals.unwrap_or(0))));
+                }
+                out.push_str(&text);
+            }
+            Token::Number(n) => {
+                // VAT codes and tags may come between an account and its colon
+                let after_codes = tokens[i + 1..]
+                    .iter()
+                    .find(|t| !matches!(t, Token::Identifier(_) | Token::Tag(..)));
+                let is_account = matches!(
+                    after_codes,
+                    Some(Token::Colon | Token::String(_) | Token::BlockStart(_) | Token::Assign)
+                ) || matches!(
+                    tokens[..i].last(),
+                    Some(Token::Assign | Token::Auto)
+                ) || (after_codes.is_none() && has_children);
+                match n.as_integer() {
+                    Some(n) if is_account => out.push_str(&n.to_string()),
+                    _ => out.push_str(&amount(*n)),
+                }
+            }
+            Token::Date(d) => out.push_str(&date(*d)),
+            Token::String(s) => out.push_str(&quote(s)),
+            Token::Identifier(s) => out.push_str(s),
+            Token::Minus if is_range(prev, token, next) => out.push('–'),
+            Token::Minus => out.push('-'),
+            Token::Plus => out.push('+'),
+            Token::Debit => out.push('D'),
+            Token::Credit => out.push('C'),
+            Token::Assign => out.push('='),
+            Token::Colon => out.push(':'),
+            Token::Semicolon => out.push(';'),
+            Token::BlockStart(c) => out.push(*c),
+            Token::BlockEnd(c) => out.push(closing_bracket(*c)),
+            Token::Auto => out.push_str("AUTO"),
+            Token::Section => out.push('§'),
+            Token::At => out.push('@'),
+            Token::Cleared => out.push('*'),
+            Token::Tag(key, value) => {
+                out.push('#');
+                out.push_str(key);
+                if !value.is_empty() {
+                    out.push('=');
+                    out.push_str(value);
+                }
+            }
+            Token::ColonBlockEnd
+            | Token::Newline
+            | Token::Indent
+            | Token::Dedent
+            | Token::Comment(_)
+            | Token::Eof => {}
+        }
+    }
+    out
+}
+
+pub fn amount(n: Money) -> String {
+    n.to_string().replace('.', ",")
+}
+
+/// A percentage without trailing zeros, e.g. `25,5`.
+pub fn rate(n: Money) -> String {
+    let s = n.to_string();
+    s.trim_end_matches('0')
+        .trim_end_matches('.')
+        .replace('.', ",")
+}
+
+/// Whether `token` is the dash or the end of a date range, `1.1.–31.12.`.
+fn is_range(prev: Option<&Token>, token: &Token, next: Option<&Token>) -> bool {
+    matches!(
+        (prev, token, next),
+        (Some(Token::Date(_)), Token::Minus, Some(Token::Date(_)))
+    ) || matches!((prev, token), (Some(Token::Minus), Token::Date(_)))
+}
+
+pub fn date(d: Date) -> String {
+    if d.year() == 0 {
+        format!("{}.{}.", d.day(), d.month() as u8)
+    } else {
+        d.format(format_description!(
+            "[day padding:none].[month padding:none].[year padding:none]"
+        ))
+        .unwrap()
+    }
+}
+
+/// Quotes `s` with `"`, falling back to `'` or `»` if it contains the
+/// quote character.
+pub fn quote(s: &str) -> String {
+    if !s.contains('"') {
+        format!("\"{s}\"")
+    } else if !s.contains('\'') {
+        format!("'{s}'")
+    } else {
+        format!("»{s}»")
+    }
+}
+
+fn lines(source: &str, file: usize) -> Vec<Line> {
+    let mut lexer = Lexer::with_file(source, file).keeping_comments();
+    let source_lines = source.lines().collect::<Vec<_>>();
+    let mut lines: Vec<Line> = Vec::new();
+    let mut line_numbers: Vec<usize> = Vec::new();
+    let mut depth = 0_usize;
+    let mut brackets = 0_usize;
+    while let Some((token, span)) = lexer.next_spanned() {
+        match token {
+            Token::Indent => depth += 1,
+            Token::Dedent => depth = depth.saturating_sub(1),
+            Token::Newline | Token::ColonBlockEnd => {}
+            Token::Eof => break,
+            token => {
+                if line_numbers.last() != Some(&span.line) {
+                    let closes = matches!(token, Token::BlockEnd(_)) as usize;
+                    lines.push(Line {
+                        depth: (depth + brackets).saturating_sub(closes),
+                        tokens: Vec::new(),
+                        comment: None,
+                        blank_before: line_numbers.last().is_some_and(|l| span.line > l + 1),
+                        has_children: false,
+                        raw: source_lines[span.line - 1].trim().to_string(),
+                    });
+                    line_numbers.push(span.line);
+                }
+                let line = lines.last_mut().unwrap();
+                match token {
+                    Token::Comment(c) => line.comment = Some(c),
+                    Token::BlockStart(c) => {
+                        brackets += 1;
+                        line.tokens.push(Token::BlockStart(c));
+                    }
+                    Token::BlockEnd(c) => {
+                        brackets = brackets.saturating_sub(1);
+                        line.tokens.push(Token::BlockEnd(c));
+                    }
+                    token => line.tokens.push(token),
+                }
+            }
+        }
+    }
+    // Comments on their own line belong to the code that follows them.
+    let mut next_depth = 0;
+    for line in lines.iter_mut().rev() {
+        if line.is_comment_only() {
+            line.depth = next_depth;
+        } else {
+            line.has_children = line.depth < next_depth;
+            next_depth = line.depth;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSY: &str = "-- Tilit\n§ TILIKARTTA\n+ 'Vastaavaa'\n    1910 »Pankki»\n\"Tulot\"\n    3000 \"Myynti\"\n\n\n§ KIRJANPITO\n1.1.\n    'Myynti' -- käteinen\n        1910: 12,5\n        3000:  Cr 12.5\n    \"Toinen\"\n      -- huom\n      1910: 1234\n      3000: C 1 234,5\n    tarkista 1910 = 1 034,5\n";
+
+    #[test]
+    fn formats() {
+        assert_eq!(
+            format_source(MESSY, 0).unwrap(),
+            "-- Tilit\n§ TILIKARTTA\n+ \"Vastaavaa\"\n  1910 \"Pankki\"\n\"Tulot\"\n  3000 \"Myynti\"\n\n§ KIRJANPITO\n1.1.\n  \"Myynti\" -- käteinen\n    1910:   12,50\n    3000: C 12,50\n  \"Toinen\"\n    -- huom\n    1910:    1234,00\n    3000: C 1 234,50\n  tarkista 1910 = 1 034,50\n"
+        );
+    }
+
+    #[test]
+    fn idempotent() {
+        let once = format_source(MESSY, 0).unwrap();
+        assert_eq!(format_source(&once, 0).unwrap(), once);
+        let statement =
+            "§ KIRJANPITO\n31.3.2025: tiliote 1910 = 1 000\n1.1.2025: tarkista 1910 = 12 005,5\n";
+        let once = format_source(statement, 0).unwrap();
+        assert_eq!(
+            once,
+            "§ KIRJANPITO\n31.3.2025: tiliote 1910 = 1 000,00\n1.1.2025: tarkista 1910 = 12 005,50\n"
+        );
+        assert_eq!(format_source(&once, 0).unwrap(), once);
+    }
+}
@@ -145,8 +145,7 @@ impl Html {
     }
 
     pub fn div_with_class(class: &str) -> Self {
-        let elem = Self::new("div").with_class(class);
-        elem
+        Self::new("div").with_class(class)
     }
 
     pub fn new_void(tag: &str) -> Self {
@@ -164,7 +163,7 @@ impl Html {
     pub fn div_with_class_and_text(class: &str, text: String) -> Html {
         Self::div_with_class(class).with_text(&text)
     }
-    
+
     pub fn push_child_div_with_class_and_text(&mut self, child_class: &str, child_text: String) {
         let child = Self::div_with_class(child_class).with_text(&child_text);
         self.push_child(child);
@@ -226,9 +225,3 @@ impl Html {
         self.0.render(true, 0)
     }
 }
-
-impl From<&Html> for Html {
-    fn from(val: &Html) -> Self {
-        val.into()
-    }
-}
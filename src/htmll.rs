@@ -1,11 +1,18 @@
-use std::iter::zip;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    iter::zip,
+    path::Path,
+};
 
 use itertools::Itertools;
+use time::Date;
 
 use crate::{
+    format,
     html::Html,
-    ledger::{Account, Ledger, LedgerType, Transaction},
-    semantic::AccountType,
+    ledger::{Account, Ledger, LedgerType, OpenItems, Reconciliation, Transaction, VatEntry},
+    money::Money,
+    semantic::{AccountType, Metadata},
 };
 
 pub(crate) enum Budgeting {
@@ -37,10 +44,7 @@ impl Ledger {
 
         let mut body = Html::new("body");
 
-        let is_budgeting = match budgeting {
-            Budgeting::No => false,
-            _ => true,
-        };
+        let is_budgeting = !matches!(budgeting, Budgeting::No);
 
         if !(self.ledger_type == LedgerType::Budget || is_budgeting) {
             if include_d_gl {
@@ -99,6 +103,70 @@ impl Ledger {
                 .with_child(self.html_income_statement(is_budgeting, include_d_gl)),
         );
 
+        if !(self.vat.is_empty() || self.ledger_type == LedgerType::Budget || is_budgeting) {
+            body.push_child(
+                Html::new("section")
+                    .with_attribute("id", "arvonlisävero")
+                    .with_child(Html::new("h2").with_text("Arvonlisävero"))
+                    .with_child(self.html_vat_report()),
+            );
+        }
+
+        let dimensions = self.dimension_names();
+        if !(dimensions.is_empty() || self.ledger_type == LedgerType::Budget || is_budgeting) {
+            let mut section = Html::new("section")
+                .with_attribute("id", "ulottuvuudet")
+                .with_child(Html::new("h2").with_text("Tuloslaskelma ulottuvuuksittain"));
+            for name in dimensions {
+                section.push_child(Html::new("h3").with_string(format!("#{name}")));
+                section.push_child(self.html_dimension_report(name));
+            }
+            body.push_child(section);
+        }
+
+        let ledgers = [
+            (
+                "myyntireskontra",
+                "Myyntireskontra",
+                self.sales_ledger(),
+                "Asiakas",
+            ),
+            (
+                "ostoreskontra",
+                "Ostoreskontra",
+                self.purchase_ledger(),
+                "Toimittaja",
+            ),
+        ];
+        for (id, title, items, party) in ledgers {
+            let Some(items) = items else {
+                continue;
+            };
+            if self.ledger_type == LedgerType::Budget || is_budgeting {
+                continue;
+            }
+            let mut section = Html::new("section")
+                .with_attribute("id", id)
+                .with_child(Html::new("h2").with_text(title));
+            for table in self.html_open_items(&items, party) {
+                section.push_child(table);
+            }
+            body.push_child(section);
+        }
+
+        let reconciliations = self.reconciliations();
+        if !reconciliations.is_empty() && self.ledger_type != LedgerType::Budget && !is_budgeting {
+            let mut section = Html::new("section")
+                .with_attribute("id", "täsmäytys")
+                .with_child(Html::new("h2").with_text("Täsmäytys"));
+            for reconciliation in &reconciliations {
+                for elem in self.html_reconciliation(reconciliation) {
+                    section.push_child(elem);
+                }
+            }
+            body.push_child(section);
+        }
+
         match budgeting {
             Budgeting::File => {
                 body.push_child(
@@ -138,6 +206,334 @@ impl Ledger {
             .with_child(root)
     }
 
+    /// Taxable sales per VAT rate, purchases and the VAT due for each month,
+    /// or quarter if `alv_jakso` is `neljännesvuosi`.
+    fn html_vat_report(&self) -> Html {
+        let quarterly = self.options[0]
+            .get("alv_jakso")
+            .is_some_and(|p| p == "neljännesvuosi");
+        let mut periods: BTreeMap<(i32, u8), Vec<&VatEntry>> = BTreeMap::new();
+        for entry in &self.vat {
+            let month = entry.date.month() as u8;
+            let period = if quarterly {
+                (month - 1) / 3 + 1
+            } else {
+                month
+            };
+            periods
+                .entry((entry.date.year(), period))
+                .or_default()
+                .push(entry);
+        }
+        let row = |label: String, net: Option<Money>, tax: Money| {
+            Html::new("tr")
+                .with_child(Html::new("td").with_string(label))
+                .with_child(
+                    Html::new("td")
+                        .with_class("amount")
+                        .with_string(net.map_or("".into(), |n| self.amount_as_string(n, true))),
+                )
+                .with_child(
+                    Html::new("td")
+                        .with_class("amount")
+                        .with_string(self.amount_as_string(tax, true)),
+                )
+        };
+        let mut table = Html::new("table").with_class("vat-report").with_child(
+            Html::new("tr")
+                .with_child(Html::new("th").with_text("Kausi"))
+                .with_child(Html::new("th").with_text("Veroton"))
+                .with_child(Html::new("th").with_text("Vero")),
+        );
+        for ((year, period), entries) in periods {
+            let label = if quarterly {
+                format!("Q{period}/{year}")
+            } else {
+                format!("{period}/{year}")
+            };
+            table.push_child(Html::new("tr").with_child(Html::new("th").with_string(label)));
+            let mut sales: BTreeMap<Money, (Money, Money)> = BTreeMap::new();
+            let (mut purchases, mut deductible) = (Money::ZERO, Money::ZERO);
+            for entry in entries {
+                if entry.sales {
+                    let (net, tax) = sales.entry(entry.rate).or_default();
+                    *net -= entry.net;
+                    *tax -= entry.tax;
+                } else {
+                    purchases += entry.net;
+                    deductible += entry.tax;
+                }
+            }
+            let mut due = -deductible;
+            for (rate, (net, tax)) in sales.into_iter().rev() {
+                due += tax;
+                table.push_child(row(
+                    format!("Myynti {} %", format::rate(rate)),
+                    Some(net),
+                    tax,
+                ));
+            }
+            table.push_child(row("Ostot".into(), Some(purchases), deductible));
+            table.push_child(row("Maksettava vero".into(), None, due));
+        }
+        table
+    }
+
+    /// The open invoices by due date, and their total by age at the report
+    /// date reconciled with the balance of the account.
+    fn html_open_items(&self, items: &OpenItems, party: &str) -> Vec<Html> {
+        let date = self.report_date();
+        let overdue = |due: Date| date.map_or(0, |d| (d - due).whole_days());
+        let mut invoices = Html::new("table").with_class("open-items").with_child(
+            [
+                "Tosite",
+                "Päiväys",
+                party,
+                "Viite",
+                "Eräpäivä",
+                "Laskutettu",
+                "Avoinna",
+                "Myöhässä",
+            ]
+            .into_iter()
+            .fold(Html::new("tr"), |tr, h| {
+                tr.with_child(Html::new("th").with_text(h))
+            }),
+        );
+        let mut ages = [Money::ZERO; AGEING.len()];
+        for invoice in items
+            .invoices
+            .iter()
+            .filter(|i| !i.open.is_zero())
+            .sorted_by_key(|i| i.due)
+        {
+            let days = overdue(invoice.due);
+            let bucket = AGEING
+                .iter()
+                .rposition(|(min, _)| days >= *min)
+                .unwrap_or(0);
+            ages[bucket] += invoice.open;
+            let cells = [
+                invoice.transaction.doc.clone(),
+                self.fmt_date(invoice.transaction.date),
+                invoice.party.clone().unwrap_or_default(),
+                invoice.reference.clone(),
+                self.fmt_date(invoice.due),
+            ];
+            let mut row = cells.into_iter().fold(Html::new("tr"), |tr, c| {
+                tr.with_child(Html::new("td").with_string(c))
+            });
+            for amount in [invoice.amount, invoice.open] {
+                row.push_child(
+                    Html::new("td")
+                        .with_class("amount")
+                        .with_string(self.amount_as_string(amount, true)),
+                );
+            }
+            row.push_child(
+                Html::new("td")
+                    .with_class("amount")
+                    .with_string(if days > 0 {
+                        format!("{days} pv")
+                    } else {
+                        "".into()
+                    }),
+            );
+            invoices.push_child(row);
+        }
+
+        let row = |label: String, amount: Money| {
+            Html::new("tr")
+                .with_child(Html::new("td").with_string(label))
+                .with_child(
+                    Html::new("td")
+                        .with_class("amount")
+                        .with_string(self.amount_as_string(amount, true)),
+                )
+        };
+        let mut ageing = Html::new("table").with_class("ageing").with_child(
+            Html::new("tr")
+                .with_child(Html::new("th").with_string(match date {
+                    Some(d) => format!("Ikä {}", self.fmt_date(d)),
+                    None => "Ikä".into(),
+                }))
+                .with_child(Html::new("th").with_text("Avoinna")),
+        );
+        for ((_, label), amount) in AGEING.iter().zip(ages) {
+            ageing.push_child(row(label.to_string(), amount));
+        }
+        let open = ages.iter().fold(Money::ZERO, |a, &b| a + b);
+        ageing.push_child(row("Avoimet laskut yhteensä".into(), open));
+        ageing.push_child(row("Kohdistamattomat".into(), items.unallocated));
+        ageing.push_child(row(format!("Tilin {} saldo", items.account), items.balance));
+        ageing.push_child(row(
+            "Erotus".into(),
+            items.balance - open - items.unallocated,
+        ));
+        vec![invoices, ageing]
+    }
+
+    /// The uncleared entries of an account and its balance compared with the
+    /// latest bank statement.
+    fn html_reconciliation(&self, reconciliation: &Reconciliation) -> Vec<Html> {
+        let name = self
+            .get_account(reconciliation.account)
+            .map_or(String::new(), |a| a.name);
+        let title = Html::new("h3").with_string(format!("{} {name}", reconciliation.account));
+        let amount_cell = |amount: Money| {
+            Html::new("td")
+                .with_class("amount")
+                .with_string(self.amount_as_string(amount, true))
+        };
+        let mut uncleared = Html::new("table").with_class("uncleared").with_child(
+            ["Tosite", "Päiväys", "Selite", "Summa"]
+                .into_iter()
+                .fold(Html::new("tr"), |tr, h| {
+                    tr.with_child(Html::new("th").with_text(h))
+                }),
+        );
+        let mut total = Money::ZERO;
+        for (transaction, amount) in &reconciliation.uncleared {
+            total += *amount;
+            let cells = [
+                transaction.doc.clone(),
+                self.fmt_date(transaction.date),
+                transaction.description.clone(),
+            ];
+            uncleared.push_child(
+                cells
+                    .into_iter()
+                    .fold(Html::new("tr"), |tr, c| {
+                        tr.with_child(Html::new("td").with_string(c))
+                    })
+                    .with_child(amount_cell(*amount)),
+            );
+        }
+        uncleared.push_child(
+            Html::new("tr")
+                .with_child(
+                    Html::new("td")
+                        .with_attribute("colspan", "3")
+                        .with_text("Täsmäyttämättä yhteensä"),
+                )
+                .with_child(amount_cell(total)),
+        );
+
+        let row = |label: String, amount: Money| {
+            Html::new("tr")
+                .with_child(Html::new("td").with_string(label))
+                .with_child(amount_cell(amount))
+        };
+        let mut balances = Html::new("table").with_class("reconciliation");
+        match reconciliation.statement {
+            Some((date, amount)) => {
+                let date = self.fmt_date(date);
+                balances.push_child(row(format!("Tiliotteen saldo {date}"), amount));
+                balances.push_child(row(
+                    format!("Kirjanpidon saldo {date}"),
+                    reconciliation.balance,
+                ));
+                balances.push_child(row("Erotus".into(), reconciliation.balance - amount));
+            }
+            None => balances.push_child(row("Kirjanpidon saldo".into(), reconciliation.balance)),
+        }
+        vec![title, balances, uncleared]
+    }
+
+    /// The income statement split into columns by the values of dimension
+    /// `name`.
+    fn html_dimension_report(&self, name: &str) -> Html {
+        let sums = self.dimension_sums(name);
+        let mut columns = sums
+            .values()
+            .flat_map(|s| s.keys().copied())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect_vec();
+        // Entries without the dimension last
+        if columns.first() == Some(&None) {
+            columns.rotate_left(1);
+        }
+
+        let mut header = Html::new("tr").with_child(Html::new("th").with_text("Tili"));
+        for column in &columns {
+            header.push_child(Html::new("th").with_text(column.unwrap_or("kohdistamaton")));
+        }
+        header.push_child(Html::new("th").with_text("yhteensä"));
+        let mut rows = vec![header];
+        let mut result = vec![Money::ZERO; columns.len()];
+        for account in self.accounts().iter().filter(|a| a.t == AccountType::None) {
+            let account_sums = self.dimension_rows(account, 0, &sums, &columns, &mut rows);
+            for (r, s) in result.iter_mut().zip(account_sums) {
+                *r += s;
+            }
+        }
+        rows.push(self.dimension_row("Tilikauden tulos".into(), 0, &result));
+        let mut table = Html::new("table").with_class("dimension-report");
+        for row in rows {
+            table.push_child(row);
+        }
+        table
+    }
+
+    /// Adds the rows of `account` and its sub-accounts with a non-zero sum
+    /// to `rows`, and returns the sums of the account.
+    fn dimension_rows(
+        &self,
+        account: &Account,
+        depth: usize,
+        sums: &HashMap<i32, BTreeMap<Option<&str>, Money>>,
+        columns: &[Option<&str>],
+        rows: &mut Vec<Html>,
+    ) -> Vec<Money> {
+        let mut account_sums = match account.n.and_then(|n| sums.get(&n)) {
+            Some(s) => columns
+                .iter()
+                .map(|c| -s.get(c).copied().unwrap_or_default())
+                .collect(),
+            None => vec![Money::ZERO; columns.len()],
+        };
+        let mut sub_rows = vec![];
+        for sub_account in &account.sub_accounts {
+            let sub_sums = self.dimension_rows(
+                &sub_account.borrow(),
+                depth + 1,
+                sums,
+                columns,
+                &mut sub_rows,
+            );
+            for (a, s) in account_sums.iter_mut().zip(sub_sums) {
+                *a += s;
+            }
+        }
+        if account_sums.iter().any(|s| !s.is_zero()) {
+            let label = match account.n {
+                Some(n) => format!("{n} {}", account.name),
+                None => account.name.clone(),
+            };
+            rows.push(self.dimension_row(label, depth, &account_sums));
+            rows.append(&mut sub_rows);
+        }
+        account_sums
+    }
+
+    fn dimension_row(&self, label: String, depth: usize, sums: &[Money]) -> Html {
+        let mut row = Html::new("tr").with_child(
+            Html::new("td")
+                .with_class(&format!("depth-{depth}"))
+                .with_string(label),
+        );
+        let total = sums.iter().fold(Money::ZERO, |a, &b| a + b);
+        for sum in sums.iter().chain([&total]) {
+            row.push_child(
+                Html::new("td")
+                    .with_class("amount")
+                    .with_string(self.amount_as_string(*sum, false)),
+            );
+        }
+        row
+    }
+
     fn html_diary(&self) -> Html {
         let mut diary = Html::div_with_class("diary");
 
@@ -149,7 +545,7 @@ impl Ledger {
 
         let transactions = &self.ledger;
 
-        for transaction in transactions {
+        for transaction in transactions.iter().filter(|t| self.is_shown(t)) {
             diary.push_child(self.html_transaction(transaction.clone()));
         }
         diary
@@ -169,86 +565,109 @@ impl Ledger {
 
         let account_ns = self.account_dict.keys().sorted();
         for account_n in account_ns {
-            if let Some(account) = self.get_account(*account_n) {
-                if !account.transactions.is_empty() {
-                    let mut account_elem = Html::div_with_class("account");
-                    account_elem
-                        .push_attribute("id", format!("gl-{}", account.n.unwrap()).as_str());
-                    let mut header = Html::div_with_class("header");
-                    header.push_child(
-                        Html::div_with_class("account-info")
-                            .with_child(
-                                Html::new("a")
-                                    .with_class("n")
-                                    .with_attribute("href", format!("#a-{account_n}").as_str())
-                                    .with_string(format!("{account_n}")),
-                            )
-                            .with_child(Html::div_with_class_and_text("name", account.name)),
-                    );
-                    account_elem.push_child(header);
-                    let mut entries = Html::div_with_class("entries");
-                    let mut debit_sum = 0;
-                    let mut credit_sum = 0;
-                    let sum_multiplyer = if account.t == AccountType::Assets {
-                        1
-                    } else {
-                        -1
-                    };
-                    for transaction in account.transactions {
-                        let date = transaction.clone().fmt_date();
-                        let doc = transaction.doc;
-                        let desc = transaction.description;
-                        let amount = transaction.amount;
-                        if amount > 0 {
-                            debit_sum += amount;
-                        } else {
-                            credit_sum += amount;
-                        }
-                        let mut entry = Html::div_with_class("entry");
-                        entry.push_attribute(
-                            "id",
-                            format!("gl-{}-{}", account.n.unwrap(), doc).as_str(),
-                        );
-                        entry.push_child(
-                            Html::div_with_class("doc").with_child(
-                                Html::new("a")
-                                    .with_attribute("href", format!("#d-{}", doc.clone()).as_str())
-                                    .with_string(doc),
-                            ),
-                        );
-                        entry.push_child_div_with_class_and_text("date", date);
-                        entry.push_child_div_with_class_and_text("description", desc);
-                        entry.push_child_div_with_class_and_text(
-                            "debit amount",
-                            Self::debit(amount),
-                        );
-                        entry.push_child_div_with_class_and_text(
-                            "credit amount",
-                            Self::credit(amount),
-                        );
-                        entry.push_child_div_with_class_and_text(
-                            "saldo amount",
-                            Self::amount_as_string(sum_multiplyer * (debit_sum + credit_sum), true),
+            if let Some(account) = self.get_account(*account_n)
+                && account
+                    .transactions
+                    .iter()
+                    .any(|t| self.is_shown(&self.ledger[t.n as usize]))
+            {
+                let mut account_elem = Html::div_with_class("account");
+                account_elem.push_attribute("id", format!("gl-{}", account.n.unwrap()).as_str());
+                let mut header = Html::div_with_class("header");
+                header.push_child(
+                    Html::div_with_class("account-info")
+                        .with_child(
+                            Html::new("a")
+                                .with_class("n")
+                                .with_attribute("href", format!("#a-{account_n}").as_str())
+                                .with_string(format!("{account_n}")),
+                        )
+                        .with_child(Html::div_with_class_and_text("name", account.name)),
+                );
+                account_elem.push_child(header);
+                let mut entries = Html::div_with_class("entries");
+                let mut debit_sum = Money::ZERO;
+                let mut credit_sum = Money::ZERO;
+                let mut foreign_sums: BTreeMap<String, Money> = BTreeMap::new();
+                let sum_multiplyer = if account.t == AccountType::Assets {
+                    1
+                } else {
+                    -1
+                };
+                for transaction in account.transactions {
+                    if !self.is_shown(&self.ledger[transaction.n as usize]) {
+                        continue;
+                    }
+                    let date = self.fmt_date(transaction.date);
+                    let doc = transaction.doc;
+                    let mut desc = transaction.description;
+                    let amount = transaction.amount;
+                    if let Some(foreign) = transaction.foreign {
+                        desc = format!(
+                            "{desc} ({} {})",
+                            self.amount_as_string(foreign.amount, true),
+                            foreign.currency
                         );
-                        entries.push_child(entry);
+                        *foreign_sums.entry(foreign.currency).or_default() += foreign.amount;
                     }
-                    let mut sums = Html::div_with_class("sums");
-                    sums.push_child_div_with_class_and_text(
-                        "debit amount",
-                        Self::amount_as_string(debit_sum, true),
+                    if amount.is_positive() {
+                        debit_sum += amount;
+                    } else {
+                        credit_sum += amount;
+                    }
+                    let mut entry = Html::div_with_class(if transaction.cleared {
+                        "entry cleared"
+                    } else {
+                        "entry"
+                    });
+                    entry.push_attribute(
+                        "id",
+                        format!("gl-{}-{}", account.n.unwrap(), doc).as_str(),
                     );
-                    sums.push_child_div_with_class_and_text(
-                        "credit amount",
-                        Self::amount_as_string(-credit_sum, true),
+                    entry.push_child(
+                        Html::div_with_class("doc").with_child(
+                            Html::new("a")
+                                .with_attribute("href", format!("#d-{}", doc.clone()).as_str())
+                                .with_string(doc),
+                        ),
                     );
-                    sums.push_child_div_with_class_and_text(
-                        "sum amount",
-                        Self::amount_as_string(sum_multiplyer * (debit_sum + credit_sum), true),
+                    entry.push_child_div_with_class_and_text("date", date);
+                    entry.push_child_div_with_class_and_text("description", desc);
+                    entry.push_child_div_with_class_and_text("debit amount", self.debit(amount));
+                    entry.push_child_div_with_class_and_text("credit amount", self.credit(amount));
+                    entry.push_child_div_with_class_and_text(
+                        "saldo amount",
+                        self.amount_as_string((debit_sum + credit_sum) * sum_multiplyer, true),
                     );
-                    account_elem.push_child(entries);
-                    account_elem.push_child(sums);
-                    general_ledger.push_child(account_elem);
+                    entries.push_child(entry);
                 }
+                let mut sums = Html::div_with_class("sums");
+                sums.push_child_div_with_class_and_text(
+                    "debit amount",
+                    self.amount_as_string(debit_sum, true),
+                );
+                sums.push_child_div_with_class_and_text(
+                    "credit amount",
+                    self.amount_as_string(-credit_sum, true),
+                );
+                sums.push_child_div_with_class_and_text(
+                    "sum amount",
+                    self.amount_as_string((debit_sum + credit_sum) * sum_multiplyer, true),
+                );
+                account_elem.push_child(entries);
+                account_elem.push_child(sums);
+                for (currency, sum) in foreign_sums {
+                    account_elem.push_child(
+                        Html::div_with_class("sums foreign")
+                            .with_child(Html::div_with_class("debit amount"))
+                            .with_child(Html::div_with_class_and_text("credit currency", currency))
+                            .with_child(Html::div_with_class_and_text(
+                                "sum amount",
+                                self.amount_as_string(sum * sum_multiplyer, true),
+                            )),
+                    );
+                }
+                general_ledger.push_child(account_elem);
             }
         }
         general_ledger
@@ -332,7 +751,7 @@ impl Ledger {
             let mut fy_container_elem = Html::div_with_class("fy");
             fy_container_elem.push_child(Html::new("div"));
             let title = if self.ledger_type == LedgerType::Budgeting
-                && self.options[0].get("lyhenne").is_some()
+                && self.options[0].contains_key("lyhenne")
             {
                 self.options[0]
                     .get("lyhenne")
@@ -389,7 +808,8 @@ impl Ledger {
             account_elem.push_attribute("class", "leaf");
         }
         if account.transactions.is_empty()
-            && (account.debits.iter().all(|a| *a == 0) && account.credits.iter().all(|a| *a == 0))
+            && (account.debits.iter().all(|a| a.is_zero())
+                && account.credits.iter().all(|a| a.is_zero()))
         {
             account_elem.push_attribute("class", "empty");
         }
@@ -428,25 +848,7 @@ impl Ledger {
             ));
         }
         if account.t == AccountType::LiabilitiesTopLevel {
-            let accs = self.accounts();
-            let mut profit_account =
-                Account::naked(None, "Tilikauden tulos".into(), AccountType::Liabilities);
-            profit_account.credits = vec![0; self.l_index + 1];
-            profit_account.debits = vec![0; self.l_index + 1];
-            profit_account.rec_credits = vec![0; self.l_index + 1];
-            profit_account.rec_debits = vec![0; self.l_index + 1];
-            accs.iter()
-                .filter(|a| a.t == AccountType::None)
-                .for_each(|x| {
-                    profit_account.debits =
-                        zip(profit_account.debits.clone(), x.rec_debits.clone())
-                            .map(|(a, b)| a + b)
-                            .collect_vec();
-                    profit_account.credits =
-                        zip(profit_account.credits.clone(), x.rec_credits.clone())
-                            .map(|(a, b)| a + b)
-                            .collect_vec();
-                });
+            let profit_account = self.result_account();
             account.rec_debits = zip(account.rec_debits.clone(), profit_account.debits.clone())
                 .map(|(a, b)| a + b)
                 .collect_vec();
@@ -469,11 +871,7 @@ impl Ledger {
                             .with_class("name")
                             .with_string(account_name),
                     )
-                    .with_child(
-                        Html::new("span")
-                            .with_class("yht")
-                            .with_text("yhteensä".into()),
-                    ),
+                    .with_child(Html::new("span").with_class("yht").with_text("yhteensä")),
             );
             // footer.push_child_div_with_class_and_text(
             //     "account-info",
@@ -504,78 +902,73 @@ impl Ledger {
                 // println!("{} {:?} {:?}", account.name, account.credits, account.debits);
                 elems.push(Html::div_with_class_and_text(
                     "debit amount",
-                    Self::debit(account.debits[i]),
+                    self.debit(account.debits[i]),
                 ));
                 elems.push(Html::div_with_class_and_text(
                     "credit amount",
-                    Self::debit(account.credits[i]),
+                    self.debit(account.credits[i]),
                 ));
                 elems.push(Html::div_with_class_and_text(
                     "sum amount",
-                    Self::amount_as_string(
+                    self.amount_as_string(
                         account.credits[i] - account.debits[i],
-                        account.credits[i] != 0 || account.debits[i] != 0,
+                        !account.credits[i].is_zero() || !account.debits[i].is_zero(),
                     ),
                 ));
             } else {
-                match self.comp_ledger_types[i] {
-                    LedgerType::Main => {
-                        let sum = if account.t == AccountType::Assets {
-                            account.debits[i] - account.credits[i]
-                        } else {
-                            account.credits[i] - account.debits[i]
-                        };
-                        elems.push(Html::div_with_class_and_text(
-                            "sum amount",
-                            Self::amount_as_string(sum, account.is_leaf()),
-                        ));
-                    }
-                    _ => {}
+                if self.comp_ledger_types[i] == LedgerType::Main {
+                    let sum = if account.t == AccountType::Assets {
+                        account.debits[i] - account.credits[i]
+                    } else {
+                        account.credits[i] - account.debits[i]
+                    };
+                    elems.push(Html::div_with_class_and_text(
+                        "sum amount",
+                        self.amount_as_string(sum, account.is_leaf()),
+                    ));
                 }
             }
         }
 
-        if include_budgeting_cells {
-            if account.t == AccountType::None {
-                if account.n.is_some() {
-                    let (debit, credit, sum) = {
-                        if self.ledger_type == LedgerType::Budgeting {
-                            (
-                                Self::debit(account.debits[0]),
-                                Self::debit(account.credits[0]),
-                                Self::amount_as_string(
-                                    account.credits[0] - account.debits[0],
-                                    account.credits[0] != 0 || account.debits[0] != 0,
-                                ),
-                            )
-                        } else {
-                            ("".to_string(), "".to_string(), "".to_string())
-                        }
-                    };
-                    elems.push(
-                        Html::div_with_class("debit amount budget").with_child(
-                            Html::new_void("input")
-                                .with_attribute("type", "text")
-                                .with_attribute("autocomplete", "off")
-                                .with_attribute("value", &debit),
-                        ),
-                    );
-                    elems.push(
-                        Html::div_with_class("credit amount budget").with_child(
-                            Html::new_void("input")
-                                .with_attribute("type", "text")
-                                .with_attribute("autocomplete", "off")
-                                .with_attribute("value", &credit),
-                        ),
-                    );
-                    elems.push(
-                        Html::div_with_class("sum amount budget").with_string(sum), // .with_child(Html::new_void("input").with_attribute("type", "text")),
-                    );
-                } else {
-                    elems.push(Html::div_with_class("debit"));
-                    elems.push(Html::div_with_class("credit"));
-                    elems.push(Html::div_with_class("sum"));
-                }
+        if include_budgeting_cells && account.t == AccountType::None {
+            if account.n.is_some() {
+                let (debit, credit, sum) = {
+                    if self.ledger_type == LedgerType::Budgeting {
+                        (
+                            self.debit(account.debits[0]),
+                            self.debit(account.credits[0]),
+                            self.amount_as_string(
+                                account.credits[0] - account.debits[0],
+                                !account.credits[0].is_zero() || !account.debits[0].is_zero(),
+                            ),
+                        )
+                    } else {
+                        ("".to_string(), "".to_string(), "".to_string())
+                    }
+                };
+                elems.push(
+                    Html::div_with_class("debit amount budget").with_child(
+                        Html::new_void("input")
+                            .with_attribute("type", "text")
+                            .with_attribute("autocomplete", "off")
+                            .with_attribute("value", &debit),
+                    ),
+                );
+                elems.push(
+                    Html::div_with_class("credit amount budget").with_child(
+                        Html::new_void("input")
+                            .with_attribute("type", "text")
+                            .with_attribute("autocomplete", "off")
+                            .with_attribute("value", &credit),
+                    ),
+                );
+                elems.push(
+                    Html::div_with_class("sum amount budget").with_string(sum), // .with_child(Html::new_void("input").with_attribute("type", "text")),
+                );
+            } else {
+                elems.push(Html::div_with_class("debit"));
+                elems.push(Html::div_with_class("credit"));
+                elems.push(Html::div_with_class("sum"));
             }
         }
         elems
@@ -602,63 +995,83 @@ impl Ledger {
                 };
                 elems.push(Html::div_with_class_and_text(
                     dc,
-                    Self::debit(account.rec_debits[i]),
+                    self.debit(account.rec_debits[i]),
                 ));
                 elems.push(Html::div_with_class_and_text(
                     cc,
-                    Self::debit(account.rec_credits[i]),
+                    self.debit(account.rec_credits[i]),
                 ));
                 elems.push(Html::div_with_class_and_text(
                     sc,
-                    Self::amount_as_string(
+                    self.amount_as_string(
                         account.rec_credits[i] - account.rec_debits[i],
-                        account.rec_credits[i] != 0 || account.rec_debits[i] != 0,
+                        !account.rec_credits[i].is_zero() || !account.rec_debits[i].is_zero(),
                     ),
                 ));
             } else {
-                match self.comp_ledger_types[i] {
-                    LedgerType::Main => {
-                        let sum = if account.t == AccountType::Assets {
-                            account.rec_debits[i] - account.rec_credits[i]
-                        } else {
-                            account.rec_credits[i] - account.rec_debits[i]
-                        };
-                        elems.push(Html::div_with_class_and_text(
-                            "sum amount",
-                            format!("{}", Self::amount_as_string(sum, true)),
-                        ));
-                    }
-                    _ => {}
+                if self.comp_ledger_types[i] == LedgerType::Main {
+                    let sum = if account.t == AccountType::Assets {
+                        account.rec_debits[i] - account.rec_credits[i]
+                    } else {
+                        account.rec_credits[i] - account.rec_debits[i]
+                    };
+                    elems.push(Html::div_with_class_and_text(
+                        "sum amount",
+                        self.amount_as_string(sum, true).to_string(),
+                    ));
                 }
             }
         }
-        if include_budgeting_cells && self.ledger_type != LedgerType::Budgeting {
-            if account.t == AccountType::None {
-                elems.push(Html::div_with_class("debit amount budget"));
-                elems.push(Html::div_with_class("credit amount budget"));
-                elems.push(Html::div_with_class("sum amount budget"));
-            }
+        if include_budgeting_cells
+            && self.ledger_type != LedgerType::Budgeting
+            && account.t == AccountType::None
+        {
+            elems.push(Html::div_with_class("debit amount budget"));
+            elems.push(Html::div_with_class("credit amount budget"));
+            elems.push(Html::div_with_class("sum amount budget"));
         }
         elems
     }
     fn html_transaction(&self, transaction: Transaction) -> Html {
-        let date = transaction.clone().fmt_date();
+        let date = self.fmt_date(transaction.date);
         let desc = transaction.clone().description;
         let doc = transaction.clone().doc;
         let mut elem = Html::new("div")
-            .with_class("transaction")
+            .with_class(if transaction.recurring {
+                "transaction recurring"
+            } else {
+                "transaction"
+            })
             .with_attribute("id", format!("d-{}", doc).as_str());
         let mut heading = Html::div_with_class("header");
         heading.push_child_div_with_class_and_text("doc", doc.clone());
-        heading.push_child_div_with_class_and_text("date", date);
-        heading.push_child_div_with_class_and_text("description", desc);
+        let mut date = Html::div_with_class_and_text("date", date);
+        if transaction.recurring {
+            date.push_attribute("title", "Toistuva tapahtuma");
+        }
+        heading.push_child(date);
+        let mut description = Html::div_with_class_and_text("description", desc);
+        for path in self.attachments(&doc) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            description.push_child(
+                Html::new("a")
+                    .with_class("attachment")
+                    .with_attribute("href", &file_url(&path))
+                    .with_string(name.into_owned()),
+            );
+        }
+        heading.push_child(description);
+        if let Some(title) = metadata_title(&transaction.metadata) {
+            heading.push_attribute("class", "has-metadata");
+            heading.push_attribute("title", &title);
+        }
         elem.push_child(heading);
 
         let mut entries = Html::div_with_class("entries");
         for entry in transaction.entries {
             let mut entry_elem = Html::div_with_class("entry");
             let mut account_info = Html::div_with_class("account-info");
-            let account_n = entry.0;
+            let account_n = entry.account;
             let doc = doc.clone();
             account_info.push_child(
                 Html::div_with_class("account-n").with_child(
@@ -668,7 +1081,7 @@ impl Ledger {
                 ),
             );
 
-            let account_name = if let Some(account) = self.get_account(entry.0) {
+            let account_name = if let Some(account) = self.get_account(entry.account) {
                 account.name
             } else {
                 entry_elem.push_attribute("class", "invalid-account");
@@ -676,33 +1089,36 @@ impl Ledger {
             };
             account_info.push_child_div_with_class_and_text("account-name", account_name);
             entry_elem.push_child(account_info);
-            entry_elem.push_child_div_with_class_and_text("debit amount", Self::debit(entry.1));
-            entry_elem.push_child_div_with_class_and_text("credit amount", Self::credit(entry.1));
+            entry_elem.push_child_div_with_class_and_text("debit amount", self.debit(entry.amount));
+            entry_elem
+                .push_child_div_with_class_and_text("credit amount", self.credit(entry.amount));
+            if let Some(title) = metadata_title(&entry.metadata) {
+                entry_elem.push_attribute("class", "has-metadata");
+                entry_elem.push_attribute("title", &title);
+            }
             entries.push_child(entry_elem);
         }
         elem.push_child(entries);
         elem
     }
 
-    fn amount_as_string(amount: i32, render_zero: bool) -> String {
-        if !render_zero && amount == 0 {
+    pub(crate) fn amount_as_string(&self, amount: Money, render_zero: bool) -> String {
+        if !render_zero && amount.is_zero() {
             return "".to_string();
         }
-        format!("{:.2}", (amount as f64) / 100.0)
-            .replacen('.', ",", 1)
-            .replacen('-', "\u{2212}", 1)
+        amount.to_fi_string(self.precision(0))
     }
 
-    fn debit(amount: i32) -> String {
-        if amount > 0 {
-            Ledger::amount_as_string(amount, false)
+    fn debit(&self, amount: Money) -> String {
+        if amount.is_positive() {
+            self.amount_as_string(amount, false)
         } else {
             "".into()
         }
     }
-    fn credit(amount: i32) -> String {
-        if amount < 0 {
-            Ledger::amount_as_string(-amount, false)
+    fn credit(&self, amount: Money) -> String {
+        if amount.is_negative() {
+            self.amount_as_string(-amount, false)
         } else {
             "".into()
         }
@@ -751,3 +1167,45 @@ impl Ledger {
         head
     }
 }
+
+/// Ageing buckets of open items: the least number of days overdue and the
+/// label.
+const AGEING: [(i64, &str); 5] = [
+    (i64::MIN, "Erääntymättä"),
+    (1, "1–30 pv"),
+    (31, "31–60 pv"),
+    (61, "61–90 pv"),
+    (91, "Yli 90 pv"),
+];
+
+/// A `file:` URL of an absolute path. Bytes other than letters, digits,
+/// `-._~`, `/` and `:` are percent-encoded.
+fn file_url(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut url = String::from("file://");
+    if !path.starts_with('/') {
+        url.push('/');
+    }
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/:".contains(&b) {
+            url.push(b as char);
+        } else {
+            url += &format!("%{b:02X}");
+        }
+    }
+    url
+}
+
+/// The dimensions, tags and metadata lines shown when hovering over a
+/// transaction or an entry.
+fn metadata_title(metadata: &Metadata) -> Option<String> {
+    let lines = metadata
+        .dimensions
+        .iter()
+        .map(|(k, v)| format!("#{k}={v}"))
+        .chain(metadata.tags.iter().map(|t| format!("#{t}")))
+        .chain(metadata.values.iter().map(|(k, v)| format!("{k}: {v}")))
+        .collect_vec();
+    (!lines.is_empty())
+        .then(|| html_escape::encode_double_quoted_attribute(&lines.join("\n")).to_string())
+}
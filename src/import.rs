@@ -0,0 +1,654 @@
+use std::collections::{HashMap, HashSet};
+
+use regex::{Regex, RegexBuilder};
+use time::{Date, Month};
+
+use crate::{
+    diagnostic::{Diagnostic, Span},
+    format,
+    ledger::Ledger,
+    lexer::Token,
+    money::Money,
+    parser::{Node, Parser},
+    reference,
+    semantic::REFERENCE,
+};
+
+/// Metadata key holding the bank's archive id of an imported transaction.
+pub const ARCHIVE_ID: &str = "arkistointitunnus";
+
+/// A transaction read from a bank statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BankTransaction {
+    pub date: Date,
+    /// Positive for money coming in.
+    pub amount: Money,
+    pub payee: String,
+    pub reference: String,
+    pub message: String,
+    pub archive_id: String,
+}
+
+/// The text fields of a bank transaction that rules can match.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Payee,
+    Reference,
+    Message,
+}
+
+impl Field {
+    fn parse(s: &str) -> Option<Field> {
+        match s.to_lowercase().as_str() {
+            "saaja" => Some(Field::Payee),
+            "viite" => Some(Field::Reference),
+            "viesti" => Some(Field::Message),
+            _ => None,
+        }
+    }
+}
+
+impl BankTransaction {
+    fn field(&self, field: Field) -> &str {
+        match field {
+            Field::Payee => &self.payee,
+            Field::Reference => &self.reference,
+            Field::Message => &self.message,
+        }
+    }
+}
+
+/// `saaja "K-Market": 4000 "Tarvikkeet {viesti}"`: transactions whose
+/// fields match all the patterns are booked against `account`.
+#[derive(Debug)]
+struct Rule {
+    conditions: Vec<(Field, Regex)>,
+    account: String,
+    description: Option<String>,
+}
+
+impl Rule {
+    fn matches(&self, t: &BankTransaction) -> bool {
+        self.conditions
+            .iter()
+            .all(|(f, re)| re.is_match(t.field(*f)))
+    }
+
+    fn description(&self, t: &BankTransaction) -> String {
+        let description = match &self.description {
+            Some(template) => template
+                .replace("{saaja}", &t.payee)
+                .replace("{viite}", &t.reference)
+                .replace("{viesti}", &t.message),
+            None => default_description(t).to_string(),
+        };
+        description.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+fn default_description(t: &BankTransaction) -> &str {
+    if t.payee.trim().is_empty() {
+        &t.message
+    } else {
+        &t.payee
+    }
+}
+
+/// A column of the statement, by its header or 1-based position.
+#[derive(Debug, Clone, PartialEq)]
+enum Column {
+    Name(String),
+    Index(usize),
+}
+
+/// The columns of a CSV statement. Each has a list of header names
+/// tried in order, covering the exports of the common Finnish banks.
+const COLUMNS: [(&str, &[&str]); 6] = [
+    ("päivämäärä", &["Kirjauspäivä", "Päivämäärä"]),
+    ("summa", &["Määrä EUROA", "Määrä", "Summa"]),
+    ("saaja", &["Saaja/Maksaja", "Nimi", "Saaja"]),
+    ("viite", &["Viite", "Viitenumero"]),
+    ("viesti", &["Viesti", "Otsikko"]),
+    (ARCHIVE_ID, &["Arkistointitunnus", "Arkistotunnus"]),
+];
+
+/// Settings and rules for importing bank statements, read from a rules
+/// file with a `§ TIEDOT` and a `§ SÄÄNNÖT` section.
+#[derive(Debug)]
+pub struct Rules {
+    bank_account: String,
+    default_account: Option<String>,
+    delimiter: u8,
+    columns: HashMap<String, Column>,
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn from_source(source: &str, file: usize) -> Result<Rules, Vec<Diagnostic>> {
+        let mut parser = Parser::with_file(source, file);
+        let tree = parser.parse();
+        let mut errors = parser.errors;
+        let mut rules = Rules {
+            bank_account: String::new(),
+            default_account: None,
+            delimiter: b';',
+            columns: HashMap::new(),
+            rules: Vec::new(),
+        };
+        let mut in_rules = false;
+        for node in tree {
+            let result = match (&node, in_rules) {
+                (Node::List(tokens, _), _) if matches!(tokens[..], [Token::Section, _]) => {
+                    match &tokens[1] {
+                        Token::Identifier(name) if name.to_uppercase() == "SÄÄNNÖT" => {
+                            in_rules = true;
+                            Ok(())
+                        }
+                        Token::Identifier(name) if name.to_uppercase() == "TIEDOT" => {
+                            in_rules = false;
+                            Ok(())
+                        }
+                        _ => Err(Diagnostic::new(
+                            node.span(),
+                            "odotettiin osiota TIEDOT tai SÄÄNNÖT",
+                        )),
+                    }
+                }
+                (Node::List(tokens, span), false) => rules.option(tokens, *span),
+                (Node::Block(header, body, span), true) => rules.rule(header, body, *span),
+                _ => Err(Diagnostic::new(node.span(), "tunnistamaton rivi")),
+            };
+            if let Err(e) = result {
+                errors.push(e);
+            }
+        }
+        if rules.bank_account.is_empty() {
+            errors.push(Diagnostic::without_span(
+                "pankkitiliä ei ole asetettu; aseta pankkitili",
+            ));
+        }
+        if errors.is_empty() {
+            Ok(rules)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn option(&mut self, tokens: &[Token], span: Span) -> Result<(), Diagnostic> {
+        let [Token::Identifier(key), Token::Assign, value] = tokens else {
+            return Err(Diagnostic::new(span, "tunnistamaton rivi"));
+        };
+        let key = key.to_lowercase();
+        match (key.as_str(), value) {
+            ("pankkitili" | "oletustili", _) => {
+                let account =
+                    account(value).ok_or_else(|| Diagnostic::new(span, "odotettiin tiliä"))?;
+                if key == "pankkitili" {
+                    self.bank_account = account;
+                } else {
+                    self.default_account = Some(account);
+                }
+            }
+            ("erotin", Token::String(s)) if s.len() == 1 => self.delimiter = s.as_bytes()[0],
+            ("erotin", _) => {
+                return Err(Diagnostic::new(span, "erottimen pitää olla yksi merkki"));
+            }
+            (key, Token::String(name)) if COLUMNS.iter().any(|(k, _)| *k == key) => {
+                self.columns.insert(key.into(), Column::Name(name.clone()));
+            }
+            (key, Token::Number(n)) if COLUMNS.iter().any(|(k, _)| *k == key) => {
+                let index = n
+                    .as_integer()
+                    .and_then(|n| usize::try_from(n).ok())
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| Diagnostic::new(span, "virheellinen sarakkeen numero"))?;
+                self.columns.insert(key.into(), Column::Index(index));
+            }
+            _ => return Err(Diagnostic::new(span, format!("tuntematon asetus '{key}'"))),
+        }
+        Ok(())
+    }
+
+    fn rule(&mut self, header: &[Token], body: &[Node], span: Span) -> Result<(), Diagnostic> {
+        let mut conditions = Vec::new();
+        for pair in header.chunks(2) {
+            let [Token::Identifier(field), Token::String(pattern)] = pair else {
+                return Err(Diagnostic::new(
+                    span,
+                    "odotettiin kenttää ja hakulauseketta",
+                ));
+            };
+            let field = Field::parse(field).ok_or_else(|| {
+                Diagnostic::new(
+                    span,
+                    format!("tuntematon kenttä '{field}'; odotettiin saaja, viite tai viesti"),
+                )
+            })?;
+            let re = RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| Diagnostic::new(span, format!("virheellinen hakulauseke: {e}")))?;
+            conditions.push((field, re));
+        }
+        if conditions.is_empty() {
+            return Err(Diagnostic::new(
+                span,
+                "odotettiin kenttää ja hakulauseketta",
+            ));
+        }
+        let (account, description) = match body {
+            [Node::List(tokens, _)] => match &tokens[..] {
+                [a] => (account(a), None),
+                [a, Token::String(s)] => (account(a), Some(s.clone())),
+                _ => (None, None),
+            },
+            _ => (None, None),
+        };
+        let account = account.ok_or_else(|| Diagnostic::new(span, "odotettiin tiliä"))?;
+        self.rules.push(Rule {
+            conditions,
+            account,
+            description,
+        });
+        Ok(())
+    }
+
+    fn matching(&self, t: &BankTransaction) -> Option<&Rule> {
+        self.rules.iter().find(|r| r.matches(t))
+    }
+
+    /// Reads the transactions of a CSV statement. The text may be UTF-8
+    /// or Latin-1.
+    pub fn read_csv(&self, bytes: &[u8]) -> Result<Statement, Diagnostic> {
+        let text = match std::str::from_utf8(bytes) {
+            Ok(text) => text.to_string(),
+            Err(_) => bytes.iter().map(|b| *b as char).collect(),
+        };
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .flexible(true)
+            .from_reader(text.trim_start_matches('\u{feff}').as_bytes());
+        let error =
+            |e: csv::Error| Diagnostic::without_span(format!("tiliotetta ei voi lukea: {e}"));
+        let headers = reader.headers().map_err(error)?.clone();
+        let mut indices = HashMap::new();
+        for (key, names) in COLUMNS {
+            let index = match self.columns.get(key) {
+                Some(Column::Index(i)) => Some(i - 1),
+                Some(Column::Name(name)) => {
+                    Some(column_index(&headers, &[name]).ok_or_else(|| {
+                        Diagnostic::without_span(format!("tiliotteessa ei ole saraketta '{name}'"))
+                    })?)
+                }
+                None => column_index(&headers, names),
+            };
+            if let Some(index) = index {
+                indices.insert(key, index);
+            } else if matches!(key, "päivämäärä" | "summa") {
+                return Err(Diagnostic::without_span(format!(
+                    "tiliotteesta ei löydy saraketta '{key}'; aseta {key}"
+                )));
+            }
+        }
+        let mut transactions = Vec::new();
+        for (i, record) in reader.records().enumerate() {
+            let record = record.map_err(error)?;
+            if record.iter().all(|f| f.trim().is_empty()) {
+                continue;
+            }
+            let line = i + 2;
+            let get = |key: &str| {
+                indices
+                    .get(key)
+                    .and_then(|i| record.get(*i))
+                    .unwrap_or("")
+                    .trim()
+                    .to_string()
+            };
+            let date = parse_date(&get("päivämäärä")).ok_or_else(|| {
+                Diagnostic::without_span(format!("tiliotteen rivillä {line} ei ole päivämäärää"))
+            })?;
+            let amount = parse_amount(&get("summa")).ok_or_else(|| {
+                Diagnostic::without_span(format!("tiliotteen rivillä {line} ei ole summaa"))
+            })?;
+            transactions.push(BankTransaction {
+                date,
+                amount,
+                payee: get("saaja"),
+                reference: get("viite"),
+                message: get("viesti"),
+                archive_id: get(ARCHIVE_ID),
+            });
+        }
+        Ok(Statement {
+            transactions,
+            balances: Vec::new(),
+        })
+    }
+}
+
+/// Reads an account number or alias.
+fn account(token: &Token) -> Option<String> {
+    match token {
+        Token::Number(n) => n.as_integer().map(|n| n.to_string()),
+        Token::Identifier(alias) => Some(alias.clone()),
+        _ => None,
+    }
+}
+
+fn column_index(headers: &csv::StringRecord, names: &[&str]) -> Option<usize> {
+    names.iter().find_map(|name| {
+        headers
+            .iter()
+            .position(|h| h.trim().to_lowercase() == name.to_lowercase())
+    })
+}
+
+/// Reads a date written as `1.3.2025`, `01.03.2025` or `2025-03-01`.
+pub(crate) fn parse_date(s: &str) -> Option<Date> {
+    let parts = s.split(['.', '-']).map(str::trim).collect::<Vec<_>>();
+    let (d, m, y) = match parts[..] {
+        [y, m, d] if s.contains('-') => (d, m, y),
+        [d, m, y] => (d, m, y),
+        _ => return None,
+    };
+    let month = Month::try_from(m.parse::<u8>().ok()?).ok()?;
+    Date::from_calendar_date(y.parse().ok()?, month, d.parse().ok()?).ok()
+}
+
+/// Reads an amount written with a decimal comma, like `-1 234,50` or
+/// `+12,5`. Dots are taken as thousand separators when there is a comma.
+pub(crate) fn parse_amount(s: &str) -> Option<Money> {
+    let mut s = s.replace([' ', '\u{a0}', '\u{202f}', '\''], "");
+    if s.contains(',') {
+        s = s.replace('.', "");
+    }
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(&s)),
+    };
+    let valid = !digits.is_empty()
+        && digits
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == ',' || c == '.')
+        && digits.matches([',', '.']).count() <= 1;
+    if !valid {
+        return None;
+    }
+    let n = Money::parse(digits).ok()?;
+    Some(if negative { -n } else { n })
+}
+
+/// A booked balance of the bank account given in a statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Balance {
+    pub date: Date,
+    pub amount: Money,
+    /// An opening balance is the balance at the start of `date`, a closing
+    /// balance at its end.
+    pub opening: bool,
+}
+
+/// A bank statement read from a file.
+#[derive(Debug, Default)]
+pub struct Statement {
+    pub transactions: Vec<BankTransaction>,
+    pub balances: Vec<Balance>,
+}
+
+/// The result of an import.
+pub struct Import {
+    /// The new transactions in Tampio syntax.
+    pub source: String,
+    /// Number of transactions already in the ledger.
+    pub duplicates: usize,
+    /// Transactions no rule matched, left out when there is no
+    /// `oletustili`.
+    pub unmatched: Vec<BankTransaction>,
+    /// Balances of the statement that differ from the ledger, with the
+    /// balance of the bank account in the ledger once the new transactions
+    /// are added.
+    pub mismatches: Vec<(Balance, Money)>,
+}
+
+/// Converts the transactions of `statement` into Tampio transactions
+/// against the bank account. Transactions already in `ledger` are skipped:
+/// those with the same archive id or, when the ledger entry has none, with
+/// the same date and amount on the bank account. Payments with the
+/// reference number of an open invoice in the sales or purchase ledger are
+/// booked against the account of the invoice, unless open invoices with the
+/// reference are on different accounts. The balances of the
+/// statement are checked against the ledger.
+pub fn import(
+    statement: &Statement,
+    rules: &Rules,
+    ledger: Option<&Ledger>,
+) -> Result<Import, Diagnostic> {
+    let mut archive_ids = HashSet::new();
+    let mut present: HashMap<(Date, Money), usize> = HashMap::new();
+    let mut bank_entries = Vec::new();
+    // Accounts of the open invoices by reference and whether they are paid
+    // with money coming in, or `None` if the invoices with the reference
+    // are on different accounts.
+    let mut open_invoices: HashMap<(String, bool), Option<i32>> = HashMap::new();
+    if let Some(ledger) = ledger {
+        let ledgers = [
+            (ledger.sales_ledger(), true),
+            (ledger.purchase_ledger(), false),
+        ];
+        for (items, incoming) in ledgers {
+            for invoice in items.iter().flat_map(|i| &i.invoices) {
+                if invoice.open.is_positive() {
+                    open_invoices
+                        .entry((invoice.reference.clone(), incoming))
+                        .and_modify(|a| {
+                            if *a != Some(invoice.account) {
+                                *a = None;
+                            }
+                        })
+                        .or_insert(Some(invoice.account));
+                }
+            }
+        }
+        let bank = rules
+            .bank_account
+            .parse()
+            .ok()
+            .or_else(|| ledger.alias(&rules.bank_account))
+            .ok_or_else(|| {
+                Diagnostic::without_span(format!("tuntematon tili '{}'", rules.bank_account))
+            })?;
+        for t in &ledger.ledger {
+            let entries = t.entries.iter().filter(|e| e.account == bank);
+            if entries.clone().next().is_none() {
+                continue;
+            }
+            let amount = entries.map(|e| e.amount).sum();
+            bank_entries.push((t.date, amount));
+            match t.metadata.values.get(ARCHIVE_ID) {
+                Some(id) => {
+                    archive_ids.insert(id.as_str());
+                }
+                None => *present.entry((t.date, amount)).or_default() += 1,
+            }
+        }
+    }
+    let mut out = String::new();
+    let mut duplicates = 0;
+    let mut unmatched = Vec::new();
+    let mut sorted = statement.transactions.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|t| t.date);
+    for t in sorted {
+        let duplicate = if !t.archive_id.is_empty() && archive_ids.contains(t.archive_id.as_str()) {
+            true
+        } else if let Some(count) = present.get_mut(&(t.date, t.amount)).filter(|c| **c > 0) {
+            *count -= 1;
+            true
+        } else {
+            false
+        };
+        if duplicate {
+            duplicates += 1;
+            continue;
+        }
+        bank_entries.push((t.date, t.amount));
+        let invoice = open_invoices
+            .get(&(reference::normalise(&t.reference), t.amount.is_positive()))
+            .filter(|_| !t.reference.is_empty());
+        // A payment that may be for invoices on different accounts is left
+        // to the rules and marked for checking.
+        let ambiguous = matches!(invoice, Some(None));
+        let invoice = invoice
+            .copied()
+            .flatten()
+            .map(|account| account.to_string());
+        let (account, description, tag) = match (&invoice, rules.matching(t)) {
+            (Some(account), _) => (account, default_description(t).to_string(), ""),
+            (None, Some(rule)) if ambiguous => {
+                (&rule.account, rule.description(t), " #tarkistettava")
+            }
+            (None, Some(rule)) => (&rule.account, rule.description(t), ""),
+            (None, None) => match &rules.default_account {
+                Some(account) => (
+                    account,
+                    default_description(t).to_string(),
+                    " #tarkistettava",
+                ),
+                None => {
+                    unmatched.push(t.clone());
+                    continue;
+                }
+            },
+        };
+        out += &format!(
+            "\n{} {}{tag}\n",
+            format::date(t.date),
+            format::quote(&description)
+        );
+        if !t.archive_id.is_empty() {
+            out += &format!("  {ARCHIVE_ID}: {}\n", format::quote(&t.archive_id));
+        }
+        if invoice.is_some() {
+            out += &format!("  {REFERENCE}: {}\n", format::quote(&t.reference));
+        }
+        out += &format!("  {}: {}\n", rules.bank_account, format::amount(t.amount));
+        out += &format!("  {account}: {}\n", format::amount(-t.amount));
+    }
+    let mismatches = match ledger {
+        Some(_) => statement
+            .balances
+            .iter()
+            .filter_map(|b| {
+                let balance = bank_entries
+                    .iter()
+                    .filter(|(date, _)| *date < b.date || (!b.opening && *date == b.date))
+                    .map(|(_, amount)| *amount)
+                    .sum();
+                (balance != b.amount).then(|| (b.clone(), balance))
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    Ok(Import {
+        source: format::format_source(&out, 0).unwrap_or(out),
+        duplicates,
+        unmatched,
+        mismatches,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::tests::parse_valid;
+
+    #[test]
+    fn csv_import() {
+        let rules = Rules::from_source(
+            "§ TIEDOT\npankkitili = 1910\noletustili = 9999\n\n§ SÄÄNNÖT\n\
+             saaja \"k-market\": 4000 \"Tarvikkeet {viesti}\"\n\
+             viite \"^1001\" saaja \"Oy\": 3000\n",
+            0,
+        )
+        .unwrap();
+        let csv = "Kirjauspäivä;Määrä EUROA;Saaja/Maksaja;Viite;Viesti;Arkistointitunnus\n\
+                   02.03.2025;-12,50;K-MARKET KAMPPI;;maitoa;A1\n\
+                   03.03.2025;+1 200,00;Asiakas Oy;10016;;A2\n\
+                   04.03.2025;-5,00;Kioski;;;A3\n\
+                   05.03.2025;-7,00;Kioski;;;A4\n";
+        let mut statement = rules.read_csv(csv.as_bytes()).unwrap();
+        let transactions = &statement.transactions;
+        assert_eq!(transactions[1].amount, Money::parse("1200").unwrap());
+        assert_eq!(
+            transactions[0].date,
+            Date::from_calendar_date(2025, Month::March, 2).unwrap()
+        );
+
+        let ledger_source = "§ TILIKARTTA\n1910 \"Pankki\"\n4000 \"Tarvikkeet\"\n\n\
+                             § KIRJANPITO\n2.3.2025 \"Maito\"\n  arkistointitunnus: \"A1\"\n  \
+                             1910: -12,50\n  4000: 12,50\n\
+                             5.3.2025 \"Kahvi\"\n  1910: -7\n  4000: 7\n";
+        let ledger = parse_valid(ledger_source);
+
+        let date = |d| Date::from_calendar_date(2025, Month::March, d).unwrap();
+        statement.balances = vec![
+            Balance {
+                date: date(2),
+                amount: Money::ZERO,
+                opening: true,
+            },
+            Balance {
+                date: date(5),
+                amount: Money::parse("1175,5").unwrap(),
+                opening: false,
+            },
+        ];
+        let import = import(&statement, &rules, Some(&ledger)).unwrap();
+        assert_eq!(import.duplicates, 2);
+        assert!(import.mismatches.is_empty());
+        assert!(import.unmatched.is_empty());
+        assert_eq!(
+            import.source,
+            "3.3.2025 \"Asiakas Oy\"\n  arkistointitunnus: \"A2\"\n  1910:               1200,00\n  \
+             3000:              -1200,00\n\n4.3.2025 \"Kioski\" #tarkistettava\n  \
+             arkistointitunnus: \"A3\"\n  1910:              -5,00\n  9999:               5,00\n"
+        );
+    }
+
+    #[test]
+    fn invoice_payments() {
+        let rules =
+            Rules::from_source("§ TIEDOT\npankkitili = 1910\noletustili = 9999\n", 0).unwrap();
+        let csv = "Kirjauspäivä;Määrä EUROA;Saaja/Maksaja;Viite;Viesti;Arkistointitunnus\n\
+                   03.03.2025;100,00;Asiakas Oy;10016;;A1\n\
+                   04.03.2025;-50,00;Paperi Oy;10016;;A2\n";
+        let statement = rules.read_csv(csv.as_bytes()).unwrap();
+        // A sales and a purchase invoice with the same reference
+        let ledger_source = "§ TILIKARTTA\n+ \"Vastaavaa\"\n  1700 \"Myyntisaamiset\"\n  \
+                             1910 \"Pankki\"\n- \"Vastattavaa\"\n  2870 \"Ostovelat\"\n\
+                             3000 \"Myynti\"\n4000 \"Ostot\"\n\
+                             § TIEDOT\nmyyntisaamiset = 1700\nostovelat = 2870\n\
+                             § KIRJANPITO\n1.3.2025 \"Lasku\"\n  viite: \"10016\"\n  \
+                             1700: 100\n  3000: AUTO\n\
+                             1.3.2025 \"Paperia\"\n  viite: \"10016\"\n  4000: 50\n  2870: AUTO\n";
+        let ledger = parse_valid(ledger_source);
+        let import = import(&statement, &rules, Some(&ledger)).unwrap();
+        assert!(import.source.contains("  1700:"), "{}", import.source);
+        assert!(import.source.contains("  2870:"), "{}", import.source);
+    }
+
+    #[test]
+    fn amounts() {
+        assert_eq!(
+            parse_amount("-1.234,5"),
+            Some(-Money::parse("1234,5").unwrap())
+        );
+        assert_eq!(parse_amount("12.50"), Some(Money::parse("12,5").unwrap()));
+        assert_eq!(parse_amount("1,2,3"), None);
+        assert_eq!(parse_amount(""), None);
+        assert_eq!(
+            parse_date("2025-03-01"),
+            Date::from_calendar_date(2025, Month::March, 1).ok()
+        );
+    }
+}
@@ -0,0 +1,1011 @@
+//! Converting hledger, ledger-cli and beancount journals into Tampio files.
+//!
+//! Accounts get numbers from the first digit of their class: 1000 for
+//! assets, 2000 for liabilities and equity, 3000 for income, 4000 for
+//! expenses and 9000 for anything else, in steps of ten. An
+//! [`AccountMap`] overrides the generated numbers and names.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use itertools::Itertools;
+use time::{Date, Month};
+
+use crate::{
+    diagnostic::{Diagnostic, Span},
+    format,
+    lexer::Token,
+    money::{MAX_DECIMALS, Money},
+    parser::{Node, Parser},
+};
+
+/// An amount and its commodity, if one is written.
+#[derive(Debug, Clone, PartialEq)]
+struct Amount {
+    value: Money,
+    commodity: Option<String>,
+}
+
+/// The price of a posting in another commodity: `@` for one unit or `@@`
+/// for the whole amount.
+#[derive(Debug, Clone, PartialEq)]
+enum Price {
+    Unit(Amount),
+    Total(Amount),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Posting {
+    account: String,
+    /// `None` when the amount is left out to balance the transaction.
+    amount: Option<Amount>,
+    price: Option<Price>,
+    cleared: bool,
+    tags: Vec<(String, String)>,
+    comments: Vec<String>,
+    span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Transaction {
+    date: Date,
+    description: String,
+    code: Option<String>,
+    cleared: bool,
+    /// Tags `name:` without a value and `name: value` pairs.
+    tags: Vec<(String, String)>,
+    comments: Vec<String>,
+    postings: Vec<Posting>,
+    span: Span,
+}
+
+/// The class of an account, from its `type:` or the name of the top-level
+/// account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Class {
+    Assets,
+    Liabilities,
+    Income,
+    Expenses,
+    Other,
+}
+
+impl Class {
+    fn from_type(t: &str) -> Option<Class> {
+        match t.to_lowercase().as_str() {
+            "a" | "asset" | "c" | "cash" => Some(Class::Assets),
+            "l" | "liability" | "e" | "equity" => Some(Class::Liabilities),
+            "r" | "revenue" => Some(Class::Income),
+            "x" | "expense" => Some(Class::Expenses),
+            _ => None,
+        }
+    }
+
+    fn from_name(name: &str) -> Class {
+        match name.to_lowercase().as_str() {
+            "assets" | "asset" | "varat" | "vastaavaa" => Class::Assets,
+            "liabilities" | "liability" | "equity" | "velat" | "vastattavaa" => Class::Liabilities,
+            "income" | "revenue" | "revenues" | "tulot" | "tuotot" => Class::Income,
+            "expenses" | "expense" | "kulut" | "menot" => Class::Expenses,
+            _ => Class::Other,
+        }
+    }
+
+    fn first_number(self) -> i32 {
+        match self {
+            Class::Assets => 1000,
+            Class::Liabilities => 2000,
+            Class::Income => 3000,
+            Class::Expenses => 4000,
+            Class::Other => 9000,
+        }
+    }
+}
+
+/// The transactions, accounts and prices of a journal.
+#[derive(Debug, Default)]
+pub struct Journal {
+    transactions: Vec<Transaction>,
+    /// Accounts in the order they are declared or first used.
+    accounts: Vec<String>,
+    /// Accounts used in postings.
+    used: HashSet<String>,
+    /// Classes given with `type:` in `account` directives.
+    classes: HashMap<String, Class>,
+    /// `P` directives and beancount `price` entries.
+    prices: Vec<(Date, String, Amount)>,
+}
+
+/// Whether `s` can be written as a Tampio identifier, such as a document
+/// id or a metadata key.
+fn is_identifier(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_alphabetic())
+        && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && s != "AUTO"
+}
+
+fn is_tag(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-'))
+}
+
+/// Reads a date such as `2025-03-01`, `2025/3/1` or, with the year of a
+/// `Y` directive, `3/1`.
+fn date(s: &str, year: Option<i32>) -> Option<Date> {
+    let parts = s
+        .split(['-', '/', '.'])
+        .map(|p| p.parse::<i32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let (y, m, d) = match parts[..] {
+        [y, m, d] => (y, m, d),
+        [m, d] => (year?, m, d),
+        _ => return None,
+    };
+    let month = Month::try_from(u8::try_from(m).ok()?).ok()?;
+    Date::from_calendar_date(y, month, u8::try_from(d).ok()?).ok()
+}
+
+/// Reads an unsigned number. With both `.` and `,` the last one is the
+/// decimal mark and with only one of them, it is the decimal mark unless
+/// repeated. Decimals beyond what [`Money`] holds are rounded if `round`.
+fn number(s: &str, round: bool) -> Option<Money> {
+    let marks = s.match_indices(['.', ',']).collect::<Vec<_>>();
+    let (int, frac) = match marks.last() {
+        Some((i, mark)) if marks.iter().filter(|(_, m)| m == mark).count() == 1 => {
+            (s[..*i].replace(['.', ','], ""), &s[i + 1..])
+        }
+        _ => (s.replace(['.', ','], ""), ""),
+    };
+    if int.is_empty() && frac.is_empty()
+        || !int.chars().chain(frac.chars()).all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let decimals = MAX_DECIMALS as usize;
+    let value = Money::parse(&format!("{int}.{:.decimals$}", frac)).ok()?;
+    match frac.as_bytes().get(decimals) {
+        Some(_) if !round => None,
+        Some(b'5'..=b'9') => value.checked_add(Money::parse("0.0001").ok()?),
+        _ => Some(value),
+    }
+}
+
+/// Reads an amount such as `-12.50 EUR`, `EUR -12.50`, `$-12.50` or
+/// `10 "AAPL"`.
+fn amount(s: &str, round: bool) -> Option<Amount> {
+    let mut rest = s.trim();
+    let mut negative = false;
+    let mut value = None;
+    let mut commodity = None;
+    while let Some(c) = rest.chars().next() {
+        let len = match c {
+            '-' | '+' => {
+                negative ^= c == '-';
+                1
+            }
+            '0'..='9' | '.' | ',' if value.is_none() => {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == ','))
+                    .unwrap_or(rest.len());
+                value = Some(number(&rest[..len], round)?);
+                len
+            }
+            '"' if commodity.is_none() => {
+                let len = rest[1..].find('"')? + 2;
+                commodity = Some(rest[1..len - 1].to_string());
+                len
+            }
+            _ if commodity.is_none() => {
+                let len = rest
+                    .find(|c: char| c.is_whitespace() || c.is_ascii_digit() || "-+.,\"".contains(c))
+                    .unwrap_or(rest.len());
+                commodity = Some(match &rest[..len] {
+                    "€" => "EUR".to_string(),
+                    "$" => "USD".to_string(),
+                    "£" => "GBP".to_string(),
+                    c => c.to_string(),
+                });
+                len
+            }
+            _ => return None,
+        };
+        if len == 0 {
+            return None;
+        }
+        rest = rest[len..].trim_start();
+    }
+    let value = value?;
+    Some(Amount {
+        value: if negative { -value } else { value },
+        commodity,
+    })
+}
+
+/// Splits a comment into tags, `name:` or `name: value` up to the next
+/// comma, and the text before the first tag.
+fn comment_tags(comment: &str) -> (Vec<(String, String)>, String) {
+    let mut tags = Vec::new();
+    let mut text = String::new();
+    for piece in comment.split(',') {
+        let tag = piece.split_once(':').and_then(|(before, value)| {
+            let name = before.rsplit(char::is_whitespace).next()?;
+            (!name.is_empty()).then(|| (before[..before.len() - name.len()].trim(), name, value))
+        });
+        match tag {
+            Some((before, name, value)) => {
+                if tags.is_empty() {
+                    text = before.to_string();
+                }
+                tags.push((name.to_string(), value.trim().to_string()));
+            }
+            None if tags.is_empty() => {
+                text = [text.as_str(), piece].join(if text.is_empty() { "" } else { "," });
+            }
+            None => {}
+        }
+    }
+    (tags, text.trim().to_string())
+}
+
+impl Journal {
+    /// Reads a journal in hledger, ledger-cli or beancount syntax.
+    /// Directives other than accounts and prices are skipped, as are
+    /// periodic and automated transactions.
+    pub fn read(source: &str, file: usize) -> Result<Journal, Vec<Diagnostic>> {
+        let mut journal = Journal::default();
+        let mut errors = Vec::new();
+        let mut current: Option<(Transaction, bool)> = None;
+        let mut year = None;
+        for (i, line) in source.lines().enumerate() {
+            let span = Span::new(file, i + 1, 1);
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                journal.finish(current.take());
+                continue;
+            }
+            if line.starts_with(char::is_whitespace) {
+                if let Some((t, beancount)) = &mut current {
+                    let result = Journal::posting_line(t, trimmed, *beancount, span);
+                    match result {
+                        Ok(Some(posting)) => t.postings.push(posting),
+                        Ok(None) => {}
+                        Err(e) => errors.push(e),
+                    }
+                }
+                continue;
+            }
+            journal.finish(current.take());
+            let (word, rest) = trimmed
+                .split_once(char::is_whitespace)
+                .map_or((trimmed, ""), |(w, r)| (w, r.trim()));
+            let result = match word {
+                _ if word.starts_with(|c: char| c.is_ascii_digit()) => {
+                    let date_part = word.split('=').next().unwrap_or(word);
+                    match date(date_part, year) {
+                        Some(date) => journal.dated(date, rest, span).map(|t| current = t),
+                        None => Err(Diagnostic::new(span, "virheellinen päivämäärä")),
+                    }
+                }
+                "account" => {
+                    let (name, comment) = rest.split_once(';').unwrap_or((rest, ""));
+                    let name = name.trim();
+                    let (tags, _) = comment_tags(comment);
+                    let class = tags
+                        .iter()
+                        .find(|(name, _)| name == "type")
+                        .and_then(|(_, t)| Class::from_type(t));
+                    if let Some(class) = class {
+                        journal.classes.insert(name.to_string(), class);
+                    }
+                    journal.add_account(name);
+                    Ok(())
+                }
+                "P" => journal.price(rest, year, span),
+                "Y" | "year" => rest
+                    .parse()
+                    .map(|y| year = Some(y))
+                    .map_err(|_| Diagnostic::new(span, "virheellinen vuosi")),
+                "include" => Err(Diagnostic::new(
+                    span,
+                    "include-direktiiviä ei tueta; yhdistä tiedostot ensin",
+                )),
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                errors.push(e);
+            }
+        }
+        journal.finish(current);
+        if errors.is_empty() {
+            Ok(journal)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn add_account(&mut self, name: &str) {
+        if !self.accounts.iter().any(|a| a == name) {
+            self.accounts.push(name.to_string());
+        }
+    }
+
+    fn finish(&mut self, t: Option<(Transaction, bool)>) {
+        if let Some((t, _)) = t {
+            for p in &t.postings {
+                self.add_account(&p.account);
+                self.used.insert(p.account.clone());
+            }
+            self.transactions.push(t);
+        }
+    }
+
+    /// Reads `P 2025-03-01 USD 0.92 EUR`, without the `P`.
+    fn price(&mut self, s: &str, year: Option<i32>, span: Span) -> Result<(), Diagnostic> {
+        let error = || Diagnostic::new(span, "virheellinen hinta");
+        let mut words = s.split_whitespace();
+        let date = words.next().and_then(|d| date(d, year)).ok_or_else(error)?;
+        let mut commodity = words.next().ok_or_else(error)?;
+        // An optional time of day
+        if commodity.contains(':') {
+            commodity = words.next().ok_or_else(error)?;
+        }
+        let price = amount(&words.collect::<Vec<_>>().join(" "), true).ok_or_else(error)?;
+        self.prices.push((date, commodity.to_string(), price));
+        Ok(())
+    }
+
+    /// Reads a line starting with a date: a transaction header or a
+    /// beancount entry such as `open` or `price`. Returns the transaction
+    /// begun and whether it is in beancount syntax.
+    fn dated(
+        &mut self,
+        date: Date,
+        rest: &str,
+        span: Span,
+    ) -> Result<Option<(Transaction, bool)>, Diagnostic> {
+        let (word, tail) = rest
+            .split_once(char::is_whitespace)
+            .map_or((rest, ""), |(w, r)| (w, r.trim()));
+        match word {
+            "open" => {
+                let name = tail.split_whitespace().next().unwrap_or_default();
+                self.add_account(name);
+                return Ok(None);
+            }
+            "price" => {
+                self.price(&format!("{date} {tail}"), None, span)?;
+                return Ok(None);
+            }
+            "close" | "balance" | "pad" | "note" | "document" | "event" | "custom"
+            | "commodity" | "query" => return Ok(None),
+            _ => {}
+        }
+        let (cleared, mut rest) = match rest.chars().next() {
+            Some(c @ ('*' | '!')) => (c == '*', rest[1..].trim_start()),
+            _ => (false, rest),
+        };
+        if let Some(tail) = rest.strip_prefix("txn") {
+            rest = tail.trim_start();
+        }
+        let mut t = Transaction {
+            date,
+            description: String::new(),
+            code: None,
+            cleared,
+            tags: Vec::new(),
+            comments: Vec::new(),
+            postings: Vec::new(),
+            span,
+        };
+        if rest.starts_with('"') {
+            // Beancount: `"payee" "narration" #tag ^link`, where the flag
+            // doesn't tell if the transaction is reconciled
+            let mut strings = Vec::new();
+            while let Some(tail) = rest.strip_prefix('"') {
+                let end = tail
+                    .find('"')
+                    .ok_or_else(|| Diagnostic::new(span, "päättymätön merkkijono"))?;
+                strings.push(&tail[..end]);
+                rest = tail[end + 1..].trim_start();
+            }
+            t.description = strings.iter().filter(|s| !s.is_empty()).join(", ");
+            for word in rest.split_whitespace() {
+                if let Some(tag) = word.strip_prefix('#') {
+                    t.tags.push((tag.to_string(), String::new()));
+                }
+            }
+            t.cleared = false;
+            return Ok(Some((t, true)));
+        }
+        if let Some(tail) = rest.strip_prefix('(') {
+            let end = tail
+                .find(')')
+                .ok_or_else(|| Diagnostic::new(span, "odotettiin merkkiä ')'"))?;
+            t.code = Some(tail[..end].trim().to_string()).filter(|c| !c.is_empty());
+            rest = tail[end + 1..].trim_start();
+        }
+        let (description, comment) = rest.split_once(';').unwrap_or((rest, ""));
+        t.description = description.trim().to_string();
+        let (tags, text) = comment_tags(comment);
+        t.tags = tags;
+        t.comments.extend(Some(text).filter(|c| !c.is_empty()));
+        Ok(Some((t, false)))
+    }
+
+    /// Reads an indented line of a transaction: a posting, a comment or
+    /// beancount metadata. Comments and metadata after a posting belong to
+    /// it.
+    fn posting_line(
+        t: &mut Transaction,
+        line: &str,
+        beancount: bool,
+        span: Span,
+    ) -> Result<Option<Posting>, Diagnostic> {
+        let (tags, comments) = match t.postings.last_mut() {
+            Some(p) => (&mut p.tags, &mut p.comments),
+            None => (&mut t.tags, &mut t.comments),
+        };
+        if let Some(comment) = line.strip_prefix([';', '#']) {
+            let (mut new_tags, text) = comment_tags(comment);
+            tags.append(&mut new_tags);
+            comments.extend(Some(text).filter(|c| !c.is_empty()));
+            return Ok(None);
+        }
+        let metadata = line
+            .split_once(": ")
+            .filter(|(k, _)| k.starts_with(|c: char| c.is_ascii_lowercase()) && is_tag(k));
+        if let Some((key, value)) = metadata.filter(|_| beancount) {
+            tags.push((key.to_string(), value.trim().trim_matches('"').to_string()));
+            return Ok(None);
+        }
+        let (line, comment) = line.split_once(';').unwrap_or((line, ""));
+        let (cleared, line) = match line.chars().next() {
+            Some(c @ ('*' | '!')) => (c == '*', line[1..].trim_start()),
+            _ => (false, line),
+        };
+        let end = if beancount {
+            line.find(char::is_whitespace)
+        } else {
+            [line.find("  "), line.find('\t')]
+                .into_iter()
+                .flatten()
+                .min()
+        };
+        let (account, rest) = match end {
+            Some(end) => (line[..end].trim(), line[end..].trim()),
+            None => (line.trim(), ""),
+        };
+        let account = match account.strip_prefix('[').and_then(|a| a.strip_suffix(']')) {
+            Some(account) => account,
+            None if account.starts_with('(') => {
+                return Err(Diagnostic::new(
+                    span,
+                    "virtuaalisia vientejä ilman vastavientiä ei tueta",
+                ));
+            }
+            None => account,
+        };
+        let (rest, assertion) = rest.split_once('=').unwrap_or((rest, ""));
+        let rest = rest.trim();
+        if rest.is_empty() && !assertion.trim().is_empty() {
+            return Err(Diagnostic::new(span, "saldon asetusta ei tueta"));
+        }
+        if rest.contains('{') {
+            return Err(Diagnostic::new(span, "hankintahintoja ei tueta"));
+        }
+        let error = || Diagnostic::new(span, format!("virheellinen summa '{rest}'"));
+        let (value, price) = match rest.split_once('@') {
+            Some((value, price)) => match price.strip_prefix('@') {
+                Some(total) => (
+                    value,
+                    Some(Price::Total(amount(total, true).ok_or_else(error)?)),
+                ),
+                None => (
+                    value,
+                    Some(Price::Unit(amount(price, true).ok_or_else(error)?)),
+                ),
+            },
+            None => (rest, None),
+        };
+        let value = match value.trim() {
+            "" => None,
+            value => Some(amount(value, false).ok_or_else(error)?),
+        };
+        let (tags, text) = comment_tags(comment);
+        Ok(Some(Posting {
+            account: account.to_string(),
+            price: price.filter(|_| value.is_some()),
+            amount: value,
+            cleared,
+            tags,
+            comments: Some(text).into_iter().filter(|c| !c.is_empty()).collect(),
+            span,
+        }))
+    }
+
+    /// The most used commodity, or EUR if no amount has one.
+    fn base_currency(&self) -> String {
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        let commodities = self
+            .transactions
+            .iter()
+            .flat_map(|t| &t.postings)
+            .filter_map(|p| p.amount.as_ref()?.commodity.as_deref());
+        for c in commodities {
+            match counts.iter_mut().find(|(name, _)| *name == c) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((c, 1)),
+            }
+        }
+        // The first one seen wins a tie
+        counts
+            .iter()
+            .rev()
+            .max_by_key(|(_, n)| *n)
+            .map_or("EUR", |(c, _)| c)
+            .to_string()
+    }
+}
+
+/// Numbers and names for the accounts of a journal, by the full account
+/// name in the journal. Read from a mapping file with lines such as
+/// `"Assets:Bank" = 1910 "Pankkitili"`, or `"Assets" = "Vastaavaa"` for
+/// accounts shown as headings.
+#[derive(Debug, Default)]
+pub struct AccountMap {
+    accounts: BTreeMap<String, (Option<i32>, String)>,
+    /// Accounts not in the mapping file, given generated numbers.
+    pub added: Vec<String>,
+}
+
+impl AccountMap {
+    pub fn from_source(source: &str, file: usize) -> Result<AccountMap, Vec<Diagnostic>> {
+        let mut parser = Parser::with_file(source, file);
+        let tree = parser.parse();
+        let mut errors = parser.errors;
+        let mut map = AccountMap::default();
+        for node in tree {
+            let Node::List(tokens, span) = &node else {
+                errors.push(Diagnostic::new(node.span(), "tunnistamaton rivi"));
+                continue;
+            };
+            let (path, n, name) = match &tokens[..] {
+                [
+                    Token::String(path),
+                    Token::Assign,
+                    Token::Number(n),
+                    rest @ ..,
+                ] => (path, Some(n), rest),
+                [Token::String(path), Token::Assign, rest @ ..] => (path, None, rest),
+                _ => {
+                    errors.push(Diagnostic::new(
+                        *span,
+                        "odotettiin riviä muodossa '\"tili\" = numero \"nimi\"'",
+                    ));
+                    continue;
+                }
+            };
+            let n = match n.map(|n| n.as_integer().and_then(|n| i32::try_from(n).ok())) {
+                Some(None) => {
+                    errors.push(Diagnostic::new(*span, "virheellinen tilinumero"));
+                    continue;
+                }
+                Some(n) => n,
+                None => None,
+            };
+            let name = match name {
+                [Token::String(name)] => name.clone(),
+                [] if n.is_some() => path.rsplit(':').next().unwrap_or(path).to_string(),
+                _ => {
+                    errors.push(Diagnostic::new(*span, "odotettiin tilin nimeä"));
+                    continue;
+                }
+            };
+            map.accounts.insert(path.clone(), (n, name));
+        }
+        if errors.is_empty() {
+            Ok(map)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn line(path: &str, (n, name): &(Option<i32>, String)) -> String {
+        match n {
+            Some(n) => format!("{} = {n} {}\n", format::quote(path), format::quote(name)),
+            None => format!("{} = {}\n", format::quote(path), format::quote(name)),
+        }
+    }
+
+    /// The mapping in the syntax of the mapping file.
+    pub fn source(&self) -> String {
+        self.accounts
+            .iter()
+            .map(|(path, account)| AccountMap::line(path, account))
+            .collect()
+    }
+
+    /// The lines of the accounts in [`AccountMap::added`].
+    pub fn added_source(&self) -> String {
+        self.added
+            .iter()
+            .map(|path| AccountMap::line(path, &self.accounts[path]))
+            .collect()
+    }
+}
+
+/// A node of the account tree of a journal.
+struct TreeNode {
+    path: String,
+    children: Vec<usize>,
+}
+
+/// Writes `journal` as a Tampio file: the chart of accounts, the exchange
+/// rates and the transactions. Accounts missing from `map` are numbered
+/// and added to it.
+pub fn convert(journal: &Journal, map: &mut AccountMap) -> Result<String, Vec<Diagnostic>> {
+    let mut nodes: Vec<TreeNode> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut roots = Vec::new();
+    for account in &journal.accounts {
+        let mut parent: Option<usize> = None;
+        let mut path = String::new();
+        for part in account.split(':') {
+            if !path.is_empty() {
+                path.push(':');
+            }
+            path.push_str(part);
+            let i = match index.get(&path) {
+                Some(i) => *i,
+                None => {
+                    let i = nodes.len();
+                    nodes.push(TreeNode {
+                        path: path.clone(),
+                        children: Vec::new(),
+                    });
+                    index.insert(path.clone(), i);
+                    match parent {
+                        Some(p) => nodes[p].children.push(i),
+                        None => roots.push(i),
+                    }
+                    i
+                }
+            };
+            parent = Some(i);
+        }
+    }
+    let class = |root: usize| {
+        let path = &nodes[root].path;
+        journal
+            .classes
+            .get(path)
+            .copied()
+            .unwrap_or_else(|| Class::from_name(path))
+    };
+    roots.sort_by_key(|r| class(*r));
+
+    let mut taken: HashSet<i32> = map.accounts.values().filter_map(|(n, _)| *n).collect();
+    let mut chart = String::new();
+    let mut numbers = HashMap::new();
+    let mut wrapper = None;
+    for root in roots {
+        let class = class(root);
+        let mut next = class.first_number();
+        let depth = match class {
+            Class::Assets | Class::Liabilities => {
+                let name = if class == Class::Assets {
+                    "+ \"Vastaavaa\""
+                } else {
+                    "- \"Vastattavaa\""
+                };
+                if wrapper != Some(class) {
+                    chart.push_str(name);
+                    chart.push('\n');
+                    wrapper = Some(class);
+                }
+                1
+            }
+            _ => 0,
+        };
+        let mut stack = vec![(root, depth)];
+        while let Some((i, depth)) = stack.pop() {
+            let node = &nodes[i];
+            let numbered = journal.used.contains(&node.path) || node.children.is_empty();
+            let (n, name) = match map.accounts.get(&node.path) {
+                Some(account) => account.clone(),
+                None => {
+                    let n = numbered.then(|| {
+                        while !taken.insert(next) {
+                            next += 10;
+                        }
+                        next
+                    });
+                    let name = node.path.rsplit(':').next().unwrap_or_default();
+                    let account = (n, name.to_string());
+                    map.accounts.insert(node.path.clone(), account.clone());
+                    map.added.push(node.path.clone());
+                    account
+                }
+            };
+            // A top-level account named like the wrapper is the wrapper
+            let is_wrapper = depth == 1
+                && n.is_none()
+                && matches!(name.to_lowercase().as_str(), "vastaavaa" | "vastattavaa");
+            if !is_wrapper {
+                chart.push_str(&"  ".repeat(depth));
+                if let Some(n) = n {
+                    chart.push_str(&format!("{n} "));
+                    numbers.insert(node.path.as_str(), n);
+                }
+                chart.push_str(&format::quote(&name));
+                chart.push('\n');
+            }
+            let child_depth = if is_wrapper { depth } else { depth + 1 };
+            stack.extend(node.children.iter().rev().map(|c| (*c, child_depth)));
+        }
+    }
+
+    let base = journal.base_currency();
+    let is_base = |a: &Amount| a.commodity.as_deref().is_none_or(|c| c == base);
+    let mut out = String::new();
+    if base != "EUR" {
+        out.push_str(&format!(
+            "§ TIEDOT\nvaluutta = {}\n\n",
+            format::quote(&base)
+        ));
+    }
+    out.push_str("§ TILIKARTTA\n");
+    out.push_str(&chart);
+    let rates = journal
+        .prices
+        .iter()
+        .filter(|(_, c, price)| *c != base && is_currency_code(c) && is_base(price))
+        .map(|(date, c, price)| {
+            format!(
+                "{} {c} {}\n",
+                format::date(*date),
+                format::amount(price.value)
+            )
+        })
+        .collect::<String>();
+    if !rates.is_empty() {
+        out.push_str("\n§ KURSSIT\n");
+        out.push_str(&rates);
+    }
+    out.push_str("\n§ KIRJANPITO\n");
+    let (transactions, errors): (Vec<_>, Vec<_>) = journal
+        .transactions
+        .iter()
+        .map(|t| transaction(t, &numbers, &base))
+        .partition_result();
+    out.push_str(&transactions.join("\n"));
+    if errors.is_empty() {
+        Ok(format::format_source(&out, 0).unwrap_or(out))
+    } else {
+        Err(errors)
+    }
+}
+
+/// Writes a transaction in Tampio syntax.
+fn transaction(
+    t: &Transaction,
+    numbers: &HashMap<&str, i32>,
+    base: &str,
+) -> Result<String, Diagnostic> {
+    if t.postings.iter().filter(|p| p.amount.is_none()).count() > 1 {
+        return Err(Diagnostic::new(
+            t.span,
+            "tapahtumassa voi olla vain yksi vienti ilman summaa",
+        ));
+    }
+    let mut out = format!(
+        "{}{} {}",
+        format::date(t.date),
+        if t.cleared { " *" } else { "" },
+        format::quote(&t.description)
+    );
+    let mut lines = Vec::new();
+    match &t.code {
+        Some(code) if is_identifier(code) => out.push_str(&format!(" {code}")),
+        Some(code) => lines.push(format!("koodi: {}", format::quote(code))),
+        None => {}
+    }
+    for (name, value) in &t.tags {
+        match value.as_str() {
+            "" if is_tag(name) => out.push_str(&format!(" #{name}")),
+            _ if is_identifier(name) => lines.push(format!("{name}: {}", format::quote(value))),
+            _ => lines.push(format!("-- {name}: {value}")),
+        }
+    }
+    out.push('\n');
+    for comment in &t.comments {
+        out.push_str(&format!("  -- {comment}\n"));
+    }
+    for line in lines {
+        out.push_str(&format!("  {line}\n"));
+    }
+    for p in &t.postings {
+        let mut tags = String::new();
+        let mut comments = p.comments.clone();
+        for (name, value) in &p.tags {
+            match value.as_str() {
+                "" if is_tag(name) => tags.push_str(&format!(" #{name}")),
+                _ if is_tag(name) && is_tag(value) => {
+                    tags.push_str(&format!(" #{name}={value}"));
+                }
+                _ => comments.push(format!("{name}: {value}")),
+            }
+        }
+        out.push_str(&format!(
+            "  {}{}{tags}: {}\n",
+            if p.cleared && !t.cleared { "* " } else { "" },
+            numbers
+                .get(p.account.as_str())
+                .ok_or_else(|| Diagnostic::new(
+                    p.span,
+                    format!("tilille '{}' ei ole annettu numeroa", p.account)
+                ))?,
+            posting_amount(p, base)?,
+        ));
+        for comment in comments {
+            out.push_str(&format!("  -- {comment}\n"));
+        }
+    }
+    Ok(out)
+}
+
+/// The amount of a posting in Tampio syntax: in the base currency, in
+/// another currency with its rate, or `AUTO` when left out.
+fn posting_amount(p: &Posting, base: &str) -> Result<String, Diagnostic> {
+    let Some(amount) = &p.amount else {
+        return Ok("AUTO".to_string());
+    };
+    let commodity = amount.commodity.as_deref().unwrap_or(base);
+    let in_base = |price: &Amount| price.commodity.as_deref().is_none_or(|c| c == base);
+    if commodity == base {
+        return Ok(format::amount(amount.value));
+    }
+    match &p.price {
+        Some(Price::Total(total)) if in_base(total) => {
+            Ok(format::amount(if amount.value.is_negative() {
+                -total.value.abs()
+            } else {
+                total.value.abs()
+            }))
+        }
+        Some(Price::Unit(rate)) if in_base(rate) && is_currency_code(commodity) => Ok(format!(
+            "{} {commodity} @ {}",
+            format::amount(amount.value),
+            format::amount(rate.value)
+        )),
+        None if is_currency_code(commodity) => {
+            Ok(format!("{} {commodity}", format::amount(amount.value)))
+        }
+        _ => Err(Diagnostic::new(
+            p.span,
+            format!("hyödykettä '{commodity}' ei voi kirjata; vain valuutat ovat tuettuja"),
+        )),
+    }
+}
+
+fn is_currency_code(c: &str) -> bool {
+    c.len() == 3 && c.chars().all(|c| c.is_ascii_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::tests::parse_valid;
+
+    /// The error messages of reading and converting `source` with `map`.
+    fn errors(source: &str, map: &str) -> Vec<String> {
+        let mut map = AccountMap::from_source(map, 0).unwrap();
+        Journal::read(source, 0)
+            .and_then(|journal| convert(&journal, &mut map))
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.message)
+            .collect()
+    }
+
+    #[test]
+    fn journal_import() {
+        let source = "\
+; A comment
+account Assets:Bank  ; type: A
+P 2025-03-01 USD 0.92 EUR
+
+2025-03-01 * (A1) Sale  ; project: youth, urgent:
+    Assets:Bank        1,200.50 EUR
+    Income:Sales
+
+2025/03/02 ! Travel
+    ; a note
+    Expenses:Travel    100 USD @ 0.9 EUR  ; trip:
+    * Assets:Bank      -90 EUR
+
+2025-03-03 * \"Shop\" \"Paper\" #office
+  receipt: \"17\"
+  Expenses:Office  12.5 EUR
+  Assets:Bank
+";
+        let journal = Journal::read(source, 0).unwrap();
+        let mut map = AccountMap::from_source(
+            "\"Assets:Bank\" = 1910 \"Pankki\"\n\"Income\" = \"Tulot\"\n",
+            0,
+        )
+        .unwrap();
+        let tampio = convert(&journal, &mut map).unwrap();
+        assert_eq!(
+            tampio,
+            "§ TILIKARTTA\n+ \"Vastaavaa\"\n  \"Assets\"\n    1910 \"Pankki\"\n\"Tulot\"\n  \
+             3000 \"Sales\"\n\"Expenses\"\n  4000 \"Travel\"\n  4010 \"Office\"\n\n\
+             § KURSSIT\n1.3.2025 USD 0,92\n\n\
+             § KIRJANPITO\n\
+             1.3.2025 * \"Sale\" A1 #urgent\n  project: \"youth\"\n  1910:    1200,50\n  3000:    AUTO\n\n\
+             2.3.2025 \"Travel\"\n  -- a note\n  4000 #trip: 100,00 USD @ 0,90\n  * 1910:     -90,00\n\n\
+             3.3.2025 \"Shop, Paper\" #office\n  receipt: \"17\"\n  4010:    12,50\n  1910:    AUTO\n"
+        );
+        parse_valid(&tampio);
+        assert_eq!(map.added.len(), 5);
+        assert!(
+            map.source()
+                .contains("\"Expenses:Office\" = 4010 \"Office\"\n")
+        );
+        assert!(Journal::read("2025-03-01 x\n  (Budget)  10\n", 0).is_err());
+    }
+
+    #[test]
+    fn total_prices() {
+        let source = "\
+2025-03-01 Exchange
+    Assets:Bank     -9 EUR
+    Assets:Cash     10 USD @@ 9 EUR
+2025-03-02 Back
+    Assets:Cash     -10 USD @@ 9.5 EUR = 0 USD
+    Assets:Bank     9.5 EUR
+";
+        let journal = Journal::read(source, 0).unwrap();
+        let tampio = convert(&journal, &mut AccountMap::default()).unwrap();
+        assert!(
+            tampio.contains("  1000: -9,00\n  1010:  9,00\n"),
+            "{tampio}"
+        );
+        assert!(
+            tampio.contains("  1010: -9,50\n  1000:  9,50\n"),
+            "{tampio}"
+        );
+        parse_valid(&tampio);
+    }
+
+    #[test]
+    fn journal_errors() {
+        let bank = "\"Assets:Bank\" = \"Pankki\"\n";
+        assert_eq!(
+            errors("2025-03-01 x\n  Assets:Bank  10 EUR\n", bank),
+            ["tilille 'Assets:Bank' ei ole annettu numeroa"]
+        );
+        assert_eq!(
+            errors(
+                "2025-03-01 x\n  Assets:Bank  -1000 EUR\n  Assets:Stock  10 AAPL @ 100 EUR\n",
+                ""
+            ),
+            ["hyödykettä 'AAPL' ei voi kirjata; vain valuutat ovat tuettuja"]
+        );
+        assert_eq!(
+            errors(
+                "2025-03-01 x\n  Assets:Bank  10 EUR\n  Income:A\n  Income:B\n",
+                ""
+            ),
+            ["tapahtumassa voi olla vain yksi vienti ilman summaa"]
+        );
+        assert_eq!(
+            errors("2025-03-01 x\n  Assets:Bank  = 10 EUR\n  Income:A\n", ""),
+            ["saldon asetusta ei tueta"]
+        );
+        assert_eq!(
+            errors("include other.journal\n", ""),
+            ["include-direktiiviä ei tueta; yhdistä tiedostot ensin"]
+        );
+    }
+}
@@ -2,27 +2,86 @@ use itertools::Itertools;
 use serde::{Serialize, ser::SerializeStruct};
 use std::{
     cell::RefCell,
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs, io,
+    iter::zip,
+    path::{Path, PathBuf},
     rc::Rc,
 };
 
-use time::{Date, macros::format_description};
+use time::{
+    Date, Duration, Month,
+    format_description::{self, OwnedFormatItem},
+    macros::format_description,
+};
 
 use crate::{
+    diagnostic::{Diagnostic, SourceMap, Span},
+    format,
+    money::{DEFAULT_PRECISION, MAX_DECIMALS, Money},
     parser::Parser,
+    reference,
     semantic::{
-        AccountType, EntryType, SAccount, SAuto, SEntry, SExpression, SHeader, SStatement,
-        SectionType, Semantic,
+        AccountType, DUE_DATE, Dimensions, EntryType, Frequency, INVOICE_NUMBER, Metadata,
+        REFERENCE, SAccount, SAmount, SAuto, SEntry, SExpression, SHeader, SRecurring, SStatement,
+        SectionType, Semantic, VatSide,
     },
 };
 
+/// The original amount of an entry made in a foreign currency.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Foreign {
+    pub currency: String,
+    pub amount: Money,
+}
+
+/// An entry of a transaction, in the base currency.
+#[derive(Clone, Debug, Serialize)]
+pub struct Entry {
+    pub account: i32,
+    pub amount: Money,
+    pub foreign: Option<Foreign>,
+    pub metadata: Metadata,
+}
+
+/// A condition on the transactions shown in the report: a tag `nimi`, or a
+/// dimension or metadata value `nimi=arvo`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Filter {
+    Tag(String),
+    Value(String, String),
+}
+
+impl Filter {
+    pub fn parse(s: &str) -> Self {
+        let s = s.strip_prefix('#').unwrap_or(s);
+        match s.split_once('=') {
+            Some((key, value)) => Self::Value(key.into(), value.into()),
+            None => Self::Tag(s.into()),
+        }
+    }
+
+    fn matches(&self, metadata: &Metadata) -> bool {
+        match self {
+            Self::Tag(tag) => metadata.tags.contains(tag),
+            Self::Value(key, value) => {
+                metadata.dimensions.get(key) == Some(value)
+                    || metadata.values.get(key) == Some(value)
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Transaction {
     pub date: Date,
     pub description: String,
-    pub entries: Vec<(i32, i32)>,
+    pub entries: Vec<Entry>,
     pub n: i32,
     pub doc: String,
+    pub metadata: Metadata,
+    /// Expanded from a `TOISTUVA` block.
+    pub recurring: bool,
 }
 
 impl Serialize for Transaction {
@@ -30,11 +89,14 @@ impl Serialize for Transaction {
     where
         S: serde::Serializer,
     {
-        let mut t = serializer.serialize_struct("Transaction", 4)?;
+        let mut t = serializer.serialize_struct("Transaction", 7)?;
         t.serialize_field("n", &self.n)?;
+        t.serialize_field("doc", &self.doc)?;
         t.serialize_field("date", &format!("{}", self.date))?;
         t.serialize_field("description", &self.description)?;
         t.serialize_field("entries", &self.entries)?;
+        t.serialize_field("metadata", &self.metadata)?;
+        t.serialize_field("recurring", &self.recurring)?;
         t.end()
     }
 }
@@ -47,7 +109,7 @@ impl Ord for Transaction {
 
 impl PartialOrd for Transaction {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.date.cmp(&other.date))
+        Some(self.cmp(other))
     }
 }
 
@@ -59,29 +121,15 @@ impl PartialEq for Transaction {
 
 impl Eq for Transaction {}
 
-impl Transaction {
-    pub fn fmt_date(self) -> String {
-        let date_format =
-            format_description!("[day padding:none].[month padding:none].[year padding:none]");
-        self.date.format(&date_format).unwrap()
-    }
-}
-
-impl AccTransaction {
-    pub fn fmt_date(self) -> String {
-        let date_format =
-            format_description!("[day padding:none].[month padding:none].[year padding:none]");
-        self.date.format(&date_format).unwrap()
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct AccTransaction {
     pub(crate) n: i32,
     pub(crate) date: Date,
     pub(crate) description: String,
-    pub(crate) amount: i32,
+    pub(crate) amount: Money,
+    pub(crate) foreign: Option<Foreign>,
     pub(crate) doc: String,
+    pub(crate) cleared: bool,
 }
 
 impl Serialize for AccTransaction {
@@ -89,11 +137,14 @@ impl Serialize for AccTransaction {
     where
         S: serde::Serializer,
     {
-        let mut t = serializer.serialize_struct("AccTransaction", 4)?;
+        let mut t = serializer.serialize_struct("AccTransaction", 7)?;
         t.serialize_field("n", &self.n)?;
+        t.serialize_field("doc", &self.doc)?;
         t.serialize_field("date", &format!("{}", self.date))?;
         t.serialize_field("description", &self.description)?;
         t.serialize_field("amount", &self.amount)?;
+        t.serialize_field("foreign", &self.foreign)?;
+        t.serialize_field("cleared", &self.cleared)?;
         t.end()
     }
 }
@@ -103,12 +154,18 @@ pub struct Account {
     pub n: Option<i32>,
     pub name: String,
     pub(crate) sub_accounts: Vec<Rc<RefCell<Account>>>,
-    pub credits: Vec<i32>,
-    pub debits: Vec<i32>,
-    pub rec_credits: Vec<i32>,
-    pub rec_debits: Vec<i32>,
+    pub credits: Vec<Money>,
+    pub debits: Vec<Money>,
+    pub rec_credits: Vec<Money>,
+    pub rec_debits: Vec<Money>,
     pub transactions: Vec<AccTransaction>,
     pub t: AccountType,
+    /// Default VAT rate of entries to the account, in percent. Inherited
+    /// from the parent account unless given.
+    pub vat: Option<Money>,
+    /// Whether VAT on entries to the account is on sales or purchases.
+    /// Inherited like `vat`.
+    pub(crate) vat_side: Option<VatSide>,
 }
 
 impl Account {
@@ -118,25 +175,29 @@ impl Account {
             name,
             t,
             sub_accounts: Vec::new(),
-            credits: vec![0],
-            debits: vec![0],
-            rec_credits: vec![0],
-            rec_debits: vec![0],
+            credits: vec![Money::ZERO],
+            debits: vec![Money::ZERO],
+            rec_credits: vec![Money::ZERO],
+            rec_debits: vec![Money::ZERO],
             transactions: Vec::new(),
+            vat: None,
+            vat_side: None,
         }))
     }
 
     pub(crate) fn naked(n: Option<i32>, name: String, t: AccountType) -> Account {
         Self {
-            n: n,
-            name: name,
+            n,
+            name,
             sub_accounts: vec![],
             credits: vec![],
             debits: vec![],
             rec_credits: vec![],
             rec_debits: vec![],
             transactions: vec![],
-            t: t,
+            t,
+            vat: None,
+            vat_side: None,
         }
     }
 
@@ -145,33 +206,35 @@ impl Account {
     }
 
     fn add_ledger(&mut self) {
-        self.credits.push(0);
-        self.debits.push(0);
-        self.rec_credits.push(0);
-        self.rec_debits.push(0);
+        self.credits.push(Money::ZERO);
+        self.debits.push(Money::ZERO);
+        self.rec_credits.push(Money::ZERO);
+        self.rec_debits.push(Money::ZERO);
         for child in &self.sub_accounts {
             let mut child = child.borrow_mut();
             child.add_ledger();
         }
     }
 
-    fn add_amount(&mut self, amount: i32, li: usize) {
-        if amount > 0 {
+    fn add_amount(&mut self, amount: Money, li: usize) {
+        if amount.is_positive() {
             self.debits[li] += amount;
         } else {
             self.credits[li] -= amount;
         }
     }
 
-    fn calc_rec_sum(&mut self, li: usize) -> (i32, i32) {
+    /// Returns `None` if the total of the account and its sub-accounts
+    /// would overflow.
+    fn calc_rec_sum(&mut self, li: usize) -> Option<(Money, Money)> {
         self.rec_credits[li] = self.credits[li];
         self.rec_debits[li] = self.debits[li];
         for child in &self.sub_accounts {
-            let (child_d, child_c) = child.borrow_mut().calc_rec_sum(li);
-            self.rec_credits[li] += child_c;
-            self.rec_debits[li] += child_d;
+            let (child_d, child_c) = child.borrow_mut().calc_rec_sum(li)?;
+            self.rec_credits[li] = self.rec_credits[li].checked_add(child_c)?;
+            self.rec_debits[li] = self.rec_debits[li].checked_add(child_d)?;
         }
-        (self.rec_debits[li], self.rec_credits[li])
+        Some((self.rec_debits[li], self.rec_credits[li]))
     }
 
     fn add_transaction(&mut self, transaction: Transaction) {
@@ -179,44 +242,75 @@ impl Account {
         let t_date = transaction.date;
         let t_desc = transaction.description;
         if let Some(n) = self.n {
-            for entry in transaction.entries.iter().filter(|t| t.0 == n) {
+            for entry in transaction.entries.iter().filter(|e| e.account == n) {
                 self.transactions.push(AccTransaction {
                     n: t_n,
                     date: t_date,
                     description: t_desc.clone(),
-                    amount: entry.1,
+                    amount: entry.amount,
+                    foreign: entry.foreign.clone(),
                     doc: transaction.doc.clone(),
+                    cleared: entry.metadata.cleared,
                 })
             }
         }
     }
 
-    pub fn as_string(&self,top_level:bool, indent_level: usize) -> String {
+    pub fn as_string(&self, top_level: bool, indent_level: usize) -> String {
+        self.as_string_with_vat(top_level, indent_level, (None, None))
+    }
+
+    /// Like [`Account::as_string`], leaving out the VAT rate and side if
+    /// they're inherited from the parent account.
+    fn as_string_with_vat(
+        &self,
+        top_level: bool,
+        indent_level: usize,
+        (parent_vat, parent_side): (Option<Money>, Option<VatSide>),
+    ) -> String {
         let sub_account_strings = if self.is_leaf() {
             "".to_string()
         } else {
-            format!("\n{}",self.sub_accounts.iter().map(|a| a.borrow().as_string(false, indent_level+2)).join("\n"))
+            format!(
+                "\n{}",
+                self.sub_accounts
+                    .iter()
+                    .map(|a| {
+                        a.borrow().as_string_with_vat(
+                            false,
+                            indent_level + 2,
+                            (self.vat, self.vat_side),
+                        )
+                    })
+                    .join("\n")
+            )
         };
         let number = if let Some(n) = self.n {
             format!("{n} ")
         } else {
             "".to_string()
         };
-        let prefix = if !top_level {""} else { match self.t {
-            AccountType::None | AccountType::Liabilities => "",
-            AccountType::Assets => "+ ",
-            AccountType::LiabilitiesTopLevel => "- "
-        }};
-        let name = self.name.clone();
-        let name = if !name.contains("\"") {
-            format!("\"{name}\"")
-        } else if !name.contains("'") {
-            format!("'{name}'")
+        let prefix = if !top_level {
+            ""
         } else {
-            format!("»{name}»")
+            match self.t {
+                AccountType::None | AccountType::Liabilities => "",
+                AccountType::Assets => "+ ",
+                AccountType::LiabilitiesTopLevel => "- ",
+            }
         };
+        let name = format::quote(&self.name);
+        let mut vat = match self.vat.filter(|&v| Some(v) != parent_vat) {
+            Some(rate) => format!(" ALV{}", format::rate(rate)),
+            None => "".to_string(),
+        };
+        match self.vat_side.filter(|&s| Some(s) != parent_side) {
+            Some(VatSide::Sales) => vat.push_str(" myynti"),
+            Some(VatSide::Purchases) => vat.push_str(" osto"),
+            None => {}
+        }
         let indent = " ".repeat(indent_level);
-        format!("{indent}{prefix}{number}{name}{sub_account_strings}")
+        format!("{indent}{prefix}{number}{name}{vat}{sub_account_strings}")
     }
 }
 
@@ -229,9 +323,100 @@ pub struct Ledger {
     section: SectionType,
     pub options: Vec<HashMap<String, String>>,
     pub ledger_type: LedgerType,
+    /// Only transactions matching all of these are shown in the diary and
+    /// the general ledger.
+    pub filters: Vec<Filter>,
     pub comp_ledger_types: Vec<LedgerType>,
     pub(crate) l_index: usize,
     doc_d: HashMap<String, i32>,
+    assertions: Vec<Assertion>,
+    opening: Vec<SEntry>,
+    carried_forward: Option<ClosingBalances>,
+    rates: HashMap<String, Vec<(Date, Money)>>,
+    pub vat: Vec<VatEntry>,
+    /// Whether the first transaction is the opening balance sheet.
+    has_opening: bool,
+    /// Accounts named in `§ TIEDOT` of the main ledger, checked against the
+    /// account map once it is complete.
+    named_accounts: Vec<(i32, Span)>,
+    /// The absolute path of the `tositteet` directory.
+    attachment_dir: Option<PathBuf>,
+    /// Names of the files in the `tositteet` directory.
+    attachments: Vec<String>,
+    pub errors: Vec<Diagnostic>,
+}
+
+/// The VAT part of an entry. Sales have negative amounts and purchases
+/// positive, the other way round for credit notes and refunds.
+#[derive(Debug, Clone)]
+pub struct VatEntry {
+    pub date: Date,
+    pub rate: Money,
+    pub net: Money,
+    pub tax: Money,
+    pub sales: bool,
+}
+
+/// Closing balances of a ledger, carried forward as the next year's
+/// opening balances.
+pub struct ClosingBalances {
+    /// Balance sheet accounts with a non-zero balance.
+    pub accounts: Vec<(i32, Money)>,
+    /// Sum of the income statement accounts; profit is negative.
+    pub result: Money,
+}
+
+/// An invoice of the sales or purchase ledger.
+#[derive(Debug)]
+pub struct Invoice<'a> {
+    pub transaction: &'a Transaction,
+    /// The account the invoice is booked on.
+    pub account: i32,
+    pub party: Option<String>,
+    /// `viite` or `laskunro`, the metadata key of `reference`.
+    pub key: &'static str,
+    pub reference: String,
+    pub due: Date,
+    pub amount: Money,
+    /// The part of `amount` not yet paid.
+    pub open: Money,
+}
+
+/// The invoices of a receivables or payables account. The balance of the
+/// account should be the sum of the open amounts and `unallocated`, the
+/// entries without a reference. Amounts of payables are positive.
+#[derive(Debug)]
+pub struct OpenItems<'a> {
+    pub account: i32,
+    pub invoices: Vec<Invoice<'a>>,
+    pub unallocated: Money,
+    /// The balance of the account and its sub-accounts in the account tree.
+    pub balance: Money,
+}
+
+/// The reconciliation of an account with the bank statement.
+#[derive(Debug)]
+pub struct Reconciliation<'a> {
+    pub account: i32,
+    /// The date and balance of the latest `tiliote`.
+    pub statement: Option<(Date, Money)>,
+    /// The balance in the ledger at the date of the statement or, without
+    /// one, at the end.
+    pub balance: Money,
+    /// Entries not marked cleared, with their transactions.
+    pub uncleared: Vec<(&'a Transaction, Money)>,
+}
+
+/// A `tarkista` line: the balance of `account` at the end of `date` should
+/// be `amount`.
+struct Assertion {
+    account: i32,
+    date: Date,
+    amount: Money,
+    /// A `tiliote` line: the balance on the bank statement, which clears
+    /// the entries up to `date`.
+    statement: bool,
+    span: Span,
 }
 
 #[derive(Default)]
@@ -239,13 +424,7 @@ struct Scope {
     date: Option<Date>,
     auto_balance: Option<i32>,
     aliases: HashMap<String, i32>,
-}
-
-#[derive(Serialize)]
-pub struct LedgerOut {
-    pub ledger: Vec<Transaction>,
-    pub accounts: Vec<Account>,
-    pub general_ledger: Vec<Vec<Transaction>>,
+    dimensions: Dimensions,
 }
 
 impl Scope {
@@ -263,6 +442,7 @@ impl ScopeStack<Scope> for Vec<Scope> {
         let mut date = None;
         let mut auto_balance = None;
         let mut aliases = HashMap::new();
+        let mut dimensions = Dimensions::new();
         for s in self {
             if let Some(d) = s.date {
                 date = Some(d);
@@ -271,11 +451,13 @@ impl ScopeStack<Scope> for Vec<Scope> {
                 auto_balance = Some(a_b);
             }
             aliases.extend(s.aliases.clone());
+            dimensions.extend(s.dimensions.clone());
         }
         Scope {
             date,
             auto_balance,
             aliases,
+            dimensions,
         }
     }
 }
@@ -300,9 +482,20 @@ impl Ledger {
             account_dict: HashMap::new(),
             options: vec![HashMap::new()],
             ledger_type: LedgerType::Main,
+            filters: Vec::new(),
             comp_ledger_types: vec![LedgerType::Main],
             l_index: 0,
             doc_d: HashMap::new(),
+            assertions: Vec::new(),
+            opening: Vec::new(),
+            carried_forward: None,
+            rates: HashMap::new(),
+            vat: Vec::new(),
+            has_opening: false,
+            named_accounts: Vec::new(),
+            attachment_dir: None,
+            attachments: Vec::new(),
+            errors: Vec::new(),
         }
     }
 
@@ -311,14 +504,20 @@ impl Ledger {
         self.ledger.clone()
     }
 
-    pub fn add_comparison_from_str(&mut self, s: &str) {
+    /// Reads the ledger at `path` and adds it as a comparison column.
+    pub fn add_comparison_from_path(
+        &mut self,
+        path: &str,
+        sources: &mut SourceMap,
+    ) -> io::Result<()> {
+        let source = fs::read_to_string(path)?;
+        let file = sources.add(path, &source);
+        let statements = Self::parse_source(&source, file, sources, &mut self.errors);
         let lt = self.ledger_type;
-        let mut parser = Parser::new(s);
-        let statements = Semantic::from_parse_tree(parser.parse()).statements;
-        if statements.iter().any(|x| match x {
-            SStatement::Section(SectionType::Budget) => true,
-            _ => false
-        }) {
+        if statements
+            .iter()
+            .any(|x| matches!(x, SStatement::Section(SectionType::Budget)))
+        {
             self.comp_ledger_types.push(LedgerType::Budget);
         } else {
             self.comp_ledger_types.push(LedgerType::Main);
@@ -330,8 +529,11 @@ impl Ledger {
             account.add_ledger();
         }
         self.exec_statements(statements);
+        self.exec_opening();
         self.calculate_sums();
         self.ledger_type = lt;
+        self.errors.sort_by_key(|e| e.span);
+        Ok(())
     }
 
     pub fn accounts(&self) -> Vec<Account> {
@@ -339,7 +541,10 @@ impl Ledger {
     }
 
     pub fn account_map_string(&self) -> String {
-        self.accounts.iter().map(|a| a.borrow().as_string(true,0)).join("\n")
+        self.accounts
+            .iter()
+            .map(|a| a.borrow().as_string(true, 0))
+            .join("\n")
     }
 
     #[allow(dead_code)]
@@ -348,29 +553,288 @@ impl Ledger {
         println!("{:?}", self.account_dict);
     }
 
-    #[allow(dead_code)]
-    pub fn json(&self) -> String {
-        serde_json::to_string(&LedgerOut {
-            ledger: self.ledger(),
-            accounts: self.accounts(),
-            general_ledger: Vec::new(),
-        })
-        .unwrap()
-    }
-
-    pub fn exec(statements: Vec<SStatement>) -> Self {
+    /// Executes `statements`, starting from the `opening` balances if given.
+    /// Statements that fail are reported in `errors` and skipped, so the
+    /// result is a partial ledger when there are errors.
+    pub fn exec(statements: Vec<SStatement>, opening: Option<ClosingBalances>) -> Self {
         let mut instance = Ledger::new();
+        instance.carried_forward = opening;
         instance.exec_statements(statements);
+        instance.exec_opening();
         instance.complete_multi_docs();
         instance.sort_ledger();
         instance.calculate_sums();
+        instance.clear_reconciled();
         instance.populate_account_transactions();
+        instance.check_assertions();
+        instance.check_ledger_accounts();
         instance
     }
 
-    pub fn from_string(source: String) -> Self {
-        let mut parser = Parser::new(&source);
-        Self::exec(Semantic::from_parse_tree(parser.parse()).statements)
+    /// Reads and executes the ledger at `path`, following its includes.
+    /// Errors from every phase end up in `errors`, ordered by position.
+    pub fn from_path(
+        path: &str,
+        opening: Option<ClosingBalances>,
+        sources: &mut SourceMap,
+    ) -> io::Result<Self> {
+        let source = fs::read_to_string(path)?;
+        let file = sources.add(path, &source);
+        let mut ledger = Self::from_source(&source, file, opening, sources);
+        let base = Path::new(path).parent().unwrap_or(Path::new(""));
+        ledger.read_attachments(base);
+        Ok(ledger)
+    }
+
+    /// Lists the files in the directory given by the `tositteet` option,
+    /// relative to `base`.
+    fn read_attachments(&mut self, base: &Path) {
+        let Some(dir) = self.options[0].get("tositteet") else {
+            return;
+        };
+        let dir_path = base.join(dir);
+        let entries = match fs::read_dir(&dir_path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.errors.push(Diagnostic::without_span(format!(
+                    "tositekansiota '{dir}' ei voi lukea: {e}"
+                )));
+                return;
+            }
+        };
+        self.attachments = entries
+            .flatten()
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|name| !name.starts_with('.'))
+            .sorted()
+            .collect();
+        self.attachment_dir = std::path::absolute(dir_path).ok();
+    }
+
+    /// Absolute paths of the attachments of the transaction with `doc`, so
+    /// that links to them work wherever the report is written. The file
+    /// names of attachments start with the doc, e.g. `17.pdf` or
+    /// `17_kuitti.jpg`. Transactions that share a doc, like `17:1` and
+    /// `17:2`, also share its attachments.
+    pub(crate) fn attachments(&self, doc: &str) -> Vec<PathBuf> {
+        let Some(dir) = &self.attachment_dir else {
+            return vec![];
+        };
+        self.attachments
+            .iter()
+            .filter(|name| is_attachment_of(name, doc))
+            .map(|name| dir.join(name))
+            .collect()
+    }
+
+    /// Transactions without attachments and attachments without
+    /// transactions. The opening balance sheet doesn't need an attachment.
+    pub fn missing_attachments(&self) -> (Vec<&Transaction>, Vec<&str>) {
+        let transactions = self
+            .ledger
+            .iter()
+            .skip(self.has_opening as usize)
+            .filter(|t| self.attachments(&t.doc).is_empty())
+            .collect();
+        let files = self
+            .attachments
+            .iter()
+            .filter(|name| !self.ledger.iter().any(|t| is_attachment_of(name, &t.doc)))
+            .map(String::as_str)
+            .collect();
+        (transactions, files)
+    }
+
+    /// The sales ledger: invoices booked on the account `myyntisaamiset`.
+    pub fn sales_ledger(&self) -> Option<OpenItems<'_>> {
+        let account = self.alias("myyntisaamiset")?;
+        Some(self.open_items(account, false, "asiakas"))
+    }
+
+    /// The purchase ledger: supplier invoices credited to the account
+    /// `ostovelat` or its sub-accounts.
+    pub fn purchase_ledger(&self) -> Option<OpenItems<'_>> {
+        let account = self.alias("ostovelat")?;
+        Some(self.open_items(account, true, "toimittaja"))
+    }
+
+    /// Checks that the accounts named in `§ TIEDOT` are in the account map
+    /// and that the sales and purchase ledger accounts are on the right side
+    /// of the balance sheet.
+    fn check_ledger_accounts(&mut self) {
+        for &(n, span) in &self.named_accounts {
+            if let Err(e) = self.check_account(n, span) {
+                self.errors.push(e);
+            }
+        }
+        let accounts = [
+            ("myyntisaamiset", AccountType::Assets, "vastaavaa"),
+            ("ostovelat", AccountType::Liabilities, "vastattavaa"),
+        ];
+        for (alias, t, side) in accounts {
+            let Some(n) = self.alias(alias) else {
+                continue;
+            };
+            let ok = self.account_dict.get(&n).is_none_or(|a| {
+                let a = a.borrow().t;
+                a == t || (t == AccountType::Liabilities && a == AccountType::LiabilitiesTopLevel)
+            });
+            if !ok {
+                self.errors.push(Diagnostic::without_span(format!(
+                    "tilin {alias} ({n}) pitää olla taseen {side}-puolella"
+                )));
+            }
+        }
+    }
+
+    /// Invoices on `account` and its sub-accounts, with the payments
+    /// matched to them by reference number or invoice number. An invoice is
+    /// an entry with a reference not seen before, a debit or with `credit`
+    /// a credit; later entries with the same reference are its payments.
+    /// The other party is the value `party_key` of the invoice or the name
+    /// of the sub-account.
+    fn open_items(&self, account: i32, credit: bool, party_key: &str) -> OpenItems<'_> {
+        let balance = self.account_dict.get(&account).map_or(Money::ZERO, |a| {
+            let a = a.borrow();
+            a.rec_debits[0] - a.rec_credits[0]
+        });
+        let mut items = OpenItems {
+            account,
+            invoices: Vec::new(),
+            unallocated: Money::ZERO,
+            balance: if credit { -balance } else { balance },
+        };
+        let mut sub_accounts: HashMap<i32, Option<String>> = HashMap::new();
+        let mut transactions = BTreeSet::new();
+        let mut stack = self
+            .account_dict
+            .get(&account)
+            .into_iter()
+            .cloned()
+            .collect_vec();
+        while let Some(a) = stack.pop() {
+            let a = a.borrow();
+            if let Some(n) = a.n {
+                sub_accounts.insert(n, (n != account).then(|| a.name.clone()));
+            }
+            transactions.extend(a.transactions.iter().map(|t| t.n));
+            stack.extend(a.sub_accounts.iter().cloned());
+        }
+        let mut by_reference: HashMap<String, usize> = HashMap::new();
+        for t in transactions.into_iter().map(|n| &self.ledger[n as usize]) {
+            let mut amounts: BTreeMap<Option<String>, (Money, &Entry, &'static str)> =
+                BTreeMap::new();
+            for e in t
+                .entries
+                .iter()
+                .filter(|e| sub_accounts.contains_key(&e.account))
+            {
+                let reference = [&e.metadata, &t.metadata].into_iter().find_map(|m| {
+                    match (m.values.get(REFERENCE), m.values.get(INVOICE_NUMBER)) {
+                        (Some(r), _) => Some((REFERENCE, reference::normalise(r))),
+                        (None, Some(n)) => Some((INVOICE_NUMBER, n.clone())),
+                        (None, None) => None,
+                    }
+                });
+                let (key, reference) = reference.unzip();
+                let (amount, ..) =
+                    amounts
+                        .entry(reference)
+                        .or_insert((Money::ZERO, e, key.unwrap_or_default()));
+                *amount += if credit { -e.amount } else { e.amount };
+            }
+            for (reference, (amount, e, key)) in amounts {
+                match reference {
+                    Some(r) if by_reference.contains_key(&r) => {
+                        items.invoices[by_reference[&r]].open += amount;
+                    }
+                    Some(r) if amount.is_positive() => {
+                        by_reference.insert(r.clone(), items.invoices.len());
+                        let party = [&e.metadata, &t.metadata]
+                            .into_iter()
+                            .find_map(|m| m.values.get(party_key));
+                        items.invoices.push(Invoice {
+                            transaction: t,
+                            account: e.account,
+                            party: party.or(sub_accounts[&e.account].as_ref()).cloned(),
+                            key,
+                            reference: r,
+                            due: self.due_date(t, e),
+                            amount,
+                            open: amount,
+                        });
+                    }
+                    _ => items.unallocated += amount,
+                }
+            }
+        }
+        items
+    }
+
+    /// The `eräpäivä` of an invoice entry or its transaction, or the date
+    /// after the payment term set with `maksuaika` (in days, 14 by default).
+    fn due_date(&self, t: &Transaction, e: &Entry) -> Date {
+        let due = [&e.metadata, &t.metadata]
+            .into_iter()
+            .find_map(|m| m.values.get(DUE_DATE));
+        let due = due.and_then(|d| {
+            option_date(d).or_else(|| {
+                let partial = Date::parse(
+                    &format!("{d}{}", t.date.year()),
+                    format_description!("[day padding:none].[month padding:none].[year]"),
+                );
+                partial.ok()
+            })
+        });
+        let days: u32 = self.options[0]
+            .get("maksuaika")
+            .and_then(|d| d.parse().ok())
+            .unwrap_or(14);
+        due.unwrap_or_else(|| {
+            t.date
+                .checked_add(Duration::days(days.into()))
+                .unwrap_or(Date::MAX)
+        })
+    }
+
+    /// The date open items are aged at: the end of the fiscal period or,
+    /// if it isn't set, the date of the last transaction.
+    pub fn report_date(&self) -> Option<Date> {
+        self.period(0)
+            .map(|(_, end)| end)
+            .or_else(|| self.ledger.iter().map(|t| t.date).max())
+    }
+
+    /// Parses and executes `source`, which has been added to `sources` as
+    /// `file`.
+    pub fn from_source(
+        source: &str,
+        file: usize,
+        opening: Option<ClosingBalances>,
+        sources: &mut SourceMap,
+    ) -> Self {
+        let mut errors = Vec::new();
+        let statements = Self::parse_source(source, file, sources, &mut errors);
+        let mut instance = Self::exec(statements, opening);
+        errors.append(&mut instance.errors);
+        errors.sort_by_key(|e| e.span);
+        instance.errors = errors;
+        instance
+    }
+
+    fn parse_source(
+        source: &str,
+        file: usize,
+        sources: &mut SourceMap,
+        errors: &mut Vec<Diagnostic>,
+    ) -> Vec<SStatement> {
+        let mut parser = Parser::with_file(source, file);
+        let tree = parser.parse();
+        errors.append(&mut parser.errors);
+        let semantic = Semantic::from_parse_tree(tree, file, sources);
+        errors.extend(semantic.errors);
+        semantic.statements
     }
 
     pub fn get_account(&self, account_n: i32) -> Option<Account> {
@@ -383,9 +847,9 @@ impl Ledger {
         for transaction in &self.ledger {
             let mut v = HashSet::new();
             for entry in &transaction.entries {
-                if v.insert((transaction.n, entry.0)) {
+                if v.insert((transaction.n, entry.account)) {
                     self.account_dict
-                        .get(&entry.0)
+                        .get(&entry.account)
                         .expect("invalid account number")
                         .borrow_mut()
                         .add_transaction(transaction.clone());
@@ -394,9 +858,29 @@ impl Ledger {
         }
     }
 
+    /// Sums the totals up the account tree. The debits and the credits of
+    /// all accounts must each fit in `Money`; then every balance, result and
+    /// total computed from the ledger, being a part of the debits less a
+    /// part of the credits, fits too.
     fn calculate_sums(&mut self) {
+        let mut totals = Some((Money::ZERO, Money::ZERO));
         for account in &self.accounts {
-            account.borrow_mut().calc_rec_sum(self.l_index);
+            let mut account = account.borrow_mut();
+            let Some((debits, credits)) = account.calc_rec_sum(self.l_index) else {
+                let label = account
+                    .n
+                    .map_or_else(|| format::quote(&account.name), |n| n.to_string());
+                self.errors.push(Diagnostic::without_span(format!(
+                    "tilin {label} summa on liian suuri"
+                )));
+                return;
+            };
+            totals =
+                totals.and_then(|(d, c)| Some((d.checked_add(debits)?, c.checked_add(credits)?)));
+        }
+        if totals.is_none() {
+            self.errors
+                .push(Diagnostic::without_span("kirjanpidon summa on liian suuri"));
         }
     }
 
@@ -420,27 +904,70 @@ impl Ledger {
 
     fn exec_statements(&mut self, statements: Vec<SStatement>) {
         for statement in statements {
-            self.exec_s(statement);
+            if let Err(e) = self.exec_s(statement) {
+                self.errors.push(e);
+            }
         }
     }
 
-    fn exec_s(&mut self, statement: SStatement) {
+    fn exec_s(&mut self, statement: SStatement) -> Result<(), Diagnostic> {
         match statement {
-            SStatement::Block(header, body) => self.exec_block(header, body),
+            SStatement::Block(header, dimensions, body, span) => {
+                self.exec_block(header, dimensions, body, span)?
+            }
             SStatement::Transaction {
                 date,
                 description,
                 entries,
                 doc,
-            } => self.exec_transaction(date, description, entries, doc, false),
-            SStatement::Expression(expr) => self.exec_expression(expr),
+                metadata,
+                span,
+            } => {
+                // A metadata line named like an alias is a mistyped entry,
+                // such as `pankki: 1OO`
+                let aliases = self.scopes.collapsed().aliases;
+                if let Some((key, value)) = metadata
+                    .values
+                    .iter()
+                    .find(|(key, _)| aliases.contains_key(*key))
+                {
+                    return Err(Diagnostic::new(
+                        span,
+                        format!("virheellinen summa '{value}' tilille '{key}'"),
+                    ));
+                }
+                self.scopes.push(Scope {
+                    dimensions: metadata.dimensions,
+                    ..Default::default()
+                });
+                let count = self.ledger.len();
+                let result = self.exec_transaction(date, description, entries, doc, false, span);
+                self.scopes.pop();
+                result?;
+                if self.ledger.len() > count {
+                    let transaction = self.ledger.last_mut().unwrap();
+                    transaction.metadata.tags = metadata.tags;
+                    transaction.metadata.values = metadata.values;
+                    if metadata.cleared {
+                        transaction.metadata.cleared = true;
+                        for entry in &mut transaction.entries {
+                            entry.metadata.cleared = true;
+                        }
+                    }
+                }
+            }
+            SStatement::Expression(expr, span) => self.exec_expression(expr, span)?,
             SStatement::Section(section) => self.section = section,
-            SStatement::Account(n, name, subs, acc_type) => {
+            SStatement::Account(n, name, subs, acc_type, vat, side) => {
                 if self.l_index == 0 {
-                    self.exec_account(n, name, subs, acc_type, None)
+                    self.exec_account(n, name, subs, acc_type, (vat, side), None)
                 }
             }
-            SStatement::BudgetEntry { account, amounts } => self.exec_transaction(
+            SStatement::BudgetEntry {
+                account,
+                amounts,
+                span,
+            } => self.exec_transaction(
                 None,
                 "".into(),
                 vec![SEntry {
@@ -448,15 +975,132 @@ impl Ledger {
                     amounts: amounts
                         .iter()
                         .map(|(a, t)| match t {
-                            EntryType::None | EntryType::Credit => SAuto::Val(-a),
-                            EntryType::Debit => SAuto::Val(*a),
+                            EntryType::None | EntryType::Credit => SAuto::Val((-*a).into()),
+                            EntryType::Debit => SAuto::Val((*a).into()),
                         })
                         .collect(), // amounts: vec![SAuto::Val(amount)],
+                    vat: None,
+                    metadata: Metadata::default(),
+                    span,
                 }],
                 None,
                 true,
-            ),
+                span,
+            )?,
+            SStatement::Assertion {
+                account,
+                amount,
+                statement,
+                span,
+            } => self.exec_assertion(account, amount, statement, span)?,
+            SStatement::OpeningEntry(entry) => self.opening.push(entry),
+            SStatement::Recurring(recurring) => self.exec_recurring(recurring)?,
+            SStatement::Rate {
+                date,
+                currency,
+                rate,
+                span,
+            } => self.exec_rate(date, currency, rate, span)?,
+        }
+        Ok(())
+    }
+
+    /// Expands a `TOISTUVA` block into a transaction for each occurrence,
+    /// leaving out the skipped ones and using the overrides in their place.
+    /// Occurrences share the doc of the block, numbered `V:1`, `V:2`, ….
+    /// An occurrence, skip or override that fails is reported in `errors`
+    /// and the rest are still expanded.
+    fn exec_recurring(&mut self, recurring: SRecurring) -> Result<(), Diagnostic> {
+        let SRecurring {
+            frequency,
+            start,
+            end,
+            description,
+            entries,
+            doc,
+            metadata,
+            overrides,
+            skips,
+            span,
+        } = recurring;
+        let start = self.resolve_date(start, &description, span)?;
+        let end = self.resolve_date(end, &description, span)?;
+        if end < start {
+            return Err(Diagnostic::new(
+                span,
+                "toistuvan tapahtuman loppu on ennen alkua",
+            ));
+        }
+        let dates = occurrences(frequency, start, end);
+        let check_date = |ledger: &mut Self, date: Date, span: Span| {
+            let date = ledger.resolve_date(date, &description, span)?;
+            if dates.contains(&date) {
+                Ok(date)
+            } else {
+                Err(Diagnostic::new(
+                    span,
+                    format!(
+                        "{} ei ole toistuvan tapahtuman \"{description}\" päivä",
+                        ledger.fmt_date(date)
+                    ),
+                ))
+            }
+        };
+        let mut skipped = BTreeSet::new();
+        for (date, span) in skips {
+            match check_date(self, date, span) {
+                Ok(date) => {
+                    skipped.insert(date);
+                }
+                Err(e) => self.errors.push(e),
+            }
+        }
+        let mut replaced = BTreeMap::new();
+        for (date, mut statement) in overrides {
+            let statement_span = match &statement {
+                SStatement::Transaction { span, .. } => *span,
+                _ => span,
+            };
+            let date = match check_date(self, date, statement_span) {
+                Ok(date) => date,
+                Err(e) => {
+                    self.errors.push(e);
+                    continue;
+                }
+            };
+            if let SStatement::Transaction {
+                date: d,
+                doc: override_doc,
+                ..
+            } = &mut statement
+            {
+                *d = Some(date);
+                if override_doc.is_none() {
+                    override_doc.clone_from(&doc);
+                }
+            }
+            replaced.insert(date, statement);
         }
+        for date in dates.iter().filter(|d| !skipped.contains(*d)) {
+            let statement = replaced
+                .remove(date)
+                .unwrap_or_else(|| SStatement::Transaction {
+                    date: Some(*date),
+                    description: description.clone(),
+                    entries: entries.clone(),
+                    doc: doc.clone(),
+                    metadata: metadata.clone(),
+                    span,
+                });
+            let count = self.ledger.len();
+            if let Err(e) = self.exec_s(statement) {
+                self.errors.push(e);
+            }
+            if self.ledger.len() > count {
+                self.ledger.last_mut().unwrap().recurring = true;
+            }
+        }
+        Ok(())
     }
 
     fn exec_account(
@@ -465,16 +1109,26 @@ impl Ledger {
         name: String,
         sub_accounts: Vec<SStatement>,
         acc_type: AccountType,
+        (vat, side): (Option<Money>, Option<VatSide>),
         parent: Option<Rc<RefCell<Account>>>,
     ) {
-        let account = Account::new(n, name, acc_type.clone());
+        let account = Account::new(n, name, acc_type);
+        account.borrow_mut().vat = vat;
+        account.borrow_mut().vat_side = side;
         for sub in sub_accounts {
-            if let SStatement::Account(sn, sname, ssubs, _) = sub {
+            if let SStatement::Account(sn, sname, ssubs, _, svat, sside) = sub {
                 let child_type = match acc_type {
                     AccountType::LiabilitiesTopLevel => AccountType::Liabilities,
                     _ => acc_type,
                 };
-                self.exec_account(sn, sname, ssubs, child_type, Some(Rc::clone(&account)));
+                self.exec_account(
+                    sn,
+                    sname,
+                    ssubs,
+                    child_type,
+                    (svat.or(vat), sside.or(side)),
+                    Some(Rc::clone(&account)),
+                );
             }
         }
 
@@ -488,9 +1142,12 @@ impl Ledger {
         }
     }
 
-    fn exec_expression(&mut self, expr: SExpression) {
+    fn exec_expression(&mut self, expr: SExpression, span: Span) -> Result<(), Diagnostic> {
         match expr {
             SExpression::Alias(ident, n) => {
+                if self.section == SectionType::Options && self.l_index == 0 {
+                    self.named_accounts.push((n, span));
+                }
                 self.scopes
                     .last_mut()
                     .expect("scope stack empty")
@@ -498,50 +1155,747 @@ impl Ledger {
             }
             SExpression::Definition(i, d) => {
                 if self.section == SectionType::Options {
-                    self.options[self.l_index].insert(i.to_lowercase(), d);
+                    let key = i.to_lowercase();
+                    if key == "desimaalit"
+                        && !matches!(d.parse::<u32>(), Ok(p) if p <= MAX_DECIMALS)
+                    {
+                        return Err(Diagnostic::new(
+                            span,
+                            format!("desimaalien määrän pitää olla 0–{MAX_DECIMALS}"),
+                        ));
+                    }
+                    if key == "maksuaika" && d.parse::<u32>().is_err() {
+                        return Err(Diagnostic::new(span, "maksuajan pitää olla päivien määrä"));
+                    }
+                    if key == "alv_jakso" && !matches!(d.as_str(), "kuukausi" | "neljännesvuosi") {
+                        return Err(Diagnostic::new(
+                            span,
+                            "alv_jakso on joko \"kuukausi\" tai \"neljännesvuosi\"",
+                        ));
+                    }
+                    if key == "päivämäärämuoto" && date_format(&d).is_none() {
+                        return Err(Diagnostic::new(
+                            span,
+                            format!("tunnistamaton päivämäärämuoto '{d}'"),
+                        ));
+                    }
+                    if key == "alku" || key == "loppu" {
+                        let Some(date) = option_date(&d) else {
+                            return Err(Diagnostic::new(
+                                span,
+                                format!("virheellinen päivämäärä '{d}'"),
+                            ));
+                        };
+                        let other = if key == "alku" { "loppu" } else { "alku" };
+                        let other = self.options[self.l_index]
+                            .get(other)
+                            .and_then(|d| option_date(d));
+                        if let Some(other) = other
+                            && ((key == "alku" && date > other) || (key == "loppu" && date < other))
+                        {
+                            return Err(Diagnostic::new(span, "tilikauden loppu on ennen alkua"));
+                        }
+                    }
+                    self.options[self.l_index].insert(key, d);
                 }
             }
         }
+        Ok(())
     }
 
-    fn exec_block(&mut self, header: SHeader, body: Vec<SStatement>) {
-        match header {
-            SHeader::Date { date } => {
-                self.scopes.push(Scope {
-                    date: Some(date),
+    /// Number of decimals amounts in ledger `li` may have, set with the
+    /// `desimaalit` option.
+    pub fn precision(&self, li: usize) -> u32 {
+        self.options[li]
+            .get("desimaalit")
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(DEFAULT_PRECISION)
+    }
+
+    /// The fiscal period of ledger `li`, set with the `alku` and `loppu`
+    /// options. If only one of them is given the period is a year long.
+    pub fn period(&self, li: usize) -> Option<(Date, Date)> {
+        let start = self.options[li].get("alku").and_then(|d| option_date(d));
+        let end = self.options[li].get("loppu").and_then(|d| option_date(d));
+        match (start, end) {
+            (Some(start), Some(end)) => Some((start, end)),
+            (Some(start), None) => Some((start, same_day(start, start.year() + 1).previous_day()?)),
+            (None, Some(end)) => Some((same_day(end, end.year() - 1).next_day()?, end)),
+            (None, None) => None,
+        }
+    }
+
+    /// The year-long fiscal period following the main ledger's period, or
+    /// the next calendar year if no period is set.
+    pub fn next_period(&self) -> Option<(Date, Date)> {
+        let start = match self.period(0) {
+            Some((_, end)) => end.next_day()?,
+            None if self.year != 0 => {
+                Date::from_calendar_date(self.year + 1, Month::January, 1).ok()?
+            }
+            None => return None,
+        };
+        Some((start, same_day(start, start.year() + 1).previous_day()?))
+    }
+
+    /// The account number of a top-level alias.
+    pub fn alias(&self, name: &str) -> Option<i32> {
+        self.scopes.first()?.aliases.get(name).copied()
+    }
+
+    /// Formats a date for the report, as set with the `päivämäärämuoto`
+    /// option.
+    pub fn fmt_date(&self, date: Date) -> String {
+        let format = self.options[0]
+            .get("päivämäärämuoto")
+            .and_then(|f| date_format(f))
+            .unwrap_or_else(|| date_format("suomi").unwrap());
+        date.format(&format).unwrap()
+    }
+
+    fn exec_block(
+        &mut self,
+        header: SHeader,
+        dimensions: Dimensions,
+        body: Vec<SStatement>,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        let mut scope = match header {
+            SHeader::Date { date } => Scope {
+                date: Some(date),
+                ..Default::default()
+            },
+            SHeader::AutoBalance { account } => {
+                let n = match account {
+                    SAccount::N(n) => n,
+                    SAccount::Alias(alias) => self.resolve_alias(&alias, span)?,
+                };
+                Scope {
+                    auto_balance: Some(n),
                     ..Default::default()
-                });
-                self.exec_statements(body);
-                self.scopes.pop();
+                }
             }
-            SHeader::AutoBalance { account } => match account {
-                SAccount::N(n) => {
-                    self.scopes.push(Scope {
-                        auto_balance: Some(n),
-                        ..Default::default()
-                    });
-                    self.exec_statements(body);
-                    self.scopes.pop();
+            SHeader::Dummy => Scope::default(),
+        };
+        scope.dimensions = dimensions;
+        self.scopes.push(scope);
+        self.exec_statements(body);
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn resolve_alias(&self, alias: &str, span: Span) -> Result<i32, Diagnostic> {
+        self.scopes
+            .collapsed()
+            .aliases
+            .get(alias)
+            .copied()
+            .ok_or_else(|| Diagnostic::new(span, format!("nimeä '{alias}' ei ole määritelty")))
+    }
+
+    fn account_type(&self, n: i32) -> Option<AccountType> {
+        self.account_dict.get(&n).map(|a| a.borrow().t)
+    }
+
+    fn check_account(&self, n: i32, span: Span) -> Result<(), Diagnostic> {
+        if self.account_dict.contains_key(&n) {
+            Ok(())
+        } else {
+            Err(Diagnostic::new(span, format!("tili {n} ei ole määritelty")))
+        }
+    }
+
+    /// The closing balances of the main ledger, to be used as the opening
+    /// balances of the next year.
+    pub fn closing_balances(&self) -> ClosingBalances {
+        let mut balances: BTreeMap<i32, Money> = BTreeMap::new();
+        for Entry {
+            account: n, amount, ..
+        } in self.ledger.iter().flat_map(|t| &t.entries)
+        {
+            *balances.entry(*n).or_default() += *amount;
+        }
+        let mut accounts = Vec::new();
+        let mut result = Money::ZERO;
+        for (n, balance) in balances {
+            match self.account_dict[&n].borrow().t {
+                AccountType::None => result += balance,
+                _ if balance.is_zero() => {}
+                _ => accounts.push((n, balance)),
+            }
+        }
+        ClosingBalances { accounts, result }
+    }
+
+    /// The "Tilikauden tulos" row of the balance sheet: the sums of the
+    /// income statement accounts, for every ledger.
+    pub(crate) fn result_account(&self) -> Account {
+        let mut result = Account::naked(None, "Tilikauden tulos".into(), AccountType::Liabilities);
+        result.credits = vec![Money::ZERO; self.l_index + 1];
+        result.debits = vec![Money::ZERO; self.l_index + 1];
+        result.rec_credits = vec![Money::ZERO; self.l_index + 1];
+        result.rec_debits = vec![Money::ZERO; self.l_index + 1];
+        for account in self.accounts().iter().filter(|a| a.t == AccountType::None) {
+            result.debits = zip(result.debits, account.rec_debits.iter().copied())
+                .map(|(a, b)| a + b)
+                .collect_vec();
+            result.credits = zip(result.credits, account.rec_credits.iter().copied())
+                .map(|(a, b)| a + b)
+                .collect_vec();
+        }
+        result
+    }
+
+    /// Whether `transaction` or one of its entries matches the filters.
+    pub(crate) fn is_shown(&self, transaction: &Transaction) -> bool {
+        self.filters.iter().all(|f| {
+            f.matches(&transaction.metadata)
+                || transaction.entries.iter().any(|e| f.matches(&e.metadata))
+        })
+    }
+
+    /// Names of the dimensions used in the entries of the ledger.
+    pub(crate) fn dimension_names(&self) -> Vec<&str> {
+        self.ledger
+            .iter()
+            .flat_map(|t| &t.entries)
+            .flat_map(|e| e.metadata.dimensions.keys())
+            .map(String::as_str)
+            .sorted()
+            .dedup()
+            .collect()
+    }
+
+    /// Sums of the entries of each account by the value of dimension `name`,
+    /// `None` for entries without it.
+    pub(crate) fn dimension_sums(&self, name: &str) -> HashMap<i32, BTreeMap<Option<&str>, Money>> {
+        let mut sums: HashMap<i32, BTreeMap<Option<&str>, Money>> = HashMap::new();
+        for entry in self.ledger.iter().flat_map(|t| &t.entries) {
+            *sums
+                .entry(entry.account)
+                .or_default()
+                .entry(entry.metadata.dimensions.get(name).map(String::as_str))
+                .or_default() += entry.amount;
+        }
+        sums
+    }
+
+    /// Source of the next year's ledger: the account map, the next fiscal
+    /// period, the top-level names and the closing balance sheet, including
+    /// the result of the year, as `§ AVAUS`. Open invoices of the sales and
+    /// purchase ledgers are carried as their own entries with their
+    /// reference, due date and party.
+    pub fn next_year_source(&self) -> Result<String, Diagnostic> {
+        let (start, end) = self.next_period().ok_or_else(|| {
+            Diagnostic::without_span("tilikauden päättymispäivää ei tiedetä; aseta loppu")
+        })?;
+        let mut entries = self.carried_entries(self.closing_balances())?;
+        let mut invoices = Vec::new();
+        let ledgers = [
+            (self.sales_ledger(), "asiakas", 1),
+            (self.purchase_ledger(), "toimittaja", -1),
+        ];
+        for (items, party_key, sign) in ledgers {
+            for invoice in items.iter().flat_map(|i| &i.invoices) {
+                if invoice.open.is_zero() {
+                    continue;
                 }
-                SAccount::Alias(alias) => {
-                    if let Some(n) = self.scopes.collapsed().aliases.get(&alias) {
-                        self.scopes.push(Scope {
-                            auto_balance: Some(*n),
-                            ..Default::default()
-                        });
-                        self.exec_statements(body);
-                        self.scopes.pop();
-                    } else {
-                        panic!("undefined alias")
+                let amount = invoice.open * sign;
+                match entries.iter_mut().find(|(n, _)| *n == invoice.account) {
+                    Some((_, balance)) => *balance -= amount,
+                    None => entries.push((invoice.account, -amount)),
+                }
+                let mut lines = format!(
+                    "{}\n  {}\n  {}: {}\n  {DUE_DATE}: {}\n",
+                    invoice.account,
+                    format::amount(amount),
+                    invoice.key,
+                    format::quote(&invoice.reference),
+                    format::date(invoice.due)
+                );
+                if let Some(party) = &invoice.party {
+                    lines += &format!("  {party_key}: {}\n", format::quote(party));
+                }
+                invoices.push(lines);
+            }
+        }
+        let mut out = format!(
+            "§ TILIKARTTA\n{}\n\n§ TIEDOT\nalku = {}\nloppu = {}\n",
+            self.account_map_string(),
+            format::date(start),
+            format::date(end)
+        );
+        for (key, value) in self.options[0].iter().sorted() {
+            if !["alku", "loppu", "lyhenne"].contains(&key.as_str()) {
+                out += &format!("{key} = {}\n", format::quote(value));
+            }
+        }
+        for (name, n) in self.scopes[0].aliases.iter().sorted() {
+            out += &format!("{name} = {n}\n");
+        }
+        out += "\n§ AVAUS\n";
+        for (n, amount) in entries.into_iter().filter(|(_, a)| !a.is_zero()) {
+            out += &format!("{n}: {}\n", format::amount(amount));
+        }
+        for lines in invoices {
+            out += &lines;
+        }
+        out += "\n§ KIRJANPITO\n";
+        Ok(format::format_source(&out, 0).unwrap_or(out))
+    }
+
+    /// Adds the opening balances, from `§ AVAUS` or carried forward from the
+    /// previous year, as the first transaction of the ledger. If both are
+    /// given they must agree.
+    fn exec_opening(&mut self) {
+        let explicit = std::mem::take(&mut self.opening);
+        let carried = match self.carried_forward.take().map(|c| self.carried_entries(c)) {
+            Some(Ok(entries)) => Some(entries),
+            Some(Err(e)) => {
+                self.errors.push(e);
+                return;
+            }
+            None => None,
+        };
+        if explicit.is_empty() && carried.is_none() {
+            return;
+        }
+        let span = explicit.first().map(|e| e.span);
+        let start = self
+            .period(self.l_index)
+            .map(|(start, _)| start)
+            .or_else(|| {
+                Date::from_calendar_date(self.year, Month::January, 1)
+                    .ok()
+                    .filter(|_| self.year != 0)
+            });
+        let Some(start) = start else {
+            self.errors.push(Diagnostic {
+                span,
+                message: "avaavalle taseelle ei löydy päivämäärää; aseta tilikauden alku".into(),
+            });
+            return;
+        };
+        let from_carried = explicit.is_empty();
+        let entries = if from_carried {
+            carried
+                .iter()
+                .flatten()
+                .map(|(n, amount)| SEntry {
+                    account: SAccount::N(*n),
+                    amounts: vec![SAuto::Val((*amount).into())],
+                    vat: None,
+                    metadata: Metadata::default(),
+                    span: Span::default(),
+                })
+                .collect()
+        } else {
+            explicit
+        };
+        let count = self.ledger.len();
+        if let Err(e) = self.exec_transaction(
+            Some(start),
+            "Avaava tase".into(),
+            entries,
+            Some("AT".into()),
+            false,
+            span.unwrap_or_default(),
+        ) {
+            // carried-forward entries have no place in the source
+            self.errors.push(if from_carried {
+                Diagnostic::without_span(format!(
+                    "edellisen tilikauden loppusaldoista muodostettu avaava tase: {}",
+                    e.message
+                ))
+            } else {
+                e
+            });
+            return;
+        }
+        if self.ledger.len() > count {
+            let opening = self.ledger.pop().unwrap();
+            if let (Some(carried), Some(span)) = (carried, span) {
+                self.compare_opening(&opening, carried, span);
+            }
+            self.ledger.insert(0, opening);
+            self.has_opening = true;
+        }
+    }
+
+    /// Turns the previous year's closing balances into opening entries. The
+    /// result of the previous year goes to the account named `tulostili`.
+    fn carried_entries(&self, closing: ClosingBalances) -> Result<Vec<(i32, Money)>, Diagnostic> {
+        let mut entries = closing.accounts;
+        if !closing.result.is_zero() {
+            let account = self.scopes[0].aliases.get("tulostili").ok_or_else(|| {
+                Diagnostic::without_span(
+                    "edellisen tilikauden tulokselle ei ole tiliä; määritä nimi tulostili",
+                )
+            })?;
+            entries.push((*account, closing.result));
+        }
+        let precision = self.precision(self.l_index);
+        for (n, amount) in &entries {
+            if !self.account_dict.contains_key(n) {
+                return Err(Diagnostic::without_span(format!(
+                    "edellisen tilikauden tiliä {n} ei ole tilikartassa"
+                )));
+            }
+            if !amount.fits_precision(precision) {
+                return Err(Diagnostic::without_span(format!(
+                    "edellisen tilikauden tilin {n} loppusaldossa {} on enemmän desimaaleja kuin sallitut {precision}",
+                    format::amount(amount.abs())
+                )));
+            }
+        }
+        Ok(entries)
+    }
+
+    fn compare_opening(&mut self, opening: &Transaction, carried: Vec<(i32, Money)>, span: Span) {
+        let mut written: BTreeMap<i32, Money> = BTreeMap::new();
+        for Entry {
+            account: n, amount, ..
+        } in &opening.entries
+        {
+            *written.entry(*n).or_default() += *amount;
+        }
+        let mut expected: BTreeMap<i32, Money> = BTreeMap::new();
+        for (n, amount) in carried {
+            *expected.entry(n).or_default() += amount;
+        }
+        for n in written.keys().chain(expected.keys()).sorted().dedup() {
+            let written = written.get(n).copied().unwrap_or_default();
+            let expected = expected.get(n).copied().unwrap_or_default();
+            if written != expected {
+                self.errors.push(Diagnostic::new(
+                    span,
+                    format!(
+                        "avaavan taseen tilin {n} summa {} ei vastaa edellisen tilikauden loppusaldoa {}",
+                        self.amount_as_string(written, true),
+                        self.amount_as_string(expected, true)
+                    ),
+                ));
+            }
+        }
+    }
+
+    fn exec_assertion(
+        &mut self,
+        account: SAccount,
+        amount: Money,
+        statement: bool,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        let account = match account {
+            SAccount::N(n) => n,
+            SAccount::Alias(alias) => self.resolve_alias(&alias, span)?,
+        };
+        self.check_account(account, span)?;
+        let date = self.scopes.collapsed().date.ok_or_else(|| {
+            Diagnostic::new(
+                span,
+                if statement {
+                    "tiliotteen saldolta puuttuu päivämäärä"
+                } else {
+                    "tarkistukselta puuttuu päivämäärä"
+                },
+            )
+        })?;
+        let keyword = if statement { "tiliote" } else { "tarkista" };
+        let date = self.resolve_date(date, &format!("{keyword} {account}"), span)?;
+        if self.l_index == 0 {
+            self.assertions.push(Assertion {
+                account,
+                date,
+                amount,
+                statement,
+                span,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks the `tarkista` lines against the balances of the main ledger.
+    /// Balances have the same sign as in the general ledger.
+    fn check_assertions(&mut self) {
+        for assertion in self.assertions.iter().filter(|a| !a.statement) {
+            let sign = match self.account_type(assertion.account) {
+                Some(AccountType::Assets) => 1,
+                _ => -1,
+            };
+            let balance = self
+                .ledger
+                .iter()
+                .filter(|t| t.date <= assertion.date)
+                .flat_map(|t| &t.entries)
+                .filter(|e| e.account == assertion.account)
+                .map(|e| e.amount)
+                .sum::<Money>()
+                * sign;
+            if balance != assertion.amount {
+                self.errors.push(Diagnostic::new(
+                    assertion.span,
+                    format!(
+                        "tilin {} saldo {} on {}, odotettiin {}",
+                        assertion.account,
+                        self.fmt_date(assertion.date),
+                        self.amount_as_string(balance, true),
+                        self.amount_as_string(assertion.amount, true)
+                    ),
+                ));
+            }
+        }
+    }
+
+    /// Marks cleared the opening balances and the entries dated on or before
+    /// the latest `tiliote` of their account.
+    fn clear_reconciled(&mut self) {
+        let mut latest: HashMap<i32, Date> = HashMap::new();
+        for a in self.assertions.iter().filter(|a| a.statement) {
+            let date = latest.entry(a.account).or_insert(a.date);
+            *date = (*date).max(a.date);
+        }
+        let has_opening = self.has_opening;
+        for (i, t) in self.ledger.iter_mut().enumerate() {
+            let opening = has_opening && i == 0;
+            for e in &mut t.entries {
+                if opening || latest.get(&e.account).is_some_and(|d| t.date <= *d) {
+                    e.metadata.cleared = true;
+                }
+            }
+        }
+    }
+
+    /// Reconciliations of the accounts with a `tiliote` line and of the
+    /// account named `pankkitili`. Amounts have the same sign as in the
+    /// general ledger.
+    pub fn reconciliations(&self) -> Vec<Reconciliation<'_>> {
+        let mut accounts: BTreeSet<i32> = self
+            .assertions
+            .iter()
+            .filter(|a| a.statement)
+            .map(|a| a.account)
+            .collect();
+        accounts.extend(self.alias("pankkitili"));
+        accounts.retain(|n| self.account_dict.contains_key(n));
+        accounts
+            .into_iter()
+            .map(|account| {
+                let sign = match self.account_type(account) {
+                    Some(AccountType::Assets) => 1,
+                    _ => -1,
+                };
+                let statement = self
+                    .assertions
+                    .iter()
+                    .filter(|a| a.statement && a.account == account)
+                    .max_by_key(|a| a.date)
+                    .map(|a| (a.date, a.amount));
+                let entries = self.ledger.iter().flat_map(|t| {
+                    t.entries
+                        .iter()
+                        .filter(move |e| e.account == account)
+                        .map(move |e| (t, e))
+                });
+                let balance = entries
+                    .clone()
+                    .filter(|(t, _)| statement.is_none_or(|(date, _)| t.date <= date))
+                    .map(|(_, e)| e.amount)
+                    .sum::<Money>()
+                    * sign;
+                Reconciliation {
+                    account,
+                    statement,
+                    balance,
+                    uncleared: entries
+                        .filter(|(_, e)| !e.metadata.cleared)
+                        .map(|(t, e)| (t, e.amount * sign))
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    fn exec_rate(
+        &mut self,
+        date: Option<Date>,
+        currency: String,
+        rate: Money,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        let date = match date.or(self.scopes.collapsed().date) {
+            Some(date) if date.year() == 0 => {
+                return Err(Diagnostic::new(span, "kurssin päivämäärästä puuttuu vuosi"));
+            }
+            Some(date) => date,
+            None => Date::MIN,
+        };
+        self.rates.entry(currency).or_default().push((date, rate));
+        Ok(())
+    }
+
+    /// VAT rate of the account from the account map. Only income statement
+    /// accounts have one.
+    fn default_vat(&self, account: i32) -> Option<Money> {
+        let account = self.account_dict.get(&account)?.borrow();
+        account.vat.filter(|_| account.t == AccountType::None)
+    }
+
+    /// The account VAT on entries to `account` is posted to and whether it
+    /// is on sales: the account named `alv_maksettava` for sales accounts
+    /// and `alv_vähennettävä` for purchase accounts.
+    fn vat_account(&self, account: i32, span: Span) -> Result<(i32, bool), Diagnostic> {
+        self.check_account(account, span)?;
+        let (name, sales) = match self.account_dict[&account].borrow().vat_side {
+            Some(VatSide::Sales) => ("alv_maksettava", true),
+            Some(VatSide::Purchases) => ("alv_vähennettävä", false),
+            None => {
+                return Err(Diagnostic::new(
+                    span,
+                    format!("tiliä {account} ei ole merkitty tilikartassa myynti- tai ostotiliksi"),
+                ));
+            }
+        };
+        let vat_account = self.resolve_alias(name, span)?;
+        self.check_account(vat_account, span)?;
+        Ok((vat_account, sales))
+    }
+
+    /// The VAT part of the gross amount `gross` on `account`, as an entry
+    /// to the VAT account, and whether it is on sales.
+    fn vat_entry(
+        &self,
+        account: i32,
+        gross: Money,
+        rate: Money,
+        span: Span,
+    ) -> Result<(Entry, bool), Diagnostic> {
+        let tax = gross
+            .vat_part(rate, self.precision(self.l_index))
+            .ok_or_else(|| Diagnostic::new(span, "summa on liian suuri"))?;
+        let (vat_account, sales) = self.vat_account(account, span)?;
+        let entry = Entry {
+            account: vat_account,
+            amount: tax,
+            foreign: None,
+            metadata: Metadata::default(),
+        };
+        Ok((entry, sales))
+    }
+
+    /// The currency amounts of ledger `li` are in unless given, set with
+    /// the `valuutta` option.
+    pub fn base_currency(&self, li: usize) -> &str {
+        self.options[li]
+            .get("valuutta")
+            .map_or("EUR", |c| c.as_str())
+    }
+
+    /// Converts `amount` to the base currency, at the rate given with the
+    /// amount or the latest `§ KURSSIT` rate on or before `date`.
+    fn base_amount(
+        &self,
+        amount: &SAmount,
+        date: Date,
+        span: Span,
+    ) -> Result<(Money, Option<Foreign>), Diagnostic> {
+        let Some(currency) = amount
+            .currency
+            .as_ref()
+            .filter(|c| *c != self.base_currency(self.l_index))
+        else {
+            return Ok((amount.value, None));
+        };
+        let rate = match amount.rate {
+            Some(rate) => rate,
+            None => self
+                .rates
+                .get(currency)
+                .and_then(|rates| {
+                    rates
+                        .iter()
+                        .filter(|(d, _)| *d <= date)
+                        .max_by_key(|(d, _)| *d)
+                })
+                .map(|(_, rate)| *rate)
+                .ok_or_else(|| {
+                    Diagnostic::new(
+                        span,
+                        format!(
+                            "valuutalle {currency} ei ole kurssia {}",
+                            self.fmt_date(date)
+                        ),
+                    )
+                })?,
+        };
+        let value = amount
+            .value
+            .convert(rate, self.precision(self.l_index))
+            .ok_or_else(|| Diagnostic::new(span, "summa on liian suuri"))?;
+        Ok((
+            value,
+            Some(Foreign {
+                currency: currency.clone(),
+                amount: amount.value,
+            }),
+        ))
+    }
+
+    /// Fills in the year of a year-less date, from the fiscal period if one
+    /// is set and otherwise from the previous dated transaction, and checks
+    /// that the date falls within the fiscal period.
+    fn resolve_date(
+        &mut self,
+        date: Date,
+        description: &str,
+        span: Span,
+    ) -> Result<Date, Diagnostic> {
+        let period = self.period(self.l_index);
+        let year = date.year();
+        let resolved_date = if year == 0 {
+            let with_year = |year: i32| {
+                date.replace_year(year).map_err(|_| {
+                    Diagnostic::new(
+                        span,
+                        format!(
+                            "päivämäärää {}.{}. ei ole vuonna {year}",
+                            date.day(),
+                            date.month() as u8,
+                        ),
+                    )
+                })
+            };
+            match period {
+                Some((start, _)) => {
+                    let in_start_year = with_year(start.year());
+                    match in_start_year {
+                        Ok(d) if d >= start => d,
+                        _ => with_year(start.year() + 1)?,
                     }
                 }
-            },
-            SHeader::Dummy => {
-                self.scopes.push(Scope::default());
-                self.exec_statements(body);
-                self.scopes.pop();
+                None => with_year(self.year)?,
             }
+        } else if year < 100 {
+            date.replace_year(year + 2000)
+                .map_err(|_| Diagnostic::new(span, format!("virheellinen vuosi {year}")))?
+        } else {
+            self.year = year;
+            date
+        };
+        if let Some((start, end)) = period
+            && !(start..=end).contains(&resolved_date)
+        {
+            return Err(Diagnostic::new(
+                span,
+                format!(
+                    "tapahtuman \"{description}\" päivämäärä {} on tilikauden {}–{} ulkopuolella",
+                    self.fmt_date(resolved_date),
+                    self.fmt_date(start),
+                    self.fmt_date(end)
+                ),
+            ));
         }
+        Ok(resolved_date)
     }
 
     fn exec_transaction(
@@ -551,66 +1905,180 @@ impl Ledger {
         entries: Vec<SEntry>,
         doc: Option<String>,
         budget: bool,
-    ) {
+        span: Span,
+    ) -> Result<(), Diagnostic> {
         let scope = self.scopes.collapsed();
         let resolved_date = if budget {
             self.ledger_type = LedgerType::Budget;
             Date::MIN
         } else {
-            let mut resolved_date =
-                date.unwrap_or_else(|| scope.date.expect("no date for transaction"));
-            let year = resolved_date.year();
-            if year == 0 {
-                resolved_date = resolved_date.replace_year(self.year).unwrap();
-            } else if year < 100 {
-                resolved_date = resolved_date.replace_year(year + 2000).unwrap();
-            } else {
-                self.year = year;
-            }
-            resolved_date
+            let date = date.or(scope.date).ok_or_else(|| {
+                Diagnostic::new(
+                    span,
+                    format!("tapahtumalta \"{description}\" puuttuu päivämäärä"),
+                )
+            })?;
+            self.resolve_date(date, &description, span)?
         };
         let mut resolved_entries = Vec::new();
-        let mut balance = 0;
+        let mut balance = Money::ZERO;
+        let precision = self.precision(self.l_index);
         let mut auto_index = None;
+        let mut vat_entries = Vec::new();
 
         for entry in &entries {
             let account_number = match &entry.account {
-                SAccount::N(n) => *n / 100,
-                SAccount::Alias(s) => *scope.aliases.get(s).expect("alias not found"),
+                SAccount::N(n) => *n,
+                SAccount::Alias(s) => self.resolve_alias(s, entry.span)?,
             };
+            self.check_account(account_number, entry.span)?;
+            let mut metadata = entry.metadata.clone();
+            metadata.dimensions = scope.dimensions.clone();
+            metadata
+                .dimensions
+                .extend(entry.metadata.dimensions.clone());
 
             for amount in &entry.amounts {
                 match amount {
-                    SAuto::Val(n) => {
-                        // let n = if budget { -*n } else { *n };
-                        let n = *n;
-                        resolved_entries.push((account_number, n));
-                        balance += n;
+                    SAuto::Val(amount) => {
+                        let (n, foreign) = self.base_amount(amount, resolved_date, entry.span)?;
+                        if !n.fits_precision(precision) {
+                            return Err(Diagnostic::new(
+                                entry.span,
+                                format!(
+                                    "summassa {} on enemmän desimaaleja kuin sallitut {precision}",
+                                    format::amount(n.abs())
+                                ),
+                            ));
+                        }
+                        let rate = if budget {
+                            None
+                        } else {
+                            entry.vat.or_else(|| self.default_vat(account_number))
+                        };
+                        if let Some(rate) = rate {
+                            let (tax, sales) =
+                                self.vat_entry(account_number, n, rate, entry.span)?;
+                            resolved_entries.push(Entry {
+                                account: account_number,
+                                amount: n - tax.amount,
+                                foreign,
+                                metadata: metadata.clone(),
+                            });
+                            vat_entries.push(VatEntry {
+                                date: resolved_date,
+                                rate,
+                                net: n - tax.amount,
+                                tax: tax.amount,
+                                sales,
+                            });
+                            if !tax.amount.is_zero() {
+                                resolved_entries.push(Entry {
+                                    metadata: metadata.clone(),
+                                    ..tax
+                                });
+                            }
+                        } else {
+                            resolved_entries.push(Entry {
+                                account: account_number,
+                                amount: n,
+                                foreign,
+                                metadata: metadata.clone(),
+                            });
+                        }
+                        balance = balance
+                            .checked_add(n)
+                            .ok_or_else(|| Diagnostic::new(entry.span, "summa on liian suuri"))?;
                     }
                     SAuto::Auto => {
-                        resolved_entries.push((account_number, 0));
-                        auto_index = Some(resolved_entries.len() - 1);
+                        resolved_entries.push(Entry {
+                            account: account_number,
+                            amount: Money::ZERO,
+                            foreign: None,
+                            metadata: metadata.clone(),
+                        });
+                        let rate = if budget {
+                            None
+                        } else {
+                            entry.vat.or_else(|| self.default_vat(account_number))
+                        };
+                        auto_index = Some((resolved_entries.len() - 1, rate, entry.span));
                     }
                 }
             }
         }
 
-        if let Some(i) = auto_index {
-            resolved_entries[i].1 = -balance;
-        } else if balance != 0 {
+        if let Some((i, rate, entry_span)) = auto_index {
+            let gross = -balance;
+            resolved_entries[i].amount = gross;
+            if let Some(rate) = rate {
+                let account = resolved_entries[i].account;
+                let (tax, sales) = self.vat_entry(account, gross, rate, entry_span)?;
+                resolved_entries[i].amount = gross - tax.amount;
+                vat_entries.push(VatEntry {
+                    date: resolved_date,
+                    rate,
+                    net: gross - tax.amount,
+                    tax: tax.amount,
+                    sales,
+                });
+                if !tax.amount.is_zero() {
+                    resolved_entries.push(Entry {
+                        metadata: resolved_entries[i].metadata.clone(),
+                        ..tax
+                    });
+                }
+            }
+        } else if !balance.is_zero() {
             if let Some(ab) = scope.auto_balance {
-                resolved_entries.push((ab / 100, -balance));
+                self.check_account(ab, span)?;
+                resolved_entries.push(Entry {
+                    account: ab,
+                    amount: -balance,
+                    foreign: None,
+                    metadata: Metadata {
+                        dimensions: scope.dimensions.clone(),
+                        ..Default::default()
+                    },
+                });
             } else if !budget {
-                panic!("unbalanced transaction: \"{description}\"");
+                return Err(Diagnostic::new(
+                    span,
+                    format!(
+                        "tapahtuma \"{description}\" ei täsmää: debet ja kredit eroavat {}",
+                        self.amount_as_string(balance, true)
+                    ),
+                ));
             }
         }
 
-        for (n, amt) in &resolved_entries {
-            let acc = self
-                .account_dict
-                .get(n)
-                .unwrap_or_else(|| panic!("account {} not defined", n));
-            acc.borrow_mut().add_amount(*amt, self.l_index);
+        // Check all the totals before posting, so that an overflow leaves
+        // no entries of the transaction posted.
+        let mut totals: HashMap<i32, (Money, Money)> = HashMap::new();
+        for Entry {
+            account: n, amount, ..
+        } in &resolved_entries
+        {
+            let (debits, credits) = totals.entry(*n).or_insert_with(|| {
+                let account = self.account_dict[n].borrow();
+                (account.debits[self.l_index], account.credits[self.l_index])
+            });
+            let total = if amount.is_positive() {
+                debits
+            } else {
+                credits
+            };
+            *total = total
+                .checked_add(amount.abs())
+                .ok_or_else(|| Diagnostic::new(span, format!("tilin {n} summa on liian suuri")))?;
+        }
+        for Entry {
+            account: n, amount, ..
+        } in &resolved_entries
+        {
+            self.account_dict[n]
+                .borrow_mut()
+                .add_amount(*amount, self.l_index);
         }
 
         if self.l_index == 0 {
@@ -629,13 +2097,527 @@ impl Ledger {
                 "".into()
             };
 
+            self.vat.append(&mut vat_entries);
             self.ledger.push(Transaction {
                 date: resolved_date,
                 description,
                 entries: resolved_entries,
+                metadata: Metadata {
+                    dimensions: scope.dimensions,
+                    ..Default::default()
+                },
                 n: 0,
-                doc: doc,
+                doc,
+                recurring: false,
             });
         }
+        Ok(())
+    }
+}
+
+/// The same day and month in `year`; 29.2. becomes 1.3. in common years.
+fn same_day(date: Date, year: i32) -> Date {
+    date.replace_year(year)
+        .unwrap_or_else(|_| Date::from_calendar_date(year, Month::March, 1).unwrap())
+}
+
+/// The dates from `start` to `end` at `frequency`. A day missing from a
+/// month, like 31.4., falls on the last day of the month.
+fn occurrences(frequency: Frequency, start: Date, end: Date) -> Vec<Date> {
+    let months = match frequency {
+        Frequency::Weekly => 0,
+        Frequency::Monthly => 1,
+        Frequency::Quarterly => 3,
+        Frequency::Yearly => 12,
+    };
+    (0..)
+        .map_while(|i| {
+            let date = if months == 0 {
+                start.checked_add(Duration::weeks(i))
+            } else {
+                add_months(start, i as i32 * months)
+            };
+            date.filter(|d| *d <= end)
+        })
+        .collect()
+}
+
+fn add_months(date: Date, months: i32) -> Option<Date> {
+    let index = date.year() * 12 + date.month() as i32 - 1 + months;
+    let month = Month::try_from((index.rem_euclid(12) + 1) as u8).ok()?;
+    let year = index.div_euclid(12);
+    let day = date.day().min(month.length(year));
+    Date::from_calendar_date(year, month, day).ok()
+}
+
+/// Whether the file `name` is named after `doc`: its name is the doc followed
+/// by the extension, or by a separator and anything. `17:2` is written as
+/// `17-2` in file names.
+fn is_attachment_of(name: &str, doc: &str) -> bool {
+    let base_doc = doc.split(':').next().unwrap_or(doc);
+    [doc.replace(':', "-").as_str(), base_doc]
+        .iter()
+        .any(|doc| {
+            name.strip_prefix(doc)
+                .is_some_and(|rest| rest.starts_with(|c: char| !c.is_alphanumeric()))
+        })
+}
+
+/// Parses a date option, written as `d.m.yyyy` or `yyyy-mm-dd`.
+fn option_date(value: &str) -> Option<Date> {
+    Date::parse(
+        value,
+        format_description!("[day padding:none].[month padding:none].[year]"),
+    )
+    .or_else(|_| Date::parse(value, format_description!("[year]-[month]-[day]")))
+    .ok()
+    .filter(|d| d.year() != 0)
+}
+
+/// Parses a `päivämäärämuoto` value: `suomi` (14.3.2025), `iso`
+/// (2025-03-14) or a format description such as `[day].[month].[year]`.
+fn date_format(value: &str) -> Option<OwnedFormatItem> {
+    match value {
+        "suomi" => Some(
+            format_description!("[day padding:none].[month padding:none].[year padding:none]")
+                .into(),
+        ),
+        "iso" => Some(format_description!("[year]-[month]-[day]").into()),
+        f => format_description::parse_owned::<2>(f)
+            .ok()
+            .filter(|f| Date::MIN.format(f).is_ok()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn parse(source: &str) -> Ledger {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.tamp", source);
+        Ledger::from_source(source, file, None, &mut sources)
+    }
+
+    /// Parses a ledger that must have no errors.
+    pub(crate) fn parse_valid(source: &str) -> Ledger {
+        let ledger = parse(source);
+        assert!(ledger.errors.is_empty(), "{:?}", ledger.errors);
+        ledger
+    }
+
+    const ACCOUNTS: &str = "§ TILIKARTTA\n+ \"Vastaavaa\"\n  1910 \"Pankki\"\n\"Tulot\"\n  3000 \"Myynti\"\n§ KIRJANPITO\n";
+
+    #[test]
+    fn overflow() {
+        let source = format!(
+            "{ACCOUNTS}1.1.2025 \"a\"\n  1910: 900000000000000\n  3000: AUTO\n2.1.2025 \"b\"\n  3000: 100000000000000\n  1910: 100000000000000\n  3000: AUTO\n"
+        );
+        let ledger = parse(&source);
+        assert_eq!(ledger.errors[0].message, "tilin 1910 summa on liian suuri");
+        // No entry of the failed transaction is posted
+        assert!(ledger.get_account(3000).unwrap().debits[0].is_zero());
+    }
+
+    #[test]
+    fn overflow_in_sums() {
+        let source = "§ TILIKARTTA\n+ \"Vastaavaa\"\n  1910 \"Pankki\"\n  1920 \"Säästöt\"\n\"Tulot\"\n  3000 \"Myynti\"\n  3010 \"Muut\"\n§ KIRJANPITO\n1.1.2025 \"a\"\n  1910: 500000000000000\n  3000: AUTO\n2.1.2025 \"b\"\n  1920: 500000000000000\n  3010: AUTO\n";
+        let errors = parse(source).errors;
+        assert_eq!(
+            errors[0].message,
+            "tilin \"Vastaavaa\" summa on liian suuri"
+        );
+        let source = source.replace("  1920", "1920").replace("  3010", "3010");
+        let errors = parse(&source).errors;
+        assert_eq!(errors[0].message, "kirjanpidon summa on liian suuri");
+    }
+
+    #[test]
+    fn undefined_account() {
+        let source = format!("{ACCOUNTS}1.1.2025 \"x\"\n  1910: 5\n  3999: -5\n");
+        let errors = parse(&source).errors;
+        assert_eq!(errors[0].message, "tili 3999 ei ole määritelty");
+        assert_eq!(errors[0].span, Some(Span::new(0, 9, 3)));
+    }
+
+    #[test]
+    fn collects_all_errors() {
+        let source = format!(
+            "{ACCOUNTS}1.1.2025\n  \"a\"\n    1910: x\n  \"b\"\n    1910: 5\n    3000: -4\n  \"c\"\n    1910: 5\n    3000: -5\n("
+        );
+        let ledger = parse(&source);
+        let lines = ledger
+            .errors
+            .iter()
+            .map(|e| e.span.unwrap().line)
+            .collect_vec();
+        assert_eq!(lines, vec![9, 10, 16]);
+        assert_eq!(ledger.ledger.len(), 1);
+    }
+
+    #[test]
+    fn include() {
+        let dir = std::env::temp_dir().join(format!("tampio-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("osat")).unwrap();
+        fs::write(dir.join("osat/tilit.tamp"), ACCOUNTS).unwrap();
+        fs::write(
+            dir.join("osat/silmukka.tamp"),
+            "§ SISÄLLYTÄ \"../pää.tamp\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("pää.tamp"),
+            "§ SISÄLLYTÄ \"osat/tilit.tamp\"\n1.1.2025 \"x\"\n  1910: 5\n  3000: -5\n§ SISÄLLYTÄ \"osat/silmukka.tamp\"\n",
+        )
+        .unwrap();
+        let mut sources = SourceMap::new();
+        let ledger = Ledger::from_path(dir.join("pää.tamp").to_str().unwrap(), None, &mut sources);
+        fs::remove_dir_all(&dir).unwrap();
+        let ledger = ledger.unwrap();
+        assert_eq!(ledger.ledger.len(), 1);
+        assert_eq!(ledger.errors.len(), 1);
+        let span = ledger.errors[0].span.unwrap();
+        assert!(sources.name(span.file).ends_with("silmukka.tamp"));
+    }
+
+    #[test]
+    fn fiscal_period() {
+        let source = format!(
+            "§ TIEDOT\nalku = 1.7.2024\nloppu = \"2025-06-30\"\n{ACCOUNTS}1.8.\n  \"a\"\n    1910: 5\n    3000: -5\n1.3.\n  \"b\"\n    1910: 5\n    3000: -5\n1.7.2025\n  \"c\"\n    1910: 5\n    3000: -5\n"
+        );
+        let ledger = parse(&source);
+        let dates = ledger
+            .ledger
+            .iter()
+            .map(|t| t.date.to_string())
+            .collect_vec();
+        assert_eq!(dates, vec!["2024-08-01", "2025-03-01"]);
+        assert_eq!(ledger.errors.len(), 1);
+        assert_eq!(ledger.errors[0].span.unwrap().line, 19);
+    }
+
+    #[test]
+    fn balance_assertion() {
+        let source = format!(
+            "{ACCOUNTS}1.1.2025\n  \"a\"\n    1910: 1 200,5\n    3000: C 1 200,5\n2.1.2025\n  tarkista 1910 = 1 200,50\n  tarkista 3000 = 1 200,50\n  tarkista 1910 = 5\n2.1.2025: tarkista 1910 = 1 2\n2.1.2025: tarkista 1910 = 1,20 050\n"
+        );
+        let ledger = parse(&source);
+        let messages = ledger
+            .errors
+            .iter()
+            .map(|e| e.message.as_str())
+            .collect_vec();
+        assert_eq!(
+            messages,
+            [
+                "tilin 1910 saldo 2.1.2025 on 1200,50, odotettiin 5,00",
+                "numeroryhmässä '2' pitää olla kolme numeroa",
+                "odotettiin summaa"
+            ]
+        );
+    }
+
+    #[test]
+    fn opening_balances() {
+        let accounts = ACCOUNTS.replace(
+            "\"Tulot\"",
+            "- \"Vastattavaa\"\n  2250 \"Voitto\"\n\"Tulot\"",
+        );
+        let previous = parse(&format!(
+            "{accounts}1.1.2024 \"a\"\n  1910: 5\n  3000: -5\n"
+        ));
+        let mut sources = SourceMap::new();
+        let source = format!(
+            "{accounts}tulostili = 2250\n§ AVAUS\n1910: 6\n2250: -6\n§ KIRJANPITO\n1.1.2025 \"b\"\n  1910: 1\n  3000: -1\n"
+        );
+        let file = sources.add("test.tamp", &source);
+        let ledger = Ledger::from_source(
+            &source,
+            file,
+            Some(previous.closing_balances()),
+            &mut sources,
+        );
+        assert_eq!(ledger.ledger[0].description, "Avaava tase");
+        assert_eq!(ledger.ledger[0].date.to_string(), "2025-01-01");
+        let messages = ledger.errors.iter().map(|e| &e.message).collect_vec();
+        assert_eq!(
+            messages,
+            vec![
+                "avaavan taseen tilin 1910 summa 6,00 ei vastaa edellisen tilikauden loppusaldoa 5,00",
+                "avaavan taseen tilin 2250 summa −6,00 ei vastaa edellisen tilikauden loppusaldoa −5,00",
+            ]
+        );
+
+        let previous = parse(&format!(
+            "{accounts}1.1.2024 \"a\"\n  1910: 5,5\n  3000: -5,5\n"
+        ));
+        let source =
+            format!("§ TIEDOT\nalku = 1.1.2025\ndesimaalit = 0\ntulostili = 2250\n{accounts}");
+        let file = sources.add("next.tamp", &source);
+        let ledger = Ledger::from_source(
+            &source,
+            file,
+            Some(previous.closing_balances()),
+            &mut sources,
+        );
+        assert_eq!(ledger.errors.len(), 1);
+        assert_eq!(ledger.errors[0].span, None);
+        assert_eq!(
+            ledger.errors[0].message,
+            "edellisen tilikauden tilin 1910 loppusaldossa 5,50 on enemmän desimaaleja kuin sallitut 0"
+        );
+    }
+
+    #[test]
+    fn next_year() {
+        let source = format!(
+            "§ TIEDOT\nalku = 1.7.2024\nloppu = 30.6.2025\nlyhenne = \"2024\"\nvaluutta = \"USD\"\nmaksuaika = \"30\"\ntulostili = 2250\n{}1.8. \"a\"\n  1910: 5\n  3000: -5\n",
+            ACCOUNTS.replace(
+                "\"Tulot\"",
+                "- \"Vastattavaa\"\n  2250 \"Voitto\"\n\"Tulot\""
+            )
+        );
+        let next = parse(&source).next_year_source().unwrap();
+        assert!(next.contains(
+            "alku = 1.7.2025\nloppu = 30.6.2026\nmaksuaika = \"30\"\nvaluutta = \"USD\"\n"
+        ));
+        assert!(!next.contains("lyhenne"));
+        assert!(next.contains("§ AVAUS\n1910:  5,00\n2250: -5,00\n"));
+        let next = parse(&next);
+        assert!(next.errors.is_empty());
+    }
+
+    #[test]
+    fn currencies() {
+        let source = ACCOUNTS.replace("§ KIRJANPITO\n", "")
+            + "§ KURSSIT\n1.1.2025 USD 0,9\n1.2.2025\n  USD 0,8\n§ KIRJANPITO\n15.1.2025 \"a\"\n  1910: 100 USD\n  3000: -90\n15.2.2025 \"b\"\n  1910: 100 USD\n  3000: C 100 USD @ 0,75\n  3000: AUTO\n1.1.2025 \"c\"\n  1910: 1 SEK\n  3000: AUTO\n";
+        let ledger = parse(&source);
+        assert_eq!(ledger.ledger.len(), 2);
+        let b = &ledger.ledger[1];
+        assert_eq!(b.entries[0].amount, Money::from_cents(8000));
+        assert_eq!(b.entries[1].amount, Money::from_cents(-7500));
+        assert_eq!(b.entries[2].amount, Money::from_cents(-500));
+        assert_eq!(
+            b.entries[0].foreign,
+            Some(Foreign {
+                currency: "USD".into(),
+                amount: Money::from_cents(10000)
+            })
+        );
+        assert_eq!(
+            ledger.errors[0].message,
+            "valuutalle SEK ei ole kurssia 1.1.2025"
+        );
+    }
+
+    #[test]
+    fn vat() {
+        let source = "§ TILIKARTTA\n+ \"Vastaavaa\"\n  1910 \"Pankki\"\n  1763 \"ALV-saamiset\"\n- \"Vastattavaa\"\n  2939 \"ALV-velka\"\n\"Tulot\" ALV25,5 myynti\n  3000 \"Myynti\"\n  3010 \"Kirjat\" ALV14\n4000 \"Ostot\" osto\n§ TIEDOT\nalv_maksettava = 2939\nalv_vähennettävä = 1763\n§ KIRJANPITO\n1.3.2025 \"a\"\n  1910: 125,5\n  3000: -125,5\n  1910: 114\n  3010: -114\n2.3.2025 \"b\"\n  4000 ALV25.5: 125,5\n  1910: -125,5\n3.3.2025 \"Hyvitys\"\n  3000: 25,1\n  1910: -25,1\n4.3.2025 \"c\"\n  1910: -62,75\n  4000 ALV25,5: AUTO\n";
+        let ledger = parse(source);
+        assert!(ledger.errors.is_empty(), "{:?}", ledger.errors);
+        let a = &ledger.ledger[0].entries;
+        assert_eq!((a[1].account, a[1].amount), (3000, Money::from_cents(-10000)));
+        assert_eq!((a[2].account, a[2].amount), (2939, Money::from_cents(-2550)));
+        assert_eq!((a[5].account, a[5].amount), (2939, Money::from_cents(-1400)));
+        let b = &ledger.ledger[1].entries;
+        assert_eq!((b[1].account, b[1].amount), (1763, Money::from_cents(2550)));
+        // A credit note lowers the VAT due instead of adding to the deductible
+        let credit_note = &ledger.ledger[2].entries;
+        assert_eq!(
+            (credit_note[1].account, credit_note[1].amount),
+            (2939, Money::from_cents(510))
+        );
+        assert!(ledger.vat[3].sales);
+        let c = &ledger.ledger[3].entries;
+        assert_eq!((c[1].amount, c[2].account), (Money::from_cents(5000), 1763));
+        assert_eq!(ledger.vat.len(), 5);
+    }
+
+    #[test]
+    fn dimensions() {
+        let source = "§ TILIKARTTA\n+ \"Vastaavaa\"\n  1910 \"Pankki\"\n\"Tulot\"\n  3000 \"Avustukset\"\n§ KIRJANPITO\n1.3.2025 #projekti=nuoriso\n  \"a\" #tili=x\n    1910: 100\n    3000 #projekti=kesä: AUTO\n  \"b\"\n    1910: 50\n    3000: AUTO\n";
+        let ledger = parse(source);
+        assert!(ledger.errors.is_empty(), "{:?}", ledger.errors);
+        let a = &ledger.ledger[0].entries;
+        assert_eq!(a[0].metadata.dimensions.get("projekti").unwrap(), "nuoriso");
+        assert_eq!(a[0].metadata.dimensions.get("tili").unwrap(), "x");
+        assert_eq!(a[1].metadata.dimensions.get("projekti").unwrap(), "kesä");
+        let sums = ledger.dimension_sums("projekti");
+        assert_eq!(sums[&3000][&Some("kesä")], Money::from_cents(-10000));
+        assert_eq!(sums[&3000][&Some("nuoriso")], Money::from_cents(-5000));
+    }
+
+    #[test]
+    fn metadata() {
+        let source = "§ TILIKARTTA\n+ \"Vastaavaa\"\n  1910 \"Pankki\"\n\"Tulot\"\n  3000 \"Myynti\"\n§ KIRJANPITO\n1.3.2025 \"a\" #maksettu\n  maksaja: \"Matti\"\n  laskunro: 2025-17\n  1910: 100; viite: \"1232\"\n  3000 #tarkistettu: AUTO\n2.3.2025 \"b\"\n  1910: 5\n  3000: AUTO\n";
+        let mut ledger = parse(source);
+        assert!(ledger.errors.is_empty(), "{:?}", ledger.errors);
+        let a = &ledger.ledger[0];
+        assert!(a.metadata.tags.contains("maksettu"));
+        assert_eq!(a.metadata.values["maksaja"], "Matti");
+        assert_eq!(a.metadata.values["laskunro"], "2025-17");
+        assert_eq!(a.entries[0].metadata.values["viite"], "1232");
+        assert!(a.entries[1].metadata.tags.contains("tarkistettu"));
+        ledger.filters = vec![Filter::parse("tarkistettu")];
+        assert!(ledger.is_shown(&ledger.ledger[0]));
+        assert!(!ledger.is_shown(&ledger.ledger[1]));
+
+        let typo =
+            format!("{ACCOUNTS}pankki = 1910\n1.3.2025 \"c\"\n  pankki: 1OO\n  3000: AUTO\n");
+        let errors = parse(&typo).errors;
+        assert_eq!(
+            errors[0].message,
+            "virheellinen summa '1OO' tilille 'pankki'"
+        );
+    }
+
+    #[test]
+    fn attachment_names() {
+        assert!(is_attachment_of("17.pdf", "17"));
+        assert!(is_attachment_of("17_kuitti.jpg", "17"));
+        assert!(!is_attachment_of("170.pdf", "17"));
+        assert!(is_attachment_of("17-2.pdf", "17:2"));
+        assert!(is_attachment_of("17.pdf", "17:2"));
+    }
+
+    #[test]
+    fn sales_ledger() {
+        let source = "§ TILIKARTTA\n+ \"Vastaavaa\"\n  1700 \"Myyntisaamiset\"\n  1910 \"Pankki\"\n\"Tulot\"\n  3000 \"Myynti\"\n§ TIEDOT\nmyyntisaamiset = 1700\n§ KIRJANPITO\n1.3.2025 \"Lasku\"\n  asiakas: \"Matti\"\n  viite: 1232\n  eräpäivä: 15.3.\n  1700: 100\n  3000: AUTO\n2.3.2025 \"Lasku\"\n  1700: 50; viite: \"10016\"\n  3000: AUTO\n10.3.2025 \"Maksu\"\n  viite: \"00 1232\"\n  1910: 60\n  1700: AUTO\n11.3.2025 \"Maksu\"\n  1910: 5\n  1700: AUTO\n";
+        let ledger = parse(source);
+        assert!(ledger.errors.is_empty(), "{:?}", ledger.errors);
+        let items = ledger.sales_ledger().unwrap();
+        assert_eq!(items.invoices.len(), 2);
+        let matti = &items.invoices[0];
+        assert_eq!(matti.party.as_deref(), Some("Matti"));
+        assert_eq!(matti.open, Money::parse("40").unwrap());
+        assert_eq!(
+            matti.due,
+            Date::from_calendar_date(2025, Month::March, 15).unwrap()
+        );
+        assert_eq!(
+            items.invoices[1].due,
+            Date::from_calendar_date(2025, Month::March, 16).unwrap()
+        );
+        assert_eq!(items.unallocated, -Money::parse("5").unwrap());
+        assert_eq!(items.balance, Money::parse("85").unwrap());
+
+        let source = source.replace(
+            "myyntisaamiset = 1700\n",
+            "myyntisaamiset = 1700\nloppu = 31.12.2025\ntulostili = 1910\n",
+        );
+        let next = parse(&source).next_year_source().unwrap();
+        let next = parse(&next);
+        assert!(next.errors.is_empty(), "{:?}", next.errors);
+        let items = next.sales_ledger().unwrap();
+        assert_eq!(items.invoices.len(), 2);
+        let invoice = items.invoices.iter().find(|i| i.reference == "1232");
+        let invoice = invoice.unwrap();
+        assert_eq!(invoice.party.as_deref(), Some("Matti"));
+        assert_eq!(invoice.open, Money::parse("40").unwrap());
+        assert_eq!(invoice.due, matti.due);
+        assert_eq!(items.unallocated, -Money::parse("5").unwrap());
+
+        let ledger = parse(&source.replace("§ TIEDOT\n", "§ TIEDOT\nmaksuaika = 30\n"));
+        let items = ledger.sales_ledger().unwrap();
+        assert_eq!(
+            items.invoices[1].due,
+            Date::from_calendar_date(2025, Month::April, 1).unwrap()
+        );
+        let ledger = parse(&source.replace("§ TIEDOT\n", "§ TIEDOT\nmaksuaika = \"3000000\"\n"));
+        assert_eq!(ledger.sales_ledger().unwrap().invoices[1].due, Date::MAX);
+        let errors = parse(&source.replace("§ TIEDOT\n", "§ TIEDOT\nmaksuaika = \"-5\"\n")).errors;
+        assert_eq!(errors[0].message, "maksuajan pitää olla päivien määrä");
+
+        let errors = parse("1.3.2025 \"a\"\n  viite: \"1233\"\n  1910: 1\n  3000: -1\n").errors;
+        assert_eq!(errors[0].message, "virheellinen viitenumero '1233'");
+    }
+
+    #[test]
+    fn purchase_ledger() {
+        let source = "§ TILIKARTTA\n+ \"Vastaavaa\"\n  1910 \"Pankki\"\n- \"Vastattavaa\"\n  2870 \"Ostovelat\"\n    2871 \"Paperi Oy\"\n4000 \"Ostot\"\n§ TIEDOT\nostovelat = 2870\n§ KIRJANPITO\n1.3.2025 \"Paperia\"\n  laskunro: A-17\n  eräpäivä: 2025-03-31\n  4000: 80\n  2871: AUTO\n20.3.2025 \"Maksu\"\n  2871: 80; laskunro: \"A-17\"\n  1910: AUTO\n21.3.2025 \"Lasku\"\n  toimittaja: \"Vesi Oy\"\n  viite: \"1232\"\n  4000: 10\n  2870: AUTO\n";
+        let ledger = parse(source);
+        assert!(ledger.errors.is_empty(), "{:?}", ledger.errors);
+        let items = ledger.purchase_ledger().unwrap();
+        assert_eq!(items.invoices.len(), 2);
+        assert_eq!(items.invoices[0].party.as_deref(), Some("Paperi Oy"));
+        assert_eq!(items.invoices[0].account, 2871);
+        assert!(items.invoices[0].open.is_zero());
+        assert_eq!(items.invoices[1].party.as_deref(), Some("Vesi Oy"));
+        assert_eq!(items.invoices[1].open, Money::parse("10").unwrap());
+        assert_eq!(items.balance, Money::parse("10").unwrap());
+
+        let errors = parse(&source.replace("ostovelat = 2870", "ostovelat = 1910")).errors;
+        assert_eq!(
+            errors[0].message,
+            "tilin ostovelat (1910) pitää olla taseen vastattavaa-puolella"
+        );
+    }
+
+    #[test]
+    fn recurring() {
+        let source = format!(
+            "{ACCOUNTS}TOISTUVA kuukausittain 31.1.2025–31.5.2025 \"Vuokra\" V\n  3000: 500\n  1910: AUTO\n  ohita: 31.3.\n  30.4. \"Korotettu\"\n    3000: 550\n    1910: AUTO\n"
+        );
+        let ledger = parse(&source);
+        assert!(ledger.errors.is_empty(), "{:?}", ledger.errors);
+        let dates = ledger
+            .ledger
+            .iter()
+            .map(|t| format::date(t.date))
+            .collect_vec();
+        assert_eq!(dates, ["31.1.2025", "28.2.2025", "30.4.2025", "31.5.2025"]);
+        assert_eq!(ledger.ledger[2].doc, "V:3");
+        assert_eq!(ledger.ledger[2].description, "Korotettu");
+        assert!(ledger.ledger.iter().all(|t| t.recurring));
+
+        let ledger = parse(&source.replace("ohita: 31.3.", "ohita: 1.3."));
+        assert_eq!(
+            ledger.errors[0].message,
+            "1.3.2025 ei ole toistuvan tapahtuman \"Vuokra\" päivä"
+        );
+        assert_eq!(ledger.ledger.len(), 5);
+        // A failing occurrence leaves the later ones in place
+        let ledger = parse(&source.replace("    3000: 550\n", "    3000: 550\n    9999: 1\n"));
+        assert_eq!(ledger.errors.len(), 1);
+        assert_eq!(ledger.ledger.len(), 3);
+    }
+
+    #[test]
+    fn reconciliation() {
+        let source = "§ TILIKARTTA\n+ \"Vastaavaa\"\n  1910 \"Pankki\"\n- \"Vastattavaa\"\n  3000 \"Tuotot\"\n§ KIRJANPITO\n1.3.2025 \"Jäsenmaksu\"\n  1910: 100\n  3000: AUTO\n10.3.2025 \"Myynti\"\n  * 1910: 20\n  3000: AUTO\n20.3.2025 \"Myynti\"\n  1910: 5\n  3000: AUTO\n5.3.2025: tiliote 1910 = 100\n";
+        let ledger = parse(source);
+        assert!(ledger.errors.is_empty(), "{:?}", ledger.errors);
+        let cleared = |n: i32| {
+            ledger.ledger[n as usize]
+                .entries
+                .iter()
+                .map(|e| e.metadata.cleared)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(cleared(0), [true, false]);
+        assert_eq!(cleared(1), [true, false]);
+        assert_eq!(cleared(2), [false, false]);
+        let reconciliations = ledger.reconciliations();
+        assert_eq!(reconciliations.len(), 1);
+        let r = &reconciliations[0];
+        assert_eq!(
+            r.statement.map(|(_, a)| a),
+            Some(Money::parse("100").unwrap())
+        );
+        assert_eq!(r.balance, Money::parse("100").unwrap());
+        assert_eq!(r.uncleared.len(), 1);
+        assert_eq!(r.uncleared[0].1, Money::parse("5").unwrap());
+
+        let source = source.replace("5.3.2025: tiliote 1910 = 100\n", "");
+        assert!(parse(&source).reconciliations().is_empty());
+        let source = source.replace("§ KIRJANPITO", "§ TIEDOT\npankkitili = 1910\n§ KIRJANPITO");
+        let ledger = parse(&source);
+        let reconciliations = ledger.reconciliations();
+        assert_eq!(reconciliations.len(), 1);
+        assert_eq!(reconciliations[0].uncleared.len(), 2);
+
+        let ledger = parse(&source.replace("pankkitili = 1910", "pankkitili = 9999"));
+        assert_eq!(ledger.errors[0].message, "tili 9999 ei ole määritelty");
+        assert!(ledger.reconciliations().is_empty());
     }
 }
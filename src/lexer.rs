@@ -1,7 +1,7 @@
 use std::{
     cmp::Ordering,
     collections::VecDeque,
-    iter::{Peekable, repeat},
+    iter::Peekable,
     str::{Chars, Lines},
 };
 
@@ -9,11 +9,19 @@ use itertools::Itertools;
 use time::{Date, macros::format_description};
 use unicode_bidi::{BidiDataSource, HardcodedBidiData, data_source::BidiMatchedOpeningBracket};
 
+use crate::{
+    diagnostic::{Diagnostic, Span},
+    money::{MAX_DECIMALS, Money, MoneyError},
+};
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Token {
     Date(Date),
     String(String),
-    Number(i32),
+    Number(Money),
+    /// A group of digits after a number, as `234,56` in `1 234,56`. The
+    /// digits are kept to check that the group has three of them.
+    DigitGroup(String),
     Minus,
     Plus,
     Debit,
@@ -30,7 +38,32 @@ pub enum Token {
     Dedent,
     Auto,
     Section,
-    EOF,
+    At,
+    /// `#key=value`
+    Tag(String, String),
+    /// `*`, marks a transaction or an entry cleared
+    Cleared,
+    Comment(String),
+    Eof,
+}
+
+/// Returns the closing counterpart of an opening bracket.
+pub fn closing_bracket(opening: char) -> char {
+    match opening {
+        '(' => ')',
+        '[' => ']',
+        '{' => '}',
+        c => c,
+    }
+}
+
+/// Whether the digits before `chars` are followed by `-m-d`, making them
+/// an ISO 8601 date rather than a number, as in `2025-17`.
+fn is_iso_date_tail(mut chars: Peekable<Chars>) -> bool {
+    chars.next() == Some('-')
+        && chars.peeking_take_while(|c| c.is_ascii_digit()).count() > 0
+        && chars.next() == Some('-')
+        && chars.next().is_some_and(|c| c.is_ascii_digit())
 }
 
 impl Token {
@@ -49,110 +82,192 @@ impl Token {
 pub struct Lexer<'a> {
     lines: Lines<'a>,
     indent_stack: Vec<i32>,
-    token_queue: VecDeque<Token>,
+    token_queue: VecDeque<(Token, Span)>,
+    file: usize,
+    line: usize,
+    column: usize,
+    keep_comments: bool,
+    /// Whether the last token is a number written without decimals, which
+    /// a digit group may follow.
+    after_integer: bool,
+    pub errors: Vec<Diagnostic>,
 }
 
 impl<'a> Lexer<'a> {
+    #[allow(dead_code)]
     pub fn new(source: &'a str) -> Self {
+        Self::with_file(source, 0)
+    }
+
+    /// Creates a lexer whose spans refer to `file` in a [`crate::diagnostic::SourceMap`].
+    pub fn with_file(source: &'a str, file: usize) -> Self {
         Self {
             lines: source.lines(),
             indent_stack: Vec::new(),
             token_queue: VecDeque::new(),
+            file,
+            line: 0,
+            column: 1,
+            keep_comments: false,
+            after_integer: false,
+            errors: Vec::new(),
         }
     }
 
+    /// Makes the lexer emit `--` comments as [`Token::Comment`] tokens. A
+    /// line holding only a comment produces no indentation tokens.
+    pub fn keeping_comments(mut self) -> Self {
+        self.keep_comments = true;
+        self
+    }
+
+    fn span(&self) -> Span {
+        Span::new(self.file, self.line, self.column)
+    }
+
+    fn push(&mut self, token: Token) {
+        self.after_integer = false;
+        self.token_queue.push_back((token, self.span()));
+    }
+
+    fn error(&mut self, message: impl Into<String>) {
+        self.errors.push(Diagnostic::new(self.span(), message));
+    }
+
     fn tokenize_line(&mut self, line: &str) {
         let trimmed_line = line.trim();
-        if trimmed_line.is_empty() || trimmed_line.starts_with("--") {
+        let indent = line.find(|c: char| !c.is_whitespace()).unwrap_or(0) as i32;
+        let first_column = line[..indent as usize].chars().count() + 1;
+        self.column = first_column;
+        if let Some(comment) = trimmed_line.strip_prefix("--") {
+            if self.keep_comments {
+                self.push(Token::Comment(comment.to_string()));
+            }
+            return;
+        }
+        if trimmed_line.is_empty() {
             return;
         }
         let mut line_iter = trimmed_line.chars().peekable();
-        let indent = line.find(|c: char| !c.is_whitespace()).unwrap_or(0) as i32;
+        let line_length = trimmed_line.chars().count();
 
         match self.indent_stack.last().copied() {
             None => {
                 if indent > 0 {
                     self.indent_stack.push(indent);
-                    self.token_queue.push_back(Token::Indent);
+                    self.push(Token::Indent);
                 } else {
-                    self.token_queue.push_back(Token::Newline);
+                    self.push(Token::Newline);
                 }
             }
             Some(prev_indent) if indent > prev_indent => {
                 self.indent_stack.push(indent);
-                self.token_queue.push_back(Token::Indent);
+                self.push(Token::Indent);
             }
             Some(prev_indent) if indent < prev_indent => {
                 while let Some(prev_indent) = self.indent_stack.last().copied() {
                     match indent.cmp(&prev_indent) {
                         Ordering::Less => {
                             self.indent_stack.pop();
-                            self.token_queue.push_back(Token::Dedent);
+                            self.push(Token::Dedent);
                         }
                         Ordering::Greater => {
                             self.indent_stack.push(indent);
                             break;
                         }
                         _ => {
-                            self.token_queue.push_back(Token::Newline);
+                            self.push(Token::Newline);
                             break;
                         }
                     }
                 }
             }
             Some(_) => {
-                self.token_queue.push_back(Token::Newline);
+                self.push(Token::Newline);
             }
         }
 
         let mut colon_count = 0;
 
         while let Some(&c) = line_iter.peek() {
+            self.column = first_column + line_length - line_iter.clone().count();
             match c {
                 ':' => {
                     line_iter.next();
-                    self.token_queue.push_back(Token::Colon);
+                    self.push(Token::Colon);
                     colon_count += 1;
                 }
                 ';' => {
                     line_iter.next();
-                    self.token_queue.push_back(Token::Semicolon);
+                    self.push(Token::Semicolon);
                 }
                 '=' => {
                     line_iter.next();
-                    self.token_queue.push_back(Token::Assign);
+                    self.push(Token::Assign);
                 }
                 '-' => {
                     line_iter.next();
                     match line_iter.peek() {
                         Some('-') => {
+                            if self.keep_comments {
+                                line_iter.next();
+                                self.push(Token::Comment(line_iter.by_ref().collect()));
+                            }
                             break;
                         }
                         _ => {
-                            self.token_queue.push_back(Token::Minus);
+                            self.push(Token::Minus);
                             continue;
                         }
                     }
                 }
-                '\u{2212}' => {
+                '\u{2212}' | '\u{2013}' => {
                     line_iter.next();
-                    self.token_queue.push_back(Token::Minus);
+                    self.push(Token::Minus);
                 }
                 '+' => {
                     line_iter.next();
-                    self.token_queue.push_back(Token::Plus);
+                    self.push(Token::Plus);
                 }
                 '§' => {
                     line_iter.next();
-                    self.token_queue.push_back(Token::Section);
+                    self.push(Token::Section);
+                }
+                '@' => {
+                    line_iter.next();
+                    self.push(Token::At);
+                }
+                '*' => {
+                    line_iter.next();
+                    self.push(Token::Cleared);
+                }
+                '#' => {
+                    line_iter.next();
+                    self.tag(&mut line_iter);
                 }
                 c if c.is_ascii_digit() => {
-                    let st = line_iter
+                    let mut st: String = line_iter
                         .peeking_take_while(|c| c.is_ascii_digit() || *c == '.' || *c == ',')
                         .collect();
-                    if let Some(t) = self.date_or_number(st) {
-                        self.token_queue.push_back(t);
+                    if st.len() == 4 && is_iso_date_tail(line_iter.clone()) {
+                        st.extend(
+                            line_iter.peeking_take_while(|c| c.is_ascii_digit() || *c == '-'),
+                        );
+                    }
+                    let integer = !st.contains([',', '.', '-']);
+                    if self.after_integer
+                        && st.matches([',', '.']).count() <= 1
+                        && !st.contains('-')
+                    {
+                        self.push(Token::DigitGroup(st));
+                    } else if let Some(t) = self.date_or_number(st) {
+                        self.push(t);
                     }
+                    self.after_integer = integer
+                        && matches!(
+                            self.token_queue.back(),
+                            Some((Token::Number(_) | Token::DigitGroup(_), _))
+                        );
                 }
                 c if self.is_quotation(c) => {
                     line_iter.next();
@@ -167,21 +282,25 @@ impl<'a> Lexer<'a> {
                         BidiDataSource::bidi_matched_opening_bracket(&HardcodedBidiData, c)
                     {
                         if is_open {
-                            self.token_queue.push_back(Token::BlockStart(opening));
+                            self.push(Token::BlockStart(opening));
                         } else {
-                            self.token_queue.push_back(Token::BlockEnd(opening));
+                            self.push(Token::BlockEnd(opening));
                         }
                     }
                 }
             }
         }
-        self.token_queue
-            .extend(repeat(Token::ColonBlockEnd).take(colon_count));
+        self.column = first_column + line_length;
+        let span = self.span();
+        self.token_queue.extend(std::iter::repeat_n(
+            (Token::ColonBlockEnd, span),
+            colon_count,
+        ));
     }
 
     fn string(&mut self, line_iter: &mut Peekable<Chars>, delimiter: char) {
         let string_content: String = line_iter.take_while(|c| *c != delimiter).collect();
-        self.token_queue.push_back(Token::String(string_content));
+        self.push(Token::String(string_content));
     }
 
     fn is_quotation(&self, c: char) -> bool {
@@ -214,65 +333,83 @@ impl<'a> Lexer<'a> {
     }
 
     fn identifier(&mut self, line_iter: &mut Peekable<Chars>) {
-        let ident: String = line_iter
+        let mut ident: String = line_iter
             .peeking_take_while(|c| c.is_alphanumeric() || *c == '_')
             .collect();
+        // A decimal separator between digits is part of the identifier, as
+        // in the VAT code `ALV25,5`
+        while ident.ends_with(|c: char| c.is_ascii_digit())
+            && matches!(line_iter.peek(), Some(',' | '.'))
+            && line_iter.clone().nth(1).is_some_and(|c| c.is_ascii_digit())
+        {
+            ident.push(line_iter.next().unwrap());
+            ident.extend(line_iter.peeking_take_while(|c| c.is_alphanumeric() || *c == '_'));
+        }
 
         match ident.as_str() {
             "AUTO" => {
-                self.token_queue.push_back(Token::Auto);
+                self.push(Token::Auto);
             }
             "C" | "CR" | "Cr" | "CREDIT" | "KREDIT" => {
-                self.token_queue.push_back(Token::Credit);
+                self.push(Token::Credit);
             }
             "D" | "DR" | "Dr" | "DEBIT" | "DEBET" => {
-                self.token_queue.push_back(Token::Debit);
+                self.push(Token::Debit);
             }
             "cr" if line_iter.peek() == Some(&'.') => {
                 line_iter.next();
-                self.token_queue.push_back(Token::Credit);
+                self.push(Token::Credit);
             }
             "dr" if line_iter.peek() == Some(&'.') => {
                 line_iter.next();
-                self.token_queue.push_back(Token::Debit);
+                self.push(Token::Debit);
             }
             ident => {
-                self.token_queue.push_back(Token::Identifier(ident.into()));
+                self.push(Token::Identifier(ident.into()));
             }
         }
 
         // if ident == "AUTO" {
-        //     self.token_queue.push_back(Token::Auto);
+        //     self.push(Token::Auto);
         // } else {
-        //     self.token_queue.push_back(Token::Identifier(ident))
+        //     self.push(Token::Identifier(ident))
         // }
     }
 
+    fn tag(&mut self, line_iter: &mut Peekable<Chars>) {
+        let is_tag_char = |c: &char| c.is_alphanumeric() || matches!(c, '_' | '-');
+        let key: String = line_iter.peeking_take_while(is_tag_char).collect();
+        if key.is_empty() {
+            self.error("odotettiin tunnistetta merkin # jälkeen");
+            return;
+        }
+        let value = if line_iter.peek() == Some(&'=') {
+            line_iter.next();
+            line_iter.peeking_take_while(is_tag_char).collect()
+        } else {
+            String::new()
+        };
+        self.push(Token::Tag(key, value));
+    }
+
     fn parse_decimal(&mut self, s: String) -> Option<Token> {
-        let mut decimal_separator_seen = false;
-        let mut decimal_places = 0;
-        let mut amount = 0;
-        let s_iter = s.chars();
-        for c in s_iter {
-            match c {
-                c if c.is_ascii_digit() => {
-                    if decimal_places < 2 {
-                        amount *= 10;
-                        amount += c.to_digit(10).expect("digit is not digit \u{1F914}") as i32;
-                        if decimal_separator_seen {
-                            decimal_places += 1;
-                        }
-                    }
-                }
-                '.' | ',' => {
-                    decimal_separator_seen = true;
-                }
-                _ => {}
+        match Money::parse(&s) {
+            Ok(amount) => Some(Token::Number(amount)),
+            Err(MoneyError::Overflow) => {
+                self.error(format!("luku {s} on liian suuri"));
+                None
+            }
+            Err(MoneyError::TooManyDecimals) => {
+                self.error(format!(
+                    "luvussa {s} on liikaa desimaaleja (enintään {MAX_DECIMALS})"
+                ));
+                None
             }
         }
-        Some(Token::Number(amount * 10_i32.pow(2 - decimal_places)))
     }
 
+    /// Parses `d.m.yyyy`, or `d.m.` with the year left as 0 to be resolved
+    /// later.
     fn parse_date(&mut self, s: String) -> Option<Token> {
         let date_format =
             format_description!("[day padding:none].[month padding:none].[year padding:none]");
@@ -281,39 +418,57 @@ impl<'a> Lexer<'a> {
         } else {
             Date::parse(s.as_str(), date_format)
         };
+        self.date_token(date, &s)
+    }
+
+    /// Parses an ISO 8601 date, `yyyy-mm-dd`.
+    fn parse_iso_date(&mut self, s: String) -> Option<Token> {
+        let date = Date::parse(&s, format_description!("[year]-[month]-[day]"));
+        self.date_token(date, &s)
+    }
+
+    fn date_token(&mut self, date: Result<Date, time::error::Parse>, s: &str) -> Option<Token> {
         match date {
             Ok(date) => Some(Token::Date(date)),
-            Err(_) => None,
+            Err(time::error::Parse::TryFromParsed(_)) => {
+                self.error(format!("päivämäärää {s} ei ole olemassa"));
+                None
+            }
+            Err(_) => {
+                self.error(format!("virheellinen päivämäärä {s}"));
+                None
+            }
         }
     }
 
     fn date_or_number(&mut self, s: String) -> Option<Token> {
         let comma_count = s.matches(',').count();
         let point_count = s.matches('.').count();
-        if comma_count + point_count == 0 {
-            return Some(Token::Number(
-                s.parse::<i32>().expect("invalid number") * 100,
-            ));
-        } else if comma_count + point_count == 1 {
+        if s.contains('-') {
+            return self.parse_iso_date(s);
+        } else if comma_count + point_count <= 1 {
             return self.parse_decimal(s);
-        } else if comma_count == 0 {
-            if point_count == 1 {
-                return self.parse_decimal(s);
-            } else if point_count == 2 {
-                return self.parse_date(s);
-            }
+        } else if comma_count == 0 && point_count == 2 {
+            return self.parse_date(s);
         }
+        self.error(format!("tunnistamaton luku tai päivämäärä {s}"));
         None
     }
 
+    #[allow(dead_code)]
     pub fn next_token(&mut self) -> Option<Token> {
+        self.next_spanned().map(|(t, _)| t)
+    }
+
+    pub fn next_spanned(&mut self) -> Option<(Token, Span)> {
         if self.token_queue.is_empty() {
             if let Some(l) = self.lines.next() {
+                self.line += 1;
                 match l {
                     _ if l.find(|c: char| !c.is_whitespace()).is_none()
-                        || l.trim().starts_with("--") =>
+                        || (l.trim().starts_with("--") && !self.keep_comments) =>
                     {
-                        return self.next_token();
+                        return self.next_spanned();
                     }
                     _ => {
                         self.tokenize_line(l);
@@ -321,9 +476,9 @@ impl<'a> Lexer<'a> {
                 }
             } else {
                 for _ in 0..self.indent_stack.len() {
-                    self.token_queue.push_back(Token::Dedent);
+                    self.push(Token::Dedent);
                 }
-                self.token_queue.push_back(Token::EOF);
+                self.push(Token::Eof);
             }
         }
         self.token_queue.pop_front()
@@ -375,4 +530,32 @@ mod tests {
         assert_eq!(lexer.next_token(), Some(Token::Credit));
     }
 
+    #[test]
+    fn spans() {
+        let mut lexer = Lexer::new("a\n\n  1910: 5");
+        assert_eq!(lexer.next_spanned().unwrap().1, Span::new(0, 1, 1));
+        assert_eq!(lexer.next_spanned().unwrap().1, Span::new(0, 1, 1));
+        assert_eq!(
+            lexer.next_spanned(),
+            Some((Token::Indent, Span::new(0, 3, 3)))
+        );
+        assert_eq!(lexer.next_spanned().unwrap().1, Span::new(0, 3, 3));
+        assert_eq!(
+            lexer.next_spanned(),
+            Some((Token::Colon, Span::new(0, 3, 7)))
+        );
+        assert_eq!(lexer.next_spanned().unwrap().1, Span::new(0, 3, 9));
+    }
+
+    #[test]
+    fn dates() {
+        let mut lexer = Lexer::new("14.3.2025 2025-03-14 31.2.");
+        lexer.next_token();
+        let date = Date::from_calendar_date(2025, time::Month::March, 14).unwrap();
+        assert_eq!(lexer.next_token(), Some(Token::Date(date)));
+        assert_eq!(lexer.next_token(), Some(Token::Date(date)));
+        assert_eq!(lexer.next_token(), Some(Token::Eof));
+        assert_eq!(lexer.errors.len(), 1);
+        assert_eq!(lexer.errors[0].message, "päivämäärää 31.2. ei ole olemassa");
+    }
 }
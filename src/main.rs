@@ -1,4 +1,4 @@
-use std::fs;
+use std::{fs, io::Write};
 
 use axum::{
     Router,
@@ -7,18 +7,25 @@ use axum::{
     response::IntoResponse,
     routing::{get, post},
 };
-use clap::{Arg, ArgAction, Command};
-use ledger::Ledger;
-use parser::Parser;
-use semantic::Semantic;
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use diagnostic::SourceMap;
+use ledger::{ClosingBalances, Filter, Ledger};
 
 use crate::ledger::LedgerType;
 
+mod camt;
+mod diagnostic;
+mod export;
+mod format;
 mod html;
 mod htmll;
+mod import;
+mod journal;
 mod ledger;
 mod lexer;
+mod money;
 mod parser;
+mod reference;
 mod semantic;
 
 #[tokio::main]
@@ -44,10 +51,134 @@ async fn main() {
                 .help("Exclude diary and general ledger from output"),
         )
         .arg(Arg::new("port").long("port"))
+        .arg(
+            Arg::new("opening")
+                .long("avaus")
+                .help("Previous year's ledger to carry opening balances from"),
+        )
+        .arg(
+            Arg::new("filter")
+                .long("suodata")
+                .action(ArgAction::Append)
+                .help("Show only transactions with the tag NAME or the value NAME=VALUE"),
+        )
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("fmt")
+                .about("Formats ledger files in place")
+                .arg(Arg::new("files").required(true).action(ArgAction::Append))
+                .arg(
+                    Arg::new("check")
+                        .long("check")
+                        .action(ArgAction::SetTrue)
+                        .help("Only check that the files are formatted"),
+                ),
+        )
+        .subcommand(
+            Command::new("check")
+                .about(
+                    "Lists transactions without attachments and attachments without transactions",
+                )
+                .arg(Arg::new("input").required(true)),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Converts a bank statement into transactions")
+                .arg(Arg::new("input").help("Ledger to check for transactions already imported"))
+                .arg(Arg::new("csv").long("csv").help("Bank statement as CSV"))
+                .arg(
+                    Arg::new("camt")
+                        .long("camt")
+                        .help("Bank statement as camt.053 XML"),
+                )
+                .arg(
+                    Arg::new("journal")
+                        .long("journal")
+                        .conflicts_with("input")
+                        .help("hledger, ledger-cli or beancount journal to convert into a ledger"),
+                )
+                .group(
+                    ArgGroup::new("statement")
+                        .args(["csv", "camt", "journal"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("rules")
+                        .long("rules")
+                        .required_unless_present("journal")
+                        .conflicts_with("journal")
+                        .help("File with the column mapping and the matching rules"),
+                )
+                .arg(Arg::new("map").long("map").requires("journal").help(
+                    "File with the numbers and names of the journal's accounts, written if missing",
+                )),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Exports the ledger for other programs")
+                .arg(Arg::new("inputs").required(true).action(ArgAction::Append))
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["json", "csv", "journal"])
+                        .default_value("json"),
+                )
+                .arg(
+                    Arg::new("report")
+                        .long("report")
+                        .value_parser(export::CsvReport::NAMES)
+                        .required_if_eq("format", "csv")
+                        .help("Report to export as CSV"),
+                )
+                .arg(
+                    Arg::new("delimiter")
+                        .long("delimiter")
+                        .default_value(";")
+                        .help("Field separator of CSV"),
+                )
+                .arg(
+                    Arg::new("decimal_point")
+                        .long("decimal-point")
+                        .action(ArgAction::SetTrue)
+                        .help("Write amounts in CSV with a decimal point instead of a comma"),
+                )
+                .arg(
+                    Arg::new("opening")
+                        .long("avaus")
+                        .help("Previous year's ledger to carry opening balances from"),
+                )
+                .arg(Arg::new("output").short('o').help("File to write into")),
+        )
+        .subcommand(
+            Command::new("viite")
+                .about("Prints reference numbers with the check digit appended")
+                .arg(Arg::new("bases").required(true).action(ArgAction::Append)),
+        )
+        .subcommand(
+            Command::new("close")
+                .about("Writes next year's ledger with the opening balances")
+                .arg(Arg::new("input").required(true))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .help("File to write next year's ledger into"),
+                ),
+        )
         .get_matches();
 
+    match matches.subcommand() {
+        Some(("fmt", matches)) => std::process::exit(format_files(matches)),
+        Some(("check", matches)) => std::process::exit(check_attachments(matches)),
+        Some(("close", matches)) => std::process::exit(close_year(matches)),
+        Some(("import", matches)) => std::process::exit(import_statement(matches)),
+        Some(("export", matches)) => std::process::exit(export_ledger(matches)),
+        Some(("viite", matches)) => std::process::exit(reference_numbers(matches)),
+        _ => {}
+    }
+
     let mut input_paths = matches.get_many::<String>("inputs").unwrap();
-    let input_paths_2 = input_paths.clone().map(|p| format!("{p}")).collect();
+    let input_paths_2 = input_paths.clone().map(|p| p.to_string()).collect();
 
     let mut ledger;
     let mut budgeting_file_exists = false;
@@ -62,24 +193,38 @@ async fn main() {
     } else {
         input_paths.next().unwrap()
     };
-    if let Ok(s) = fs::read_to_string(path) {
-        let mut parser = Parser::new(&s);
-        ledger = Ledger::exec(Semantic::from_parse_tree(parser.parse()).statements);
-        if budgeting_file_exists {
-            ledger.ledger_type = LedgerType::Budgeting;
+    let mut sources = SourceMap::new();
+    let opening = match matches.get_one::<String>("opening") {
+        Some(path) => match load_ledger(path, None, &mut sources) {
+            Ok(previous) => Some(previous.closing_balances()),
+            Err(e) => {
+                eprint!("{e}");
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    match load_ledger(path, opening, &mut sources) {
+        Ok(l) => {
+            ledger = l;
+            if budgeting_file_exists {
+                ledger.ledger_type = LedgerType::Budgeting;
+            }
+        }
+        Err(e) => {
+            eprint!("{e}");
+            std::process::exit(1);
         }
-    } else {
-        eprintln!("Tiedostoa '{path}' ei löydy.");
-        return;
     }
     for path in input_paths {
-        if let Ok(s) = fs::read_to_string(path) {
-            ledger.add_comparison_from_str(&s);
-        } else {
-            eprintln!("Tiedostoa '{path}' ei löydy.");
-            return;
+        if let Err(e) = add_comparison(&mut ledger, path, &mut sources) {
+            eprint!("{e}");
+            std::process::exit(1);
         }
     }
+    if let Some(filters) = matches.get_many::<String>("filter") {
+        ledger.filters = filters.map(|f| Filter::parse(f)).collect();
+    }
     // ledger.comp_ledger_types.reverse();
 
     if let Some(path) = matches.get_one::<String>("output") {
@@ -95,7 +240,7 @@ async fn main() {
                 }
             },
         );
-        if let Ok(_) = res {
+        if res.is_ok() {
             eprintln!("Kirjanpitoraportti luotu: {path}");
         } else {
             eprintln!("Kirjanpitoraportin tallennus epäonnistui :-(");
@@ -123,7 +268,7 @@ async fn main() {
                         account_map,
                         String::from_utf8_lossy(&b)
                     );
-                    if let Ok(_) = fs::write(path.clone(), file_content) {
+                    if fs::write(path.clone(), file_content).is_ok() {
                         "OK".into_response()
                     } else {
                         (
@@ -149,37 +294,366 @@ async fn main() {
     }
 }
 
-fn generate_budgeting_html(budget_path: String, comparison_paths: Vec<String>) -> String {
-    if let Ok(s) = fs::read_to_string(budget_path) {
-        let mut ledger = Ledger::from_string(s);
-        ledger.ledger_type = LedgerType::Budgeting;
-        for path in comparison_paths {
-            if let Ok(s) = fs::read_to_string(&path) {
-                ledger.add_comparison_from_str(&s);
-            } else {
-                eprintln!("Tiedostoa '{path}' ei löydy.");
-                return format!("Tiedostoa '{path}' ei löydy.");
+/// Reads and executes the ledger at `path`, starting from the `opening`
+/// balances if given. On failure the returned error is
+/// a ready-to-print report.
+fn load_ledger(
+    path: &str,
+    opening: Option<ClosingBalances>,
+    sources: &mut SourceMap,
+) -> Result<Ledger, String> {
+    let Ok(ledger) = Ledger::from_path(path, opening, sources) else {
+        return Err(format!("Tiedostoa '{path}' ei löydy.\n"));
+    };
+    if ledger.errors.is_empty() {
+        Ok(ledger)
+    } else {
+        Err(sources.render_all(&ledger.errors))
+    }
+}
+
+fn add_comparison(ledger: &mut Ledger, path: &str, sources: &mut SourceMap) -> Result<(), String> {
+    if ledger.add_comparison_from_path(path, sources).is_err() {
+        return Err(format!("Tiedostoa '{path}' ei löydy.\n"));
+    }
+    if ledger.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(sources.render_all(&ledger.errors))
+    }
+}
+
+/// Runs `tampio fmt`. Returns the exit status: non-zero if a file could not
+/// be formatted or, with `--check`, if a file is not formatted.
+fn format_files(matches: &ArgMatches) -> i32 {
+    let check = matches.get_flag("check");
+    let mut status = 0;
+    for path in matches.get_many::<String>("files").unwrap() {
+        let Ok(source) = fs::read_to_string(path) else {
+            eprintln!("Tiedostoa '{path}' ei löydy.");
+            status = 1;
+            continue;
+        };
+        let mut sources = SourceMap::new();
+        let file = sources.add(path, &source);
+        let formatted = match format::format_source(&source, file) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                eprint!("{}", sources.render_all(&errors));
+                status = 1;
+                continue;
             }
+        };
+        if formatted == source {
+            continue;
         }
-        return ledger.html_string_with_budgeting(htmll::Budgeting::Server);
-    } else {
-        let path = &comparison_paths[0];
-        let mut ledger;
-        if let Ok(s) = fs::read_to_string(path) {
-            let mut parser = Parser::new(&s);
-            ledger = Ledger::exec(Semantic::from_parse_tree(parser.parse()).statements);
+        if check {
+            eprintln!("Tiedosto '{path}' ei ole muotoiltu.");
+            status = 1;
+        } else if fs::write(path, formatted).is_ok() {
+            eprintln!("Muotoiltu: {path}");
         } else {
-            eprintln!("Tiedostoa '{path}' ei löydy.");
-            return format!("Tiedostoa '{path}' ei löydy.");
+            eprintln!("Tiedoston '{path}' tallennus epäonnistui.");
+            status = 1;
+        }
+    }
+    status
+}
+
+/// Runs `tampio close`. Returns the exit status.
+fn close_year(matches: &ArgMatches) -> i32 {
+    let input = matches.get_one::<String>("input").unwrap();
+    let mut sources = SourceMap::new();
+    let next_year = match load_ledger(input, None, &mut sources) {
+        Ok(ledger) => ledger.next_year_source().map_err(|e| sources.render(&e)),
+        Err(e) => Err(e),
+    };
+    let next_year = match next_year {
+        Ok(source) => source,
+        Err(e) => {
+            eprint!("{e}");
+            return 1;
+        }
+    };
+    if let Some(path) = matches.get_one::<String>("output") {
+        if fs::write(path, next_year).is_err() {
+            eprintln!("Tiedoston '{path}' tallennus epäonnistui.");
+            return 1;
         }
-        for path in comparison_paths.iter().skip(1) {
-            if let Ok(s) = fs::read_to_string(path) {
-                ledger.add_comparison_from_str(&s);
+        eprintln!("Seuraavan vuoden kirjanpito luotu: {path}");
+    } else {
+        print!("{next_year}");
+    }
+    0
+}
+
+/// Runs `tampio check`. Returns the exit status: non-zero if something is
+/// missing.
+fn check_attachments(matches: &ArgMatches) -> i32 {
+    let input = matches.get_one::<String>("input").unwrap();
+    let mut sources = SourceMap::new();
+    let ledger = match load_ledger(input, None, &mut sources) {
+        Ok(ledger) => ledger,
+        Err(e) => {
+            eprint!("{e}");
+            return 1;
+        }
+    };
+    if !ledger.options[0].contains_key("tositteet") {
+        eprintln!("Tositekansiota ei ole asetettu; aseta tositteet.");
+        return 1;
+    }
+    let (transactions, files) = ledger.missing_attachments();
+    if !transactions.is_empty() {
+        println!("Tapahtumat ilman tositetta:");
+        for t in &transactions {
+            println!("  {} {} {}", t.doc, ledger.fmt_date(t.date), t.description);
+        }
+    }
+    if !files.is_empty() {
+        println!("Tositteet ilman tapahtumaa:");
+        for file in &files {
+            println!("  {file}");
+        }
+    }
+    (!transactions.is_empty() || !files.is_empty()) as i32
+}
+
+/// Runs `tampio import`, printing the new transactions. Returns the exit
+/// status: non-zero on errors, if some transactions matched no rule or if
+/// the balances of the statement differ from the ledger.
+fn import_statement(matches: &ArgMatches) -> i32 {
+    if let Some(path) = matches.get_one::<String>("journal") {
+        return import_journal(path, matches.get_one::<String>("map"));
+    }
+    let mut sources = SourceMap::new();
+    let ledger = match matches.get_one::<String>("input") {
+        Some(path) => match load_ledger(path, None, &mut sources) {
+            Ok(ledger) => Some(ledger),
+            Err(e) => {
+                eprint!("{e}");
+                return 1;
+            }
+        },
+        None => None,
+    };
+    let rules_path = matches.get_one::<String>("rules").unwrap();
+    let Ok(rules_source) = fs::read_to_string(rules_path) else {
+        eprintln!("Tiedostoa '{rules_path}' ei löydy.");
+        return 1;
+    };
+    let file = sources.add(rules_path, &rules_source);
+    let rules = match import::Rules::from_source(&rules_source, file) {
+        Ok(rules) => rules,
+        Err(errors) => {
+            eprint!("{}", sources.render_all(&errors));
+            return 1;
+        }
+    };
+    let (path, camt) = match matches.get_one::<String>("camt") {
+        Some(path) => (path, true),
+        None => (matches.get_one::<String>("csv").unwrap(), false),
+    };
+    let Ok(bytes) = fs::read(path) else {
+        eprintln!("Tiedostoa '{path}' ei löydy.");
+        return 1;
+    };
+    let statement = if camt {
+        let currency = ledger.as_ref().map_or("EUR", |l| l.base_currency(0));
+        camt::read(&String::from_utf8_lossy(&bytes), currency)
+    } else {
+        rules.read_csv(&bytes)
+    };
+    let result = statement.and_then(|s| import::import(&s, &rules, ledger.as_ref()));
+    let import = match result {
+        Ok(import) => import,
+        Err(e) => {
+            eprint!("{}", sources.render(&e));
+            return 1;
+        }
+    };
+    if !import.source.is_empty() {
+        print!("\n{}", import.source);
+    }
+    if import.duplicates > 0 {
+        eprintln!("Ohitettu {} jo kirjattua tapahtumaa.", import.duplicates);
+    }
+    if !import.unmatched.is_empty() {
+        eprintln!("Tapahtumat, joihin mikään sääntö ei sovi:");
+        for t in &import.unmatched {
+            eprintln!(
+                "  {} {} {}",
+                format::date(t.date),
+                format::amount(t.amount),
+                t.payee
+            );
+        }
+    }
+    for (balance, ledger_balance) in &import.mismatches {
+        eprintln!(
+            "{} {} ei täsmää: tiliotteella {}, kirjanpidossa {}",
+            if balance.opening {
+                "Alkusaldo"
             } else {
-                eprintln!("Tiedostoa '{path}' ei löydy.");
-                return format!("Tiedostoa '{path}' ei löydy.");
+                "Loppusaldo"
+            },
+            format::date(balance.date),
+            format::amount(balance.amount),
+            format::amount(*ledger_balance)
+        );
+    }
+    (!import.unmatched.is_empty() || !import.mismatches.is_empty()) as i32
+}
+
+/// Runs `tampio import --journal`, printing the journal as a ledger. A
+/// missing mapping file is written with the generated account numbers and
+/// accounts missing from an existing one are appended to it.
+fn import_journal(path: &str, map_path: Option<&String>) -> i32 {
+    let mut sources = SourceMap::new();
+    let Ok(source) = fs::read_to_string(path) else {
+        eprintln!("Tiedostoa '{path}' ei löydy.");
+        return 1;
+    };
+    let file = sources.add(path, &source);
+    let map_source = map_path.and_then(|p| fs::read_to_string(p).ok());
+    let result = journal::Journal::read(&source, file).and_then(|journal| {
+        let mut map = match (map_path, &map_source) {
+            (Some(map_path), Some(map_source)) => {
+                let file = sources.add(map_path, map_source);
+                journal::AccountMap::from_source(map_source, file)?
+            }
+            _ => journal::AccountMap::default(),
+        };
+        journal::convert(&journal, &mut map).map(|tampio| (tampio, map))
+    });
+    let (tampio, map) = match result {
+        Ok(result) => result,
+        Err(errors) => {
+            eprint!("{}", sources.render_all(&errors));
+            return 1;
+        }
+    };
+    print!("{tampio}");
+    if let Some(map_path) = map_path {
+        let written = match &map_source {
+            None => fs::write(map_path, map.source()),
+            Some(_) if map.added.is_empty() => return 0,
+            Some(_) => fs::OpenOptions::new()
+                .append(true)
+                .open(map_path)
+                .and_then(|mut f| f.write_all(map.added_source().as_bytes())),
+        };
+        match written {
+            Ok(()) if map_source.is_none() => eprintln!("Tilien vastaavuudet luotu: {map_path}"),
+            Ok(()) => eprintln!("Lisätty {} tiliä tiedostoon '{map_path}'.", map.added.len()),
+            Err(_) => {
+                eprintln!("Tiedoston '{map_path}' tallennus epäonnistui.");
+                return 1;
+            }
+        }
+    }
+    0
+}
+
+/// Runs `tampio export`. The first input is the ledger and the rest are
+/// comparisons, as for the report. Returns the exit status.
+fn export_ledger(matches: &ArgMatches) -> i32 {
+    let mut inputs = matches.get_many::<String>("inputs").unwrap();
+    let mut sources = SourceMap::new();
+    let opening = match matches.get_one::<String>("opening") {
+        Some(path) => match load_ledger(path, None, &mut sources) {
+            Ok(previous) => Some(previous.closing_balances()),
+            Err(e) => {
+                eprint!("{e}");
+                return 1;
+            }
+        },
+        None => None,
+    };
+    let mut ledger = match load_ledger(inputs.next().unwrap(), opening, &mut sources) {
+        Ok(ledger) => ledger,
+        Err(e) => {
+            eprint!("{e}");
+            return 1;
+        }
+    };
+    for path in inputs {
+        if let Err(e) = add_comparison(&mut ledger, path, &mut sources) {
+            eprint!("{e}");
+            return 1;
+        }
+    }
+    let output = match matches.get_one::<String>("report") {
+        Some(report) if matches.get_one::<String>("format").unwrap() == "csv" => {
+            let delimiter = matches.get_one::<String>("delimiter").unwrap();
+            let &[delimiter] = delimiter.as_bytes() else {
+                eprintln!("Erottimen pitää olla yksi merkki.");
+                return 1;
+            };
+            export::csv(
+                &ledger,
+                export::CsvReport::parse(report).unwrap(),
+                delimiter,
+                matches.get_flag("decimal_point"),
+            )
+        }
+        _ if matches.get_one::<String>("format").unwrap() == "journal" => export::journal(&ledger),
+        _ => export::json(&ledger),
+    };
+    if let Some(path) = matches.get_one::<String>("output") {
+        if fs::write(path, output).is_err() {
+            eprintln!("Tiedoston '{path}' tallennus epäonnistui.");
+            return 1;
+        }
+        eprintln!("Vienti luotu: {path}");
+    } else {
+        print!("{output}");
+    }
+    0
+}
+
+/// Runs `tampio viite`. Returns the exit status.
+fn reference_numbers(matches: &ArgMatches) -> i32 {
+    let mut status = 0;
+    for base in matches.get_many::<String>("bases").unwrap() {
+        match reference::with_check_digit(base) {
+            Some(reference) => println!("{reference}"),
+            None => {
+                eprintln!("Virheellinen perusosa '{base}'; anna 3–19 numeroa.");
+                status = 1;
             }
         }
-        ledger.html_string_with_budgeting(htmll::Budgeting::Server)
     }
+    status
+}
+
+fn generate_budgeting_html(budget_path: String, comparison_paths: Vec<String>) -> String {
+    let mut sources = SourceMap::new();
+    let (mut ledger, comparison_paths) = if fs::exists(&budget_path).unwrap_or(false) {
+        match load_ledger(&budget_path, None, &mut sources) {
+            Ok(mut ledger) => {
+                ledger.ledger_type = LedgerType::Budgeting;
+                (ledger, &comparison_paths[..])
+            }
+            Err(e) => {
+                eprint!("{e}");
+                return e;
+            }
+        }
+    } else {
+        match load_ledger(&comparison_paths[0], None, &mut sources) {
+            Ok(ledger) => (ledger, &comparison_paths[1..]),
+            Err(e) => {
+                eprint!("{e}");
+                return e;
+            }
+        }
+    };
+    for path in comparison_paths {
+        if let Err(e) = add_comparison(&mut ledger, path, &mut sources) {
+            eprint!("{e}");
+            return e;
+        }
+    }
+    ledger.html_string_with_budgeting(htmll::Budgeting::Server)
 }
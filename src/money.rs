@@ -0,0 +1,270 @@
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign},
+};
+
+use serde::Serialize;
+
+/// Number of decimals stored internally. Ledgers may be configured to use
+/// fewer (two by default), but never more.
+pub const MAX_DECIMALS: u32 = 4;
+const SCALE: i64 = 10_i64.pow(MAX_DECIMALS);
+
+pub const DEFAULT_PRECISION: u32 = 2;
+
+/// A fixed-point amount of money stored as an `i64` count of
+/// 1/10 000 units. Arithmetic that would overflow panics; values coming
+/// from the source are checked with [`Money::parse`] and
+/// [`Money::checked_add`] so that overflow is reported as an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Money(i64);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MoneyError {
+    Overflow,
+    TooManyDecimals,
+}
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    #[cfg(test)]
+    pub fn from_cents(cents: i64) -> Money {
+        Money(cents * (SCALE / 100))
+    }
+
+    /// Parses an unsigned decimal number written with `,` or `.` as the
+    /// decimal separator.
+    pub fn parse(s: &str) -> Result<Money, MoneyError> {
+        let (int_part, frac_part) = match s.find([',', '.']) {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, ""),
+        };
+        if frac_part.len() > MAX_DECIMALS as usize {
+            return Err(MoneyError::TooManyDecimals);
+        }
+        let mut value: i64 = 0;
+        for c in int_part.chars().chain(frac_part.chars()) {
+            let digit = c.to_digit(10).expect("digit is not digit \u{1F914}") as i64;
+            value = value
+                .checked_mul(10)
+                .and_then(|v| v.checked_add(digit))
+                .ok_or(MoneyError::Overflow)?;
+        }
+        value
+            .checked_mul(10_i64.pow(MAX_DECIMALS - frac_part.len() as u32))
+            .map(Money)
+            .ok_or(MoneyError::Overflow)
+    }
+
+    /// Returns the value as a whole number, if it has no decimals.
+    pub fn as_integer(self) -> Option<i64> {
+        if self.0 % SCALE == 0 {
+            Some(self.0 / SCALE)
+        } else {
+            None
+        }
+    }
+
+    /// True if the amount can be written with at most `decimals` decimals.
+    pub fn fits_precision(self, decimals: u32) -> bool {
+        self.0 % 10_i64.pow(MAX_DECIMALS - decimals.min(MAX_DECIMALS)) == 0
+    }
+
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    pub fn checked_mul(self, rhs: i64) -> Option<Money> {
+        self.0.checked_mul(rhs).map(Money)
+    }
+
+    /// Multiplies by an exchange rate, rounding half away from zero to
+    /// `decimals` decimals.
+    pub fn convert(self, rate: Money, decimals: u32) -> Option<Money> {
+        let step = 10_i128.pow(MAX_DECIMALS - decimals.min(MAX_DECIMALS));
+        let unit = step * SCALE as i128;
+        let product = self.0 as i128 * rate.0 as i128;
+        let rounded = (product.abs() + unit / 2) / unit * product.signum();
+        i64::try_from(rounded * step).ok().map(Money)
+    }
+
+    /// The VAT included in a gross amount at `rate` percent, rounded half
+    /// away from zero to `decimals` decimals.
+    pub fn vat_part(self, rate: Money, decimals: u32) -> Option<Money> {
+        let step = 10_i128.pow(MAX_DECIMALS - decimals.min(MAX_DECIMALS));
+        let numerator = self.0 as i128 * rate.0 as i128;
+        let denominator = (100 * SCALE as i128 + rate.0 as i128) * step;
+        let rounded = (numerator.abs() + denominator / 2) / denominator * numerator.signum();
+        i64::try_from(rounded * step).ok().map(Money)
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn abs(self) -> Money {
+        Money(self.0.checked_abs().expect("money overflow"))
+    }
+
+    /// Formats the amount the Finnish way with exactly `decimals` decimals,
+    /// e.g. `−1234,50`. Digits beyond `decimals` are rounded half away from
+    /// zero.
+    pub fn to_fi_string(self, decimals: u32) -> String {
+        let decimals = decimals.min(MAX_DECIMALS);
+        let unit = 10_i64.pow(MAX_DECIMALS - decimals);
+        let abs = self.0.unsigned_abs();
+        let rounded = (abs + unit as u64 / 2) / unit as u64;
+        let denominator = 10_u64.pow(decimals);
+        let sign = if self.0 < 0 && rounded != 0 {
+            "\u{2212}"
+        } else {
+            ""
+        };
+        if decimals == 0 {
+            format!("{sign}{rounded}")
+        } else {
+            format!(
+                "{sign}{},{:0width$}",
+                rounded / denominator,
+                rounded % denominator,
+                width = decimals as usize
+            )
+        }
+    }
+}
+
+/// Plain representation with `.` as the decimal separator and no trailing
+/// zeros beyond two decimals, e.g. `-1234.5` is written as `-1234.50`.
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let mut frac = format!("{:04}", abs % SCALE as u64);
+        while frac.len() > DEFAULT_PRECISION as usize && frac.ends_with('0') {
+            frac.pop();
+        }
+        write!(f, "{sign}{}.{frac}", abs / SCALE as u64)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, rhs: Money) -> Money {
+        self.checked_add(rhs).expect("money overflow")
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, rhs: Money) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, rhs: Money) -> Money {
+        Money(self.0.checked_sub(rhs.0).expect("money overflow"))
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, rhs: Money) {
+        *self = *self - rhs;
+    }
+}
+
+impl Mul<i64> for Money {
+    type Output = Money;
+
+    fn mul(self, rhs: i64) -> Money {
+        Money(self.0.checked_mul(rhs).expect("money overflow"))
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money(self.0.checked_neg().expect("money overflow"))
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::ZERO, |a, b| a + b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Money::parse("12,5"), Ok(Money::from_cents(1250)));
+        assert_eq!(Money::parse("12.05"), Ok(Money::from_cents(1205)));
+        assert_eq!(
+            Money::parse("30000000"),
+            Ok(Money::from_cents(3_000_000_000))
+        );
+        assert_eq!(Money::parse("1,23456"), Err(MoneyError::TooManyDecimals));
+        assert_eq!(Money::parse("99999999999999999"), Err(MoneyError::Overflow));
+    }
+
+    #[test]
+    fn precision() {
+        let m = Money::parse("0,125").unwrap();
+        assert!(!m.fits_precision(2));
+        assert!(m.fits_precision(3));
+        assert_eq!(m.to_fi_string(3), "0,125");
+        assert_eq!((-m).to_fi_string(2), "\u{2212}0,13");
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Money::from_cents(-123450).to_string(), "-1234.50");
+        assert_eq!(Money::parse("1,2345").unwrap().to_string(), "1.2345");
+    }
+
+    #[test]
+    fn convert() {
+        let rate = Money::parse("0,9234").unwrap();
+        let usd = Money::from_cents(12_000);
+        assert_eq!(usd.convert(rate, 2), Some(Money::from_cents(11_081)));
+        assert_eq!((-usd).convert(rate, 2), Some(Money::from_cents(-11_081)));
+    }
+
+    #[test]
+    fn vat_part() {
+        let rate = Money::parse("25,5").unwrap();
+        assert_eq!(
+            Money::from_cents(12_550).vat_part(rate, 2),
+            Some(Money::from_cents(2_550))
+        );
+        assert_eq!(
+            Money::from_cents(-10_000).vat_part(rate, 2),
+            Some(Money::from_cents(-2_032))
+        );
+    }
+}
@@ -1,79 +1,124 @@
 use core::panic;
 use std::str;
 
-use crate::lexer::{Lexer, Token};
+use crate::{
+    diagnostic::{Diagnostic, Span},
+    lexer::{Lexer, Token, closing_bracket},
+};
 
 #[derive(Debug)]
 pub enum Node {
-    Block(Vec<Token>, Vec<Node>),
-    List(Vec<Token>),
+    Block(Vec<Token>, Vec<Node>, Span),
+    List(Vec<Token>, Span),
 }
 
 impl Node {
     pub fn push_child(&mut self, c: Node) {
         match self {
-            Self::Block(_, b) => b.push(c),
-            Self::List(_) => panic!("can't push to list"),
+            Self::Block(_, b, _) => b.push(c),
+            Self::List(..) => panic!("can't push to list"),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Block(_, _, span) | Self::List(_, span) => *span,
         }
     }
 }
 
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
+    pub errors: Vec<Diagnostic>,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(source: &'a str) -> Self {
+    pub fn with_file(source: &'a str, file: usize) -> Self {
         Self {
-            lexer: Lexer::new(source),
+            lexer: Lexer::with_file(source, file),
+            errors: Vec::new(),
         }
     }
 
+    /// Builds the parse tree. Problems are collected into `errors`; an
+    /// unmatched closing bracket is skipped and blocks left open at the end
+    /// of the file are closed, so the returned tree is always usable.
     pub fn parse(&mut self) -> Vec<Node> {
         let mut node_stack: Vec<Node> = Vec::new();
         let mut header: Vec<Token> = Vec::new();
+        let mut header_span: Option<Span> = None;
         let mut result: Vec<Node> = Vec::new();
 
-        while let Some(t) = self.lexer.next_token() {
+        while let Some((t, span)) = self.lexer.next_spanned() {
             match t.normalise() {
                 Token::BlockStart(_c) => {
-                    node_stack.push(Node::Block(header.clone(), Vec::new()));
+                    node_stack.push(Node::Block(
+                        header.clone(),
+                        Vec::new(),
+                        header_span.take().unwrap_or(span),
+                    ));
                     header.clear();
                 }
-                Token::BlockEnd(_c) => {
+                Token::BlockEnd(c) => {
+                    let Some(mut top) = node_stack.pop() else {
+                        let message = match c {
+                            ':' | '\t' => "lohkon loppu ilman vastaavaa alkua".to_string(),
+                            c => format!(
+                                "sulkeva '{}' ilman vastaavaa avaavaa sulkua",
+                                closing_bracket(c)
+                            ),
+                        };
+                        self.errors.push(Diagnostic::new(span, message));
+                        continue;
+                    };
                     if !header.is_empty() {
-                        node_stack
-                            .last_mut()
-                            .unwrap()
-                            .push_child(Node::List(header.clone()));
+                        top.push_child(Node::List(header.clone(), header_span.take().unwrap()));
                         header.clear();
                     }
-                    let top = node_stack.pop().unwrap();
-                    if node_stack.is_empty() {
-                        result.push(top);
+                    if let Some(parent) = node_stack.last_mut() {
+                        parent.push_child(top);
                     } else {
-                        node_stack.last_mut().unwrap().push_child(top);
+                        result.push(top);
                     }
                 }
                 Token::Semicolon => {
                     if !header.is_empty() {
+                        let list = Node::List(header.clone(), header_span.take().unwrap());
                         if let Some(top) = node_stack.last_mut() {
-                            top.push_child(Node::List(header.clone()));
+                            top.push_child(list);
                         } else {
-                            result.push(Node::List(header.clone()));
+                            result.push(list);
                         }
                         header.clear();
                     }
                 }
-                Token::EOF => {
+                Token::Eof => {
                     if !header.is_empty() {
-                        result.push(Node::List(header.clone()));
+                        let list = Node::List(header.clone(), header_span.take().unwrap());
+                        if let Some(top) = node_stack.last_mut() {
+                            top.push_child(list);
+                        } else {
+                            result.push(list);
+                        }
+                    }
+                    while let Some(top) = node_stack.pop() {
+                        self.errors
+                            .push(Diagnostic::new(top.span(), "lohkoa ei suljettu"));
+                        if let Some(parent) = node_stack.last_mut() {
+                            parent.push_child(top);
+                        } else {
+                            result.push(top);
+                        }
                     }
-                    return result;
+                    break;
+                }
+                token => {
+                    header_span.get_or_insert(span);
+                    header.push(token)
                 }
-                token => header.push(token),
             }
         }
+        self.errors.append(&mut self.lexer.errors);
         result
     }
 }
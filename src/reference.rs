@@ -0,0 +1,72 @@
+//! Finnish reference numbers (viitenumero) and RF creditor references.
+
+/// Appends the check digit to the digits `base`, e.g. `123` → `1232`.
+/// The base must be 3–19 digits long.
+pub fn with_check_digit(base: &str) -> Option<String> {
+    let base = base.replace(' ', "");
+    if !(3..=19).contains(&base.len()) || !base.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!("{base}{}", check_digit(&base)))
+}
+
+fn check_digit(base: &str) -> u32 {
+    let sum: u32 = base
+        .bytes()
+        .rev()
+        .zip([7, 3, 1].into_iter().cycle())
+        .map(|(b, w)| (b - b'0') as u32 * w)
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+/// Whether `s` is a valid Finnish reference number or RF creditor
+/// reference. Spaces are ignored.
+pub fn is_valid(s: &str) -> bool {
+    let s = normalise(s);
+    if let Some(rest) = s.strip_prefix("RF") {
+        return (3..=23).contains(&rest.len())
+            && rest.chars().all(|c| c.is_ascii_alphanumeric())
+            && rf_remainder(&format!("{}RF{}", &rest[2..], &rest[..2])) == Some(1);
+    }
+    (4..=20).contains(&s.len())
+        && s.bytes().all(|b| b.is_ascii_digit())
+        && with_check_digit(&s[..s.len() - 1]).as_deref() == Some(s.as_str())
+}
+
+/// Mod 97 of `s` with letters as numbers, A = 10 … Z = 35.
+fn rf_remainder(s: &str) -> Option<u32> {
+    s.chars().try_fold(0, |acc, c| {
+        let n = c.to_digit(36)?;
+        Some(if n < 10 { acc * 10 + n } else { acc * 100 + n } % 97)
+    })
+}
+
+/// The reference without spaces and leading zeros, in upper case, for
+/// comparing references.
+pub fn normalise(s: &str) -> String {
+    let s = s.replace(' ', "").to_uppercase();
+    match s.trim_start_matches('0') {
+        "" => s,
+        trimmed if !s.starts_with("RF") => trimmed.to_string(),
+        _ => s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references() {
+        assert_eq!(with_check_digit("123").as_deref(), Some("1232"));
+        assert_eq!(with_check_digit("1001").as_deref(), Some("10016"));
+        assert_eq!(with_check_digit("12"), None);
+        assert!(is_valid("1232"));
+        assert!(is_valid("00 1232"));
+        assert!(!is_valid("1233"));
+        assert!(is_valid("RF18 5390 0754 7034"));
+        assert!(!is_valid("RF19 5390 0754 7034"));
+        assert_eq!(normalise("0001 2320"), "12320");
+    }
+}
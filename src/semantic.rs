@@ -1,30 +1,86 @@
-use core::panic;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
 
 use serde::Serialize;
 use time::Date;
 
+use crate::diagnostic::{Diagnostic, SourceMap, Span};
 use crate::lexer::Token;
-use crate::parser::Node;
+use crate::money::Money;
+use crate::parser::{Node, Parser};
+use crate::reference;
 
-#[derive(Debug)]
+/// Metadata key of the reference number of an invoice or a payment.
+pub const REFERENCE: &str = "viite";
+/// Metadata key of an invoice number, which payments can refer to instead
+/// of a reference number.
+pub const INVOICE_NUMBER: &str = "laskunro";
+/// Metadata key of the due date of an invoice.
+pub const DUE_DATE: &str = "eräpäivä";
+/// Options whose value is a number rather than an account.
+const NUMERIC_OPTIONS: [&str; 2] = ["desimaalit", "maksuaika"];
+
+#[derive(Debug, Clone)]
 pub(crate) enum SStatement {
     Transaction {
         date: Option<Date>,
         description: String,
         entries: Vec<SEntry>,
         doc: Option<String>,
+        metadata: Metadata,
+        span: Span,
     },
-    Block(SHeader, Vec<SStatement>),
-    Expression(SExpression),
+    Block(SHeader, Dimensions, Vec<SStatement>, Span),
+    Expression(SExpression, Span),
     Section(SectionType),
-    Account(Option<i32>, String, Vec<SStatement>, AccountType),
+    Account(
+        Option<i32>,
+        String,
+        Vec<SStatement>,
+        AccountType,
+        Option<Money>,
+        Option<VatSide>,
+    ),
     BudgetEntry {
         account: SAccount,
-        amounts: Vec<(i32, EntryType)>,
+        amounts: Vec<(Money, EntryType)>,
+        span: Span,
+    },
+    /// `tarkista 1910 = 100`, or with `statement` the balance on a bank
+    /// statement, `tiliote 1910 = 100`.
+    Assertion {
+        account: SAccount,
+        amount: Money,
+        statement: bool,
+        span: Span,
+    },
+    OpeningEntry(SEntry),
+    Recurring(SRecurring),
+    Rate {
+        date: Option<Date>,
+        currency: String,
+        rate: Money,
+        span: Span,
     },
 }
 
-#[derive(Debug)]
+/// Dimensions such as `#projekti=nuoriso`, by name.
+pub type Dimensions = BTreeMap<String, String>;
+
+/// Dimensions, `#tag`s and free-form `key: value` lines of a transaction or
+/// an entry, and whether it is marked cleared with `*`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Metadata {
+    pub dimensions: Dimensions,
+    pub tags: BTreeSet<String>,
+    pub values: BTreeMap<String, String>,
+    pub cleared: bool,
+}
+
+#[derive(Debug, Clone)]
 pub(crate) enum EntryType {
     Debit,
     Credit,
@@ -39,188 +95,479 @@ pub(crate) enum AccountType {
     None,
 }
 
+/// Whether VAT on the entries to an account is on sales or purchases,
+/// `myynti` or `osto` in the account map.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub(crate) enum VatSide {
+    Sales,
+    Purchases,
+}
+
+impl VatSide {
+    fn parse(s: &str) -> Option<VatSide> {
+        match s {
+            "myynti" => Some(VatSide::Sales),
+            "osto" => Some(VatSide::Purchases),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) enum SectionType {
     Ledger,
     AccountMap,
     Budget,
     Options,
+    Opening,
+    Rates,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) enum SHeader {
     Date { date: Date },
     AutoBalance { account: SAccount },
     Dummy,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct SEntry {
     pub account: SAccount,
-    pub amounts: Vec<SAuto<i32>>,
+    pub amounts: Vec<SAuto<SAmount>>,
+    pub vat: Option<Money>,
+    pub metadata: Metadata,
+    pub span: Span,
+}
+
+/// `TOISTUVA kuukausittain 1.1.–31.12. "Vuokra"`: a transaction repeated at
+/// `frequency` from `start` to `end`. An override, a transaction dated on
+/// an occurrence, replaces that occurrence, and `ohita: 1.8.` leaves
+/// occurrences out.
+#[derive(Debug, Clone)]
+pub(crate) struct SRecurring {
+    pub frequency: Frequency,
+    pub start: Date,
+    pub end: Date,
+    pub description: String,
+    pub entries: Vec<SEntry>,
+    pub doc: Option<String>,
+    pub metadata: Metadata,
+    pub overrides: Vec<(Date, SStatement)>,
+    pub skips: Vec<(Date, Span)>,
+    pub span: Span,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Frequency {
+    Weekly,
+    Monthly,
+    Quarterly,
+    Yearly,
+}
+
+/// An amount in the base currency, or in `currency` converted at `rate`
+/// (from `§ KURSSIT` if not given).
+#[derive(Debug, Clone)]
+pub(crate) struct SAmount {
+    pub value: Money,
+    pub currency: Option<String>,
+    pub rate: Option<Money>,
+}
+
+impl From<Money> for SAmount {
+    fn from(value: Money) -> Self {
+        Self {
+            value,
+            currency: None,
+            rate: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub(crate) enum SAccount {
     N(i32),
     Alias(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) enum SAuto<T> {
     Val(T),
     Auto,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) enum SExpression {
     Alias(String, i32),
     Definition(String, String),
 }
 
-pub struct Semantic {
+pub struct Semantic<'a> {
     pub statements: Vec<SStatement>,
+    pub errors: Vec<Diagnostic>,
     section: SectionType,
+    sources: &'a mut SourceMap,
+    include_stack: Vec<PathBuf>,
 }
 
-impl Semantic {
-    fn new() -> Self {
-        Self {
+type SResult<T> = Result<T, Diagnostic>;
+
+impl<'a> Semantic<'a> {
+    /// Converts the parse tree of `file` into statements. A malformed
+    /// statement is reported in `errors` and left out, and analysis continues
+    /// with the next one. Included files are read relative to the including
+    /// file and added to `sources`.
+    pub fn from_parse_tree(parse_tree: Vec<Node>, file: usize, sources: &'a mut SourceMap) -> Self {
+        let include_stack = Path::new(sources.name(file))
+            .canonicalize()
+            .into_iter()
+            .collect();
+        let mut instance = Self {
             statements: Vec::new(),
+            errors: Vec::new(),
             section: SectionType::Ledger,
-        }
-    }
-
-    pub fn from_parse_tree(parse_tree: Vec<Node>) -> Self {
-        let mut instance = Self::new();
+            sources,
+            include_stack,
+        };
         instance.statements = instance.nodes(parse_tree);
         instance
     }
 
     fn nodes(&mut self, nodes: Vec<Node>) -> Vec<SStatement> {
-        nodes.into_iter().map(|n| self.node(n)).collect()
+        let mut statements = Vec::new();
+        for node in nodes {
+            if let Some((path, span)) = include_target(&node) {
+                statements.extend(self.include(&path, span));
+                continue;
+            }
+            match self.node(node) {
+                Ok(statement) => statements.push(statement),
+                Err(e) => self.errors.push(e),
+            }
+        }
+        statements
+    }
+
+    /// Reads, parses and analyses an included file in place. The current
+    /// section carries over into the included file and back out of it, as if
+    /// its text was written at the point of inclusion.
+    fn include(&mut self, target: &str, span: Span) -> Vec<SStatement> {
+        let base = Path::new(self.sources.name(span.file))
+            .parent()
+            .unwrap_or(Path::new(""));
+        let path = base.join(target);
+        let text = match path
+            .canonicalize()
+            .and_then(|c| Ok((c, fs::read_to_string(&path)?)))
+        {
+            Ok((canonical, text)) => {
+                if self.include_stack.contains(&canonical) {
+                    self.errors.push(Diagnostic::new(
+                        span,
+                        format!(
+                            "tiedoston '{}' sisällyttäminen muodostaa silmukan",
+                            path.display()
+                        ),
+                    ));
+                    return vec![];
+                }
+                self.include_stack.push(canonical);
+                text
+            }
+            Err(e) => {
+                self.errors.push(Diagnostic::new(
+                    span,
+                    format!("tiedostoa '{}' ei voi lukea: {e}", path.display()),
+                ));
+                return vec![];
+            }
+        };
+        let file = self.sources.add(&path.to_string_lossy(), &text);
+        let mut parser = Parser::with_file(&text, file);
+        let tree = parser.parse();
+        self.errors.extend(parser.errors);
+        let statements = self.nodes(tree);
+        self.include_stack.pop();
+        statements
     }
 
-    fn node(&mut self, node: Node) -> SStatement {
+    fn node(&mut self, node: Node) -> SResult<SStatement> {
         match node {
-            Node::Block(_, _) => self.block(node),
-            Node::List(l) => self.list(l),
+            Node::Block(h, body, span) => self.block(h, body, span),
+            Node::List(l, span) => self.list(l, span),
         }
     }
 
-    fn list(&mut self, list: Vec<Token>) -> SStatement {
-        match &list[..] {
-            [Token::Identifier(ident), Token::Assign, Token::Number(n)] => {
-                SStatement::Expression(SExpression::Alias(ident.to_string(), *n))
-            }
-            [Token::Identifier(ident), Token::Assign, Token::String(s)] => {
-                SStatement::Expression(SExpression::Definition(ident.to_string(), s.to_string()))
+    fn list(&mut self, list: Vec<Token>, span: Span) -> SResult<SStatement> {
+        Ok(match &list[..] {
+            [Token::Identifier(ident), Token::Assign, Token::Number(n)]
+                if self.section == SectionType::Options
+                    && NUMERIC_OPTIONS.contains(&ident.to_lowercase().as_str()) =>
+            {
+                let value = n.as_integer().map_or(n.to_string(), |n| n.to_string());
+                SStatement::Expression(SExpression::Definition(ident.to_string(), value), span)
             }
+            [Token::Identifier(ident), Token::Assign, Token::Number(n)] => SStatement::Expression(
+                SExpression::Alias(ident.to_string(), account_number(*n, span)?),
+                span,
+            ),
+            [Token::Identifier(ident), Token::Assign, Token::String(s)] => SStatement::Expression(
+                SExpression::Definition(ident.to_string(), s.to_string()),
+                span,
+            ),
+            [Token::Identifier(ident), Token::Assign, Token::Date(d)] => SStatement::Expression(
+                SExpression::Definition(ident.to_string(), d.to_string()),
+                span,
+            ),
             [Token::Section, Token::Identifier(ident)] => {
                 let s_type = match ident.to_uppercase().as_str() {
                     "TILIKARTTA" => SectionType::AccountMap,
                     "TIEDOT" => SectionType::Options,
                     "TALOUSARVIO" | "BUDJETTI" => SectionType::Budget,
+                    "AVAUS" => SectionType::Opening,
+                    "KURSSIT" => SectionType::Rates,
                     _ => SectionType::Ledger,
                 };
                 self.section = s_type;
                 SStatement::Section(s_type)
             }
-            [Token::Number(n), Token::String(s)] => {
-                SStatement::Account(Some(*n / 100), s.to_string(), vec![], AccountType::None)
-            }
-            _ => panic!("malformed statement: {:?}", list),
-        }
-    }
-
-    fn block(&mut self, block: Node) -> SStatement {
-        match block {
-            Node::Block(h, body) => match &h[..] {
-                [Token::Date(date)] => self.date_block(*date, body),
-                [Token::Date(date), Token::String(description)] => {
-                    self.transaction(Some(*date), description.clone(), body, None)
-                }
-                [
-                    Token::Identifier(d),
-                    Token::Date(date),
-                    Token::String(description),
-                ]
-                | [
-                    Token::Date(date),
-                    Token::String(description),
-                    Token::Identifier(d),
-                ] => self.transaction(Some(*date), description.clone(), body, Some(d.clone())),
-                [Token::String(description)] => {
-                    self.transaction(None, description.clone(), body, None)
-                }
-                [Token::Identifier(d), Token::String(description)]
-                | [Token::String(description), Token::Identifier(d)] => {
-                    self.transaction(None, description.clone(), body, Some(d.clone()))
-                }
-                [Token::Auto, tail @ ..] => self.auto_block(tail, body),
-                [] => self.dummy_block(body),
-                [Token::Number(n), Token::String(s)] => {
-                    self.account(Some(*n), s.clone(), body, AccountType::None)
-                }
-                [Token::Plus, Token::String(s)] => {
-                    self.account(None, s.clone(), body, AccountType::Assets)
+            [Token::Identifier(currency), Token::Number(rate)]
+                if self.section == SectionType::Rates =>
+            {
+                SStatement::Rate {
+                    date: None,
+                    currency: currency_code(currency, span)?,
+                    rate: *rate,
+                    span,
                 }
-                [Token::Minus, Token::String(s)] => {
-                    self.account(None, s.clone(), body, AccountType::LiabilitiesTopLevel)
-                }
-                [Token::Number(n)] => self.budget_row(SAccount::N(*n), body),
-                [Token::Identifier(i)] => self.budget_row(SAccount::Alias(i.into()), body),
-                _ => panic!("unknown header {:?}", h),
+            }
+            [
+                Token::Date(date),
+                Token::Identifier(currency),
+                Token::Number(rate),
+            ] if self.section == SectionType::Rates => SStatement::Rate {
+                date: Some(*date),
+                currency: currency_code(currency, span)?,
+                rate: *rate,
+                span,
             },
-            Node::List(l) => match &l[..] {
-                [Token::Identifier(ident), Token::Assign, Token::Number(n)] => {
-                    SStatement::Expression(SExpression::Alias(ident.to_string(), *n))
-                }
-                [Token::Identifier(ident), Token::Assign, Token::String(s)] => {
-                    SStatement::Expression(SExpression::Definition(
-                        ident.to_string(),
-                        s.to_string(),
-                    ))
+            [Token::Number(n), Token::String(s)] => SStatement::Account(
+                Some(account_number(*n, span)?),
+                s.to_string(),
+                vec![],
+                AccountType::None,
+                None,
+                None,
+            ),
+            [Token::Number(n), Token::String(s), codes @ ..]
+                if self.section == SectionType::AccountMap =>
+            {
+                let (vat, side) = account_codes(codes, span)?;
+                SStatement::Account(
+                    Some(account_number(*n, span)?),
+                    s.to_string(),
+                    vec![],
+                    AccountType::None,
+                    vat,
+                    side,
+                )
+            }
+            [
+                Token::Identifier(keyword),
+                account,
+                Token::Assign,
+                amount @ ..,
+            ] if matches!(
+                keyword.to_lowercase().as_str(),
+                "tarkista" | "assert" | "tiliote"
+            ) =>
+            {
+                let account = match account {
+                    Token::Number(n) => SAccount::N(account_number(*n, span)?),
+                    Token::Identifier(alias) => SAccount::Alias(alias.clone()),
+                    _ => return Err(Diagnostic::new(span, "odotettiin tiliä")),
+                };
+                SStatement::Assertion {
+                    account,
+                    amount: grouped_amount(amount, span)?,
+                    statement: keyword.to_lowercase() == "tiliote",
+                    span,
                 }
-                _ => panic!("unknown expression"),
-            },
+            }
+            _ => return Err(Diagnostic::new(span, "tunnistamaton rivi")),
+        })
+    }
+
+    fn block(&mut self, h: Vec<Token>, body: Vec<Node>, span: Span) -> SResult<SStatement> {
+        let (h, metadata) = header_tags(&h, span)?;
+        let dimensions = metadata.dimensions.clone();
+        match &h[..] {
+            [Token::Date(_)] | [Token::Auto, ..] | []
+                if !metadata.tags.is_empty() || metadata.cleared =>
+            {
+                Err(Diagnostic::new(
+                    span,
+                    "tunnisteita ja merkinnän * voi antaa vain tapahtumille ja vienneille",
+                ))
+            }
+            [Token::Date(date)] => self.date_block(*date, dimensions, body, span),
+            [Token::Date(date), Token::String(description)] => {
+                self.transaction(Some(*date), description.clone(), body, None, metadata, span)
+            }
+            [
+                Token::Identifier(d),
+                Token::Date(date),
+                Token::String(description),
+            ]
+            | [
+                Token::Date(date),
+                Token::String(description),
+                Token::Identifier(d),
+            ] => self.transaction(
+                Some(*date),
+                description.clone(),
+                body,
+                Some(d.clone()),
+                metadata,
+                span,
+            ),
+            [Token::String(name), codes @ ..]
+                if self.section == SectionType::AccountMap && codes.len() > 1 =>
+            {
+                let codes = account_codes(codes, span)?;
+                self.account(None, name.clone(), body, AccountType::None, codes)
+            }
+            [Token::String(description)] => {
+                self.transaction(None, description.clone(), body, None, metadata, span)
+            }
+            [Token::Identifier(d), Token::String(description)]
+            | [Token::String(description), Token::Identifier(d)] => self.transaction(
+                None,
+                description.clone(),
+                body,
+                Some(d.clone()),
+                metadata,
+                span,
+            ),
+            [Token::Auto, tail @ ..] => self.auto_block(tail, dimensions, body, span),
+            [] => self.dummy_block(dimensions, body, span),
+            [
+                Token::Identifier(keyword),
+                Token::Identifier(frequency),
+                Token::Date(start),
+                rest @ ..,
+            ] if keyword.to_uppercase() == "TOISTUVA" => {
+                self.recurring(frequency, *start, rest, body, metadata, span)
+            }
+            _ if metadata != Metadata::default() => Err(Diagnostic::new(
+                span,
+                "ulottuvuuksia voi antaa vain tapahtumille, vienneille ja lohkoille",
+            )),
+            [Token::Number(n), Token::String(s)] => self.account(
+                Some(account_number(*n, span)?),
+                s.clone(),
+                body,
+                AccountType::None,
+                (None, None),
+            ),
+            [Token::Number(n), Token::String(s), codes @ ..]
+                if self.section == SectionType::AccountMap =>
+            {
+                let codes = account_codes(codes, span)?;
+                self.account(
+                    Some(account_number(*n, span)?),
+                    s.clone(),
+                    body,
+                    AccountType::None,
+                    codes,
+                )
+            }
+            [Token::Plus, Token::String(s)] => {
+                self.account(None, s.clone(), body, AccountType::Assets, (None, None))
+            }
+            [Token::Minus, Token::String(s)] => self.account(
+                None,
+                s.clone(),
+                body,
+                AccountType::LiabilitiesTopLevel,
+                (None, None),
+            ),
+            [Token::Number(_) | Token::Identifier(_)] if self.section == SectionType::Opening => {
+                Ok(SStatement::OpeningEntry(self.entry(Node::Block(
+                    h.clone(),
+                    body,
+                    span,
+                ))?))
+            }
+            [Token::Number(n)] => {
+                self.budget_row(SAccount::N(account_number(*n, span)?), body, span)
+            }
+            [Token::Identifier(i)] => self.budget_row(SAccount::Alias(i.into()), body, span),
+            _ => Err(Diagnostic::new(span, "tunnistamaton lohkon otsake")),
         }
     }
 
-    fn date_block(&mut self, date: Date, body: Vec<Node>) -> SStatement {
-        SStatement::Block(SHeader::Date { date }, self.nodes(body))
+    fn date_block(
+        &mut self,
+        date: Date,
+        dimensions: Dimensions,
+        body: Vec<Node>,
+        span: Span,
+    ) -> SResult<SStatement> {
+        Ok(SStatement::Block(
+            SHeader::Date { date },
+            dimensions,
+            self.nodes(body),
+            span,
+        ))
     }
 
-    fn dummy_block(&mut self, body: Vec<Node>) -> SStatement {
-        SStatement::Block(SHeader::Dummy, self.nodes(body))
+    fn dummy_block(
+        &mut self,
+        dimensions: Dimensions,
+        body: Vec<Node>,
+        span: Span,
+    ) -> SResult<SStatement> {
+        Ok(SStatement::Block(
+            SHeader::Dummy,
+            dimensions,
+            self.nodes(body),
+            span,
+        ))
     }
 
-    fn budget_row(&mut self, account: SAccount, body: Vec<Node>) -> SStatement {
-        // if body.len() != 1 {
-        //     panic!("too long body")
-        // };
+    fn budget_row(
+        &mut self,
+        account: SAccount,
+        body: Vec<Node>,
+        span: Span,
+    ) -> SResult<SStatement> {
         let mut amounts = vec![];
         for entry in body {
-            if let Node::List(amount) = entry {
+            if let Node::List(amount, amount_span) = entry {
                 amounts.push(match &amount[..] {
                     [Token::Number(n)] => (*n, EntryType::None),
-                    [Token::Minus, Token::Number(n)] => (-n, EntryType::None),
+                    [Token::Minus, Token::Number(n)] => (-*n, EntryType::None),
                     [Token::Debit, Token::Number(n)] | [Token::Number(n), Token::Debit] => {
                         (*n, EntryType::Debit)
                     }
                     [Token::Credit, Token::Number(n)] | [Token::Number(n), Token::Credit] => {
                         (*n, EntryType::Credit)
                     }
-                    _ => panic!("should be a number"),
+                    _ => return Err(Diagnostic::new(amount_span, "odotettiin summaa")),
                 })
             } else {
-                panic!("no blocks here")
+                return Err(Diagnostic::new(
+                    entry.span(),
+                    "talousarviorivin sisällä ei voi olla lohkoa",
+                ));
             }
         }
-        SStatement::BudgetEntry {
-            account: account,
+        Ok(SStatement::BudgetEntry {
+            account,
             amounts,
-        }
+            span,
+        })
     }
 
     fn account(
@@ -229,9 +576,10 @@ impl Semantic {
         name: String,
         sub_accounts: Vec<Node>,
         account_type: AccountType,
-    ) -> SStatement {
+        (vat, side): (Option<Money>, Option<VatSide>),
+    ) -> SResult<SStatement> {
         let subs = self.nodes(sub_accounts);
-        SStatement::Account(n.map(|num| num / 100), name, subs, account_type)
+        Ok(SStatement::Account(n, name, subs, account_type, vat, side))
     }
 
     fn transaction(
@@ -240,73 +588,460 @@ impl Semantic {
         description: String,
         body: Vec<Node>,
         doc: Option<String>,
-    ) -> SStatement {
-        if let SectionType::AccountMap = self.section {
-            SStatement::Account(None, description, self.nodes(body), AccountType::None)
+        mut metadata: Metadata,
+        span: Span,
+    ) -> SResult<SStatement> {
+        Ok(if let SectionType::AccountMap = self.section {
+            if metadata != Metadata::default() {
+                return Err(Diagnostic::new(
+                    span,
+                    "tilikartassa ei voi antaa ulottuvuuksia tai tunnisteita",
+                ));
+            }
+            // In the account map the identifier next to a name is a VAT code
+            // or the VAT side
+            let codes: Vec<Token> = doc.map(Token::Identifier).into_iter().collect();
+            let (vat, side) = account_codes(&codes, span)?;
+            SStatement::Account(
+                None,
+                description,
+                self.nodes(body),
+                AccountType::None,
+                vat,
+                side,
+            )
         } else {
+            let mut entries = Vec::new();
+            for node in body {
+                match self.metadata_line(&node)? {
+                    Some((key, value)) => {
+                        metadata.values.insert(key, value);
+                    }
+                    None => entries.push(node),
+                }
+            }
             SStatement::Transaction {
                 date,
                 description,
-                entries: self.entries(body),
+                entries: self.entries(entries)?,
                 doc,
+                metadata,
+                span,
             }
-        }
+        })
     }
 
-    fn auto_block(&mut self, tail: &[Token], body: Vec<Node>) -> SStatement {
-        match tail {
-            [Token::Number(n)] => SStatement::Block(
-                SHeader::AutoBalance {
-                    account: SAccount::N(*n),
-                },
-                self.nodes(body),
-            ),
-            [Token::Identifier(ident)] => SStatement::Block(
-                SHeader::AutoBalance {
-                    account: SAccount::Alias(ident.clone()),
+    fn recurring(
+        &mut self,
+        frequency: &str,
+        start: Date,
+        rest: &[Token],
+        body: Vec<Node>,
+        metadata: Metadata,
+        span: Span,
+    ) -> SResult<SStatement> {
+        if self.section != SectionType::Ledger {
+            return Err(Diagnostic::new(
+                span,
+                "toistuvia tapahtumia voi kirjata vain kirjanpitoon",
+            ));
+        }
+        let frequency = match frequency.to_lowercase().as_str() {
+            "viikoittain" => Frequency::Weekly,
+            "kuukausittain" => Frequency::Monthly,
+            "neljännesvuosittain" => Frequency::Quarterly,
+            "vuosittain" => Frequency::Yearly,
+            f => {
+                return Err(Diagnostic::new(
+                    span,
+                    format!(
+                        "tuntematon toistoväli '{f}'; käytä viikoittain, kuukausittain, neljännesvuosittain tai vuosittain"
+                    ),
+                ));
+            }
+        };
+        let (end, rest) = match rest {
+            [Token::Minus, Token::Date(end), rest @ ..] | [Token::Date(end), rest @ ..] => {
+                (*end, rest)
+            }
+            _ => {
+                return Err(Diagnostic::new(
+                    span,
+                    "toistuvalta tapahtumalta puuttuu loppupäivä",
+                ));
+            }
+        };
+        let (description, doc) = match rest {
+            [Token::String(s)] => (s.clone(), None),
+            [Token::String(s), Token::Identifier(d)] | [Token::Identifier(d), Token::String(s)] => {
+                (s.clone(), Some(d.clone()))
+            }
+            _ => {
+                return Err(Diagnostic::new(
+                    span,
+                    "toistuvalta tapahtumalta puuttuu selite",
+                ));
+            }
+        };
+        let mut metadata = metadata;
+        let mut entries = Vec::new();
+        let mut overrides = Vec::new();
+        let mut skips = Vec::new();
+        for node in body {
+            match &node {
+                Node::Block(h, b, s) if matches!(&h[..], [Token::Identifier(k)] if k.to_lowercase() == "ohita") =>
+                {
+                    let dates = match &b[..] {
+                        [Node::List(tokens, _)] => tokens
+                            .iter()
+                            .map(|t| match t {
+                                Token::Date(d) => Ok((*d, *s)),
+                                _ => Err(Diagnostic::new(*s, "ohitettavan pitää olla päivämäärä")),
+                            })
+                            .collect::<SResult<Vec<_>>>()?,
+                        _ => return Err(Diagnostic::new(*s, "ohitettavan pitää olla päivämäärä")),
+                    };
+                    skips.extend(dates);
+                }
+                Node::Block(h, ..) if matches!(h.first(), Some(Token::Date(_))) => {
+                    let node_span = node.span();
+                    match self.node(node)? {
+                        statement @ SStatement::Transaction {
+                            date: Some(date), ..
+                        } => overrides.push((date, statement)),
+                        _ => return Err(Diagnostic::new(node_span, "odotettiin tapahtumaa")),
+                    }
+                }
+                _ => match self.metadata_line(&node)? {
+                    Some((key, value)) => {
+                        metadata.values.insert(key, value);
+                    }
+                    None => entries.push(node),
                 },
-                self.nodes(body),
-            ),
-            _ => panic!(),
+            }
         }
+        Ok(SStatement::Recurring(SRecurring {
+            frequency,
+            start,
+            end,
+            description,
+            entries: self.entries(entries)?,
+            doc,
+            metadata,
+            overrides,
+            skips,
+            span,
+        }))
+    }
+
+    fn auto_block(
+        &mut self,
+        tail: &[Token],
+        dimensions: Dimensions,
+        body: Vec<Node>,
+        span: Span,
+    ) -> SResult<SStatement> {
+        let account = match tail {
+            [Token::Number(n)] => SAccount::N(account_number(*n, span)?),
+            [Token::Identifier(ident)] => SAccount::Alias(ident.clone()),
+            _ => {
+                return Err(Diagnostic::new(
+                    span,
+                    "AUTO-lohkon otsakkeessa pitää olla yksi tili",
+                ));
+            }
+        };
+        Ok(SStatement::Block(
+            SHeader::AutoBalance { account },
+            dimensions,
+            self.nodes(body),
+            span,
+        ))
     }
 
-    fn entries(&mut self, body: Vec<Node>) -> Vec<SEntry> {
+    /// Every malformed entry is reported; the transaction itself is
+    /// rejected if any of them failed.
+    fn entries(&mut self, body: Vec<Node>) -> SResult<Vec<SEntry>> {
         let mut result = Vec::new();
+        let mut errors = Vec::new();
         for e in body {
-            if let Node::Block(h, b) = e {
-                let account = match &h[..] {
-                    [Token::Identifier(ident)] => SAccount::Alias(ident.to_string()),
-                    [Token::Number(n)] => SAccount::N(*n),
-                    _ => panic!("invalid account: {:?}", h),
+            match self.entry(e) {
+                Ok(entry) => result.push(entry),
+                Err(e) => errors.push(e),
+            }
+        }
+        match errors.pop() {
+            None => Ok(result),
+            Some(last) => {
+                self.errors.append(&mut errors);
+                Err(last)
+            }
+        }
+    }
+
+    fn entry(&mut self, e: Node) -> SResult<SEntry> {
+        if let Node::Block(h, b, span) = e {
+            let (h, mut metadata) = header_tags(&h, span)?;
+            let (account, vat) = match &h[..] {
+                [account] => (account, None),
+                [account, Token::Identifier(code)] => (account, Some(vat_code(code, span)?)),
+                _ => return Err(Diagnostic::new(span, "odotettiin tiliä")),
+            };
+            let account = match account {
+                Token::Identifier(ident) => SAccount::Alias(ident.to_string()),
+                Token::Number(n) => SAccount::N(account_number(*n, span)?),
+                _ => return Err(Diagnostic::new(span, "odotettiin tiliä")),
+            };
+            let mut amounts = Vec::new();
+            for node in b {
+                match self.metadata_line(&node)? {
+                    Some((key, value)) => {
+                        metadata.values.insert(key, value);
+                    }
+                    None => amounts.push(node),
+                }
+            }
+            let amounts = self.amounts(amounts)?;
+            Ok(SEntry {
+                account,
+                amounts,
+                vat,
+                metadata,
+                span,
+            })
+        } else {
+            Err(Diagnostic::new(
+                e.span(),
+                "odotettiin vientiä muodossa 'tili: summa'",
+            ))
+        }
+    }
+
+    /// Reads a metadata line such as `maksaja: "Matti"` or
+    /// `laskunro: 2025-17`. A line whose value is an amount is an entry to an
+    /// aliased account instead, except for `viite`, which must be a valid
+    /// reference number.
+    fn metadata_line(&self, node: &Node) -> SResult<Option<(String, String)>> {
+        let Node::Block(h, body, span) = node else {
+            return Ok(None);
+        };
+        let ([Token::Identifier(key)], [Node::List(tokens, value_span)]) = (&h[..], &body[..])
+        else {
+            return Ok(None);
+        };
+        if key != REFERENCE && amount(tokens, *value_span).is_ok() {
+            return Ok(None);
+        }
+        if key == DUE_DATE && !matches!(tokens[..], [Token::Date(_)]) {
+            return Err(Diagnostic::new(*span, "eräpäivän pitää olla päivämäärä"));
+        }
+        let value = match &tokens[..] {
+            [Token::String(s)] => s.clone(),
+            _ => {
+                let line = self.sources.line(*value_span).unwrap_or_default();
+                let line = if value_span.line == span.line {
+                    line.split_once(':').map_or("", |(_, v)| v)
+                } else {
+                    line
                 };
-                let amounts = self.amounts(b);
-                result.push(SEntry { account, amounts });
-            } else {
-                panic!("expected a block")
+                line.split("--")
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string()
             }
+        };
+        if value.is_empty() {
+            return Err(Diagnostic::new(
+                *span,
+                format!("tiedolta '{key}' puuttuu arvo"),
+            ));
+        }
+        if key == REFERENCE && !reference::is_valid(&value) {
+            return Err(Diagnostic::new(
+                *span,
+                format!("virheellinen viitenumero '{value}'"),
+            ));
         }
-        result
+        Ok(Some((key.clone(), value)))
     }
 
-    fn amounts(&mut self, amounts: Vec<Node>) -> Vec<SAuto<i32>> {
+    fn amounts(&mut self, amounts: Vec<Node>) -> SResult<Vec<SAuto<SAmount>>> {
         let mut result = Vec::new();
         for a in amounts {
-            if let Node::List(l) = a {
-                match &l[..] {
-                    [Token::Number(n)]
-                    | [Token::Debit, Token::Number(n)]
-                    | [Token::Number(n), Token::Debit] => result.push(SAuto::Val(*n)),
-                    [Token::Minus, Token::Number(n)]
-                    | [Token::Credit, Token::Number(n)]
-                    | [Token::Number(n), Token::Credit] => result.push(SAuto::Val(-n)),
-                    [Token::Auto] => result.push(SAuto::Auto),
-                    _ => panic!("expected some $$$"),
-                }
+            if let Node::List(l, span) = a {
+                result.push(amount(&l, span)?);
             } else {
-                panic!("expected a list")
+                return Err(Diagnostic::new(a.span(), "odotettiin summaa"));
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn account_number(n: Money, span: Span) -> SResult<i32> {
+    n.as_integer()
+        .and_then(|n| i32::try_from(n).ok())
+        .ok_or_else(|| Diagnostic::new(span, "virheellinen tilinumero"))
+}
+
+/// Reads an entry amount: `AUTO` or a number with an optional sign or
+/// debit/credit marker, followed by an optional currency and rate, e.g.
+/// `C 1 200 USD @ 0,92`.
+fn amount(tokens: &[Token], span: Span) -> SResult<SAuto<SAmount>> {
+    let error = || Diagnostic::new(span, "odotettiin summaa");
+    let (negative, tokens) = match tokens {
+        [Token::Auto] => return Ok(SAuto::Auto),
+        [Token::Minus | Token::Credit, rest @ ..] => (Some(true), rest),
+        [Token::Debit, rest @ ..] => (Some(false), rest),
+        _ => (None, tokens),
+    };
+    let groups = tokens
+        .iter()
+        .skip(1)
+        .take_while(|t| matches!(t, Token::DigitGroup(_)))
+        .count();
+    let (number, rest) = tokens.split_at((groups + 1).min(tokens.len()));
+    let n = grouped_amount(number, span)?;
+    let (negative, rest) = match (negative, rest) {
+        (None, [Token::Credit, rest @ ..]) => (true, rest),
+        (None, [Token::Debit, rest @ ..]) => (false, rest),
+        (negative, rest) => (negative.unwrap_or(false), rest),
+    };
+    let value = if negative { -n } else { n };
+    let (currency, rate) = match rest {
+        [] => (None, None),
+        [Token::Identifier(c)] => (Some(currency_code(c, span)?), None),
+        [Token::Identifier(c), Token::At, Token::Number(rate)] => {
+            (Some(currency_code(c, span)?), Some(*rate))
+        }
+        _ => return Err(error()),
+    };
+    Ok(SAuto::Val(SAmount {
+        value,
+        currency,
+        rate,
+    }))
+}
+
+/// Separates the dimensions `#name=value` and tags `#name` from the other
+/// tokens of a header.
+fn header_tags(tokens: &[Token], span: Span) -> SResult<(Vec<Token>, Metadata)> {
+    let mut rest = Vec::new();
+    let mut metadata = Metadata::default();
+    for token in tokens {
+        match token {
+            Token::Tag(name, value) if value.is_empty() => {
+                metadata.tags.insert(name.clone());
+            }
+            Token::Tag(name, value) => {
+                if metadata
+                    .dimensions
+                    .insert(name.clone(), value.clone())
+                    .is_some()
+                {
+                    return Err(Diagnostic::new(
+                        span,
+                        format!("ulottuvuus #{name} on annettu kahdesti"),
+                    ));
+                }
             }
+            Token::Cleared => metadata.cleared = true,
+            token => rest.push(token.clone()),
         }
-        result
+    }
+    Ok((rest, metadata))
+}
+
+pub(crate) fn is_amount(tokens: &[Token]) -> bool {
+    amount(tokens, Span::default()).is_ok()
+}
+
+/// Reads the VAT code and the VAT side after the name of an account, as in
+/// `3000 "Myynti" ALV25,5 myynti`.
+fn account_codes(codes: &[Token], span: Span) -> SResult<(Option<Money>, Option<VatSide>)> {
+    let (mut vat, mut side) = (None, None);
+    for code in codes {
+        let Token::Identifier(code) = code else {
+            return Err(Diagnostic::new(span, "odotettiin ALV-koodia"));
+        };
+        match VatSide::parse(code) {
+            Some(s) if side.is_none() => side = Some(s),
+            None if vat.is_none() => vat = Some(vat_code(code, span)?),
+            _ => {
+                return Err(Diagnostic::new(
+                    span,
+                    format!("'{code}' on annettu kahdesti"),
+                ));
+            }
+        }
+    }
+    Ok((vat, side))
+}
+
+/// Reads a VAT code such as `ALV25,5` into the rate in percent.
+fn vat_code(code: &str, span: Span) -> SResult<Money> {
+    code.strip_prefix("ALV")
+        .filter(|rate| !rate.is_empty())
+        .and_then(|rate| Money::parse(rate).ok())
+        .ok_or_else(|| Diagnostic::new(span, format!("virheellinen ALV-koodi '{code}'")))
+}
+
+fn currency_code(code: &str, span: Span) -> SResult<String> {
+    if code.len() == 3 && code.chars().all(|c| c.is_ascii_uppercase()) {
+        Ok(code.to_string())
+    } else {
+        Err(Diagnostic::new(
+            span,
+            format!("virheellinen valuuttakoodi '{code}'"),
+        ))
+    }
+}
+
+/// Reads an amount that may be written with spaces between digit groups,
+/// e.g. `-1 234,56`. The groups after the first must have three digits.
+fn grouped_amount(tokens: &[Token], span: Span) -> SResult<Money> {
+    let (negative, groups) = match tokens {
+        [Token::Minus, Token::Number(n), rest @ ..] => (true, (n, rest)),
+        [Token::Number(n), rest @ ..] => (false, (n, rest)),
+        _ => return Err(Diagnostic::new(span, "odotettiin summaa")),
+    };
+    let (first, groups) = groups;
+    let mut amount = *first;
+    for token in groups {
+        let Token::DigitGroup(group) = token else {
+            return Err(Diagnostic::new(span, "odotettiin summaa"));
+        };
+        let digits = group.find([',', '.']).unwrap_or(group.len());
+        if digits != 3 {
+            return Err(Diagnostic::new(
+                span,
+                format!("numeroryhmässä '{group}' pitää olla kolme numeroa"),
+            ));
+        }
+        let n = Money::parse(group)
+            .map_err(|_| Diagnostic::new(span, format!("luvussa {group} on liikaa desimaaleja")))?;
+        amount = amount
+            .checked_mul(1000)
+            .and_then(|a| a.checked_add(n))
+            .ok_or_else(|| Diagnostic::new(span, "summa on liian suuri"))?;
+    }
+    Ok(if negative { -amount } else { amount })
+}
+
+/// Returns the path of a `§ SISÄLLYTÄ "polku"` statement.
+fn include_target(node: &Node) -> Option<(String, Span)> {
+    match node {
+        Node::List(l, span) => match &l[..] {
+            [
+                Token::Section,
+                Token::Identifier(ident),
+                Token::String(path),
+            ] if matches!(ident.to_uppercase().as_str(), "SISÄLLYTÄ" | "INCLUDE") => {
+                Some((path.clone(), *span))
+            }
+            _ => None,
+        },
+        _ => None,
     }
 }